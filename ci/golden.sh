@@ -0,0 +1,31 @@
+#!/bin/sh
+# Renders a fixed scene headlessly and compares it against its golden image.
+#
+# The render needs an OpenGL 3.3 context. `android_base` only opens windowed
+# glutin contexts, not surfaceless or OSMesa ones, so without a display this
+# runs under xvfb with Mesa's llvmpipe:
+#
+#     xvfb-run -a ci/golden.sh
+#
+# With TREES_BLESS=1 the render replaces the golden image instead, which is
+# how `golden/default.png` is made or updated after an intended change.
+set -eu
+
+cd "$(dirname "$0")/.."
+render=target/golden/default.png
+golden=golden/default.png
+
+export LIBGL_ALWAYS_SOFTWARE=1
+export TREES_RENDER_OUTPUT="$render"
+export TREES_RENDER_SIZE=256x256
+export TREES_RENDER_TIME=30
+export TREES_RENDER_CAMERA=0.6,0.8,25,0,0
+export TREES_SEED=0
+
+cargo run --release --bin Trees
+
+if [ ! -f "$golden" ] && [ -z "${TREES_BLESS:-}" ]; then
+    echo "No golden image at $golden, run with TREES_BLESS=1 to make it"
+    exit 1
+fi
+cargo run --release --bin golden -- "$render" "$golden"
@@ -0,0 +1,30 @@
+use std::env;
+use std::process::exit;
+use source::offscreen::golden::{check, Tolerance};
+
+fn main() {
+    let args = env::args().skip(1).collect::<Vec<_>>();
+    if args.len() < 2 || args.len() > 4 {
+        println!("Usage: golden <render.png> <golden.png> [per channel tolerance] [mismatched fraction]");
+        exit(2);
+    }
+    let mut tolerance = Tolerance::default();
+    if let Some(per_channel) = args.get(2) {
+        tolerance.per_channel = per_channel.parse().expect("Per channel tolerance should be 0-255");
+    }
+    if let Some(fraction) = args.get(3) {
+        tolerance.mismatched_fraction = fraction.parse().expect("Mismatched fraction should be a number");
+    }
+    match check(args[0].as_ref(), args[1].as_ref(), &tolerance) {
+        Ok(comparison) => println!(
+            "Matched with {} of {} pixels outside tolerance (max channel difference {})",
+            comparison.mismatched,
+            comparison.total,
+            comparison.max_difference
+        ),
+        Err(e) => {
+            println!("{}", e);
+            exit(1);
+        },
+    }
+}
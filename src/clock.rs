@@ -0,0 +1,174 @@
+/// Simulated seconds per step.
+pub const TIMESTEP: f64 = 1.0 / 60.0;
+/// Most steps taken in one update, so a long stall (or a large time scale)
+/// can't leave the app spending every frame catching up.
+const MAX_STEPS_PER_UPDATE: u32 = 600;
+const MAX_SCALE: f64 = 64.0;
+
+/// Turns real elapsed time into a whole number of fixed simulation steps.
+///
+/// The simulation only ever sees `step()` sized increments, so given the same
+/// steps it does the same thing whatever the frame rate. Rendering happens
+/// between steps, at `interpolated_time()`.
+#[derive(Clone, Debug)]
+pub struct Clock {
+    step: f64,
+    steps: u64,
+    /// Scaled real time not yet simulated, always less than a step.
+    accumulator: f64,
+    scale: f64,
+    paused: bool,
+    /// Steps asked for with `single_step`, taken on the next `advance`.
+    pending: u32,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new(TIMESTEP)
+    }
+}
+
+impl Clock {
+    pub fn new(step: f64) -> Self {
+        assert!(step > 0.0, "A clock's step should be positive");
+        Self {
+            step,
+            steps: 0,
+            accumulator: 0.0,
+            scale: 1.0,
+            paused: false,
+            pending: 0,
+        }
+    }
+
+    /// Accounts for `real_dt` seconds having passed, returning how many steps
+    /// should now be simulated.
+    pub fn advance(&mut self, real_dt: f64) -> u32 {
+        let mut steps = std::mem::replace(&mut self.pending, 0);
+        if !self.paused {
+            self.accumulator += real_dt.max(0.0) * self.scale;
+            let due = (self.accumulator / self.step).floor();
+            if due > MAX_STEPS_PER_UPDATE as f64 {
+                // Drop the time we can't catch up on rather than falling
+                // further behind.
+                steps += MAX_STEPS_PER_UPDATE;
+                self.accumulator = 0.0;
+            } else {
+                steps += due as u32;
+                self.accumulator -= due * self.step;
+            }
+        }
+        self.steps += steps as u64;
+        steps
+    }
+
+    pub fn step(&self) -> f64 {
+        self.step
+    }
+
+    /// Simulated time after every step taken so far.
+    pub fn time(&self) -> f64 {
+        self.steps as f64 * self.step
+    }
+
+    /// How far (0 to 1) real time has got towards the next step.
+    pub fn alpha(&self) -> f64 {
+        self.accumulator / self.step
+    }
+
+    /// The time to render at: between the last two steps, by `alpha()`.
+    pub fn interpolated_time(&self) -> f64 {
+        (self.time() - self.step * (1.0 - self.alpha())).max(0.0)
+    }
+
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    pub fn resume(&mut self) {
+        self.paused = false;
+    }
+
+    pub fn toggle_pause(&mut self) {
+        self.paused = !self.paused;
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Takes one step on the next `advance`, whether or not paused.
+    pub fn single_step(&mut self) {
+        self.pending += 1;
+    }
+
+    /// Simulated seconds per real second, clamped to `0..=64`.
+    pub fn set_scale(&mut self, scale: f64) {
+        self.scale = scale.max(0.0).min(MAX_SCALE);
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scale
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn advance_takes_whole_steps() {
+        let mut clock = Clock::new(0.25);
+        assert_eq!(clock.advance(0.625), 2);
+        assert_eq!(clock.alpha(), 0.5);
+        assert_eq!(clock.advance(0.125), 1);
+        assert_eq!(clock.advance(0.125), 0);
+        assert_eq!(clock.time(), 0.75);
+    }
+
+    #[test]
+    fn advance_caps_steps() {
+        let mut clock = Clock::new(0.1);
+        assert_eq!(clock.advance(1000.0), MAX_STEPS_PER_UPDATE);
+        assert_eq!(clock.alpha(), 0.0);
+        assert_eq!(clock.advance(0.1), 1);
+    }
+
+    #[test]
+    fn paused_clocks_only_single_step() {
+        let mut clock = Clock::new(0.25);
+        clock.pause();
+        assert_eq!(clock.advance(1.0), 0);
+        clock.single_step();
+        clock.single_step();
+        assert_eq!(clock.advance(1.0), 2);
+        assert_eq!(clock.advance(1.0), 0);
+        clock.resume();
+        assert_eq!(clock.advance(0.375), 1);
+        assert_eq!(clock.time(), 0.75);
+    }
+
+    #[test]
+    fn scale_is_clamped() {
+        let mut clock = Clock::default();
+        clock.set_scale(-1.0);
+        assert_eq!(clock.scale(), 0.0);
+        assert_eq!(clock.advance(1.0), 0);
+        clock.set_scale(1000.0);
+        assert_eq!(clock.scale(), MAX_SCALE);
+        clock.set_scale(2.0);
+        assert_eq!(clock.scale(), 2.0);
+    }
+
+    #[test]
+    fn interpolated_time_is_between_steps() {
+        let mut clock = Clock::new(0.1);
+        assert_eq!(clock.interpolated_time(), 0.0);
+        for _ in 0..20 {
+            clock.advance(0.037);
+            let time = clock.interpolated_time();
+            assert!(time >= clock.time() - clock.step() - 1e-9, "{} before the last step", time);
+            assert!(time < clock.time() + 1e-9, "{} after the latest step", time);
+        }
+    }
+}
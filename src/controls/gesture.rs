@@ -0,0 +1,138 @@
+use std::time::{Duration, Instant};
+use crate::controls::{Action, TAP_SLOP};
+
+/// How long five fingers have to stay down to throw the world away.
+const NEW_WORLD_HOLD: Duration = Duration::from_millis(1500);
+
+/// Recognises taps with several fingers. Fingers rarely land together, so
+/// nothing is decided until the last one lifts, going by the most that were
+/// down at once. Otherwise a four finger tap would also be taken for a three
+/// finger one on the way.
+///
+/// - One finger taps.
+/// - Two fingers step the simulation once.
+/// - Three fingers take a screenshot.
+/// - Four fingers pause or resume.
+/// - Five fingers held for `NEW_WORLD_HOLD` make a new world. A quick five
+///   finger tap does nothing, as it's easy to do by accident.
+///
+/// Any finger moving more than `TAP_SLOP`, or a cancelled touch, makes it a
+/// drag rather than a tap.
+#[derive(Debug, Default)]
+pub struct Gesture {
+    /// Where each finger still down went down.
+    starts: Vec<(i64, [f64; 2])>,
+    /// Where the first finger went down.
+    first: [f64; 2],
+    /// When the first finger went down.
+    began: Option<Instant>,
+    most: usize,
+    moved: bool,
+}
+
+impl Gesture {
+    pub fn start(&mut self, id: i64, position: [f64; 2], now: Instant) {
+        if self.starts.is_empty() {
+            *self = Self {
+                first: position,
+                began: Some(now),
+                ..Self::default()
+            };
+        }
+        self.starts.push((id, position));
+        self.most = self.most.max(self.starts.len());
+    }
+
+    pub fn moved(&mut self, id: i64, position: [f64; 2]) {
+        if let Some(&(_, start)) = self.starts.iter().find(|&&(finger, _)| finger == id) {
+            let (dx, dy) = (position[0] - start[0], position[1] - start[1]);
+            if dx * dx + dy * dy > TAP_SLOP * TAP_SLOP {
+                self.moved = true;
+            }
+        }
+    }
+
+    /// A finger lifted, or was cancelled. Once they all have, this is what
+    /// they did.
+    pub fn end(&mut self, id: i64, cancelled: bool, now: Instant) -> Option<Action> {
+        self.starts.retain(|&(finger, _)| finger != id);
+        self.moved |= cancelled;
+        if !self.starts.is_empty() || self.moved {
+            return None;
+        }
+        let held = self.began.map_or(Duration::from_secs(0), |began| now - began);
+        match self.most {
+            1 => Some(Action::Tap { position: self.first }),
+            2 => Some(Action::Step),
+            3 => Some(Action::Screenshot { supersample: 1 }),
+            4 => Some(Action::TogglePause),
+            5 if held >= NEW_WORLD_HOLD => Some(Action::NewWorld),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const SHORT: Duration = Duration::from_millis(100);
+
+    /// Puts `count` fingers down one after another and lifts them again,
+    /// `held` after the first went down.
+    fn tap(gesture: &mut Gesture, count: i64, held: Duration) -> Vec<Action> {
+        let start = Instant::now();
+        for id in 0..count {
+            gesture.start(id, [100.0 + 20.0 * id as f64, 200.0], start);
+        }
+        (0..count).filter_map(|id| gesture.end(id, false, start + held)).collect()
+    }
+
+    #[test]
+    fn fingers_landing_in_turn_make_one_gesture() {
+        let mut gesture = Gesture::default();
+        assert_eq!(tap(&mut gesture, 1, SHORT), vec![Action::Tap { position: [100.0, 200.0] }]);
+        assert_eq!(tap(&mut gesture, 2, SHORT), vec![Action::Step]);
+        assert_eq!(tap(&mut gesture, 3, SHORT), vec![Action::Screenshot { supersample: 1 }]);
+        assert_eq!(tap(&mut gesture, 4, SHORT), vec![Action::TogglePause]);
+    }
+
+    #[test]
+    fn fingers_lifting_early_dont_change_the_count() {
+        let mut gesture = Gesture::default();
+        let now = Instant::now();
+        gesture.start(0, [0.0; 2], now);
+        gesture.start(1, [0.0; 2], now);
+        gesture.start(2, [0.0; 2], now);
+        assert_eq!(gesture.end(2, false, now), None);
+        gesture.start(3, [0.0; 2], now);
+        assert_eq!(gesture.end(0, false, now), None);
+        assert_eq!(gesture.end(1, false, now), None);
+        assert_eq!(gesture.end(3, false, now), Some(Action::Screenshot { supersample: 1 }));
+    }
+
+    #[test]
+    fn new_worlds_need_five_fingers_held() {
+        let mut gesture = Gesture::default();
+        assert_eq!(tap(&mut gesture, 5, SHORT), vec![]);
+        assert_eq!(tap(&mut gesture, 5, NEW_WORLD_HOLD), vec![Action::NewWorld]);
+    }
+
+    #[test]
+    fn drags_and_cancels_are_not_taps() {
+        let mut gesture = Gesture::default();
+        let now = Instant::now();
+        gesture.start(0, [0.0; 2], now);
+        gesture.start(1, [50.0, 0.0], now);
+        gesture.moved(1, [50.0, TAP_SLOP + 1.0]);
+        assert_eq!(gesture.end(0, false, now), None);
+        assert_eq!(gesture.end(1, false, now), None);
+
+        gesture.start(0, [0.0; 2], now);
+        gesture.moved(0, [TAP_SLOP, 0.0]);
+        assert_eq!(gesture.end(0, true, now), None);
+
+        assert_eq!(tap(&mut gesture, 1, SHORT), vec![Action::Tap { position: [100.0, 200.0] }]);
+    }
+}
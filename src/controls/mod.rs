@@ -1,9 +1,19 @@
 use android_base::{InputEvent, AppContainer, AppImpl};
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use piston::input::{Input, Motion, Touch};
-use cgmath::{Vector2, InnerSpace, Matrix4, Vector3, Rad, MetricSpace};
+use cgmath::{Vector2, InnerSpace, Matrix4, Vector3, Vector4, Rad, SquareMatrix};
 use std::f32::consts::{FRAC_PI_4, PI, FRAC_PI_2};
 
+mod gesture;
+pub use gesture::*;
+
+macro_rules! log {
+    ($msg:literal) => {
+        concat!("[", file!(), ":", line!(), "]: ", $msg)
+    }
+}
+
 #[derive(Debug)]
 pub struct Camera {
     height: f64,
@@ -29,8 +39,26 @@ impl Default for Camera {
     }
 }
 
+/// Where the camera is looking from, without anything tied to the screen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CameraPose {
+    pub angle_from_ground: f32,
+    pub angle_about_y: f32,
+    pub dist_origin: f32,
+    pub x_target: f32,
+    pub z_target: f32,
+}
+
+impl Default for CameraPose {
+    fn default() -> Self {
+        Camera::default().pose()
+    }
+}
+
 const PERCENT_OF_SCREEN_PER_UNIT: f64 = 0.2;
 const MOVEMENT_MARGIN_SQ: f64 = 0.0;
+/// Pixels a finger can wander and still count as tapping.
+pub const TAP_SLOP: f64 = 12.0;
 
 impl Camera {
     pub fn two_fingers_same_direction(&mut self, mut new: Vector2<f64>, mut old: Vector2<f64>) {
@@ -98,13 +126,24 @@ impl Camera {
 
         let distance = old.magnitude() - new.magnitude();
 
-        self.dist_origin += distance as f32 * 3.0;
+        self.zoom_by(distance as f32 * 3.0);
+    }
+    pub fn zoom_by(&mut self, distance: f32) {
+        self.dist_origin += distance;
         if self.dist_origin < 1.0 {
             self.dist_origin = 1.0;
         } else if self.dist_origin > 40.0 {
             self.dist_origin = 40.0;
         }
     }
+    pub fn turn(&mut self, angle: f32) {
+        self.angle_about_y += angle;
+        if self.angle_about_y < -PI {
+            self.angle_about_y += 2.0 * PI;
+        } else if self.angle_about_y > PI {
+            self.angle_about_y -= 2.0 * PI;
+        }
+    }
     pub fn rotate(&mut self, mut new_a: Vector2<f64>, mut new_b: Vector2<f64>, mut old_a: Vector2<f64>, mut old_b: Vector2<f64>) {
         new_a.y = self.height - new_a.y;
         old_a.y = self.height - old_a.y;
@@ -130,6 +169,60 @@ impl Camera {
         println!("{:?}", self);
     }
 
+    /// The point on the ground the camera turns about, in world space.
+    pub fn focus(&self) -> Vector3<f32> {
+        Vector3 { x: -self.x_target, y: 0.0, z: -self.z_target }
+    }
+    /// Where the camera is in world space.
+    pub fn eye_position(&self) -> Vector3<f32> {
+        let (sin_ground, cos_ground) = self.angle_from_ground.sin_cos();
+        let (sin_y, cos_y) = self.angle_about_y.sin_cos();
+        Vector3 {
+            x: -self.x_target - self.dist_origin * cos_ground * sin_y,
+            y: self.dist_origin * sin_ground,
+            z: -self.z_target + self.dist_origin * cos_ground * cos_y,
+        }
+    }
+    /// The ray through `position` in a `size` window, as a world space origin
+    /// on the near plane and a unit direction.
+    pub fn screen_ray(&self, position: [f64; 2], size: (usize, usize), projection: Matrix4<f32>) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        let x = (2.0 * position[0] / size.0 as f64 - 1.0) as f32;
+        let y = (1.0 - 2.0 * position[1] / size.1 as f64) as f32;
+        let inverse = (projection * self.mat()).invert()?;
+        let unproject = |z: f32| {
+            let point = inverse * Vector4::new(x, y, z, 1.0);
+            point.truncate() / point.w
+        };
+        let near = unproject(-1.0);
+        let far = unproject(1.0);
+        Some((near, (far - near).normalize()))
+    }
+    /// Tilts the camera up until it's at least `height` above the ground
+    /// plane, as far as looking straight down.
+    pub fn keep_above(&mut self, height: f32) {
+        if self.dist_origin * self.angle_from_ground.sin() < height {
+            let angle = (height / self.dist_origin).min(1.0).asin();
+            self.angle_from_ground = angle.min(FRAC_PI_2);
+        }
+    }
+
+    pub fn pose(&self) -> CameraPose {
+        CameraPose {
+            angle_from_ground: self.angle_from_ground,
+            angle_about_y: self.angle_about_y,
+            dist_origin: self.dist_origin,
+            x_target: self.x_target,
+            z_target: self.z_target,
+        }
+    }
+    pub fn set_pose(&mut self, pose: CameraPose) {
+        self.angle_from_ground = pose.angle_from_ground;
+        self.angle_about_y = pose.angle_about_y;
+        self.dist_origin = pose.dist_origin;
+        self.x_target = pose.x_target;
+        self.z_target = pose.z_target;
+    }
+
     pub fn mat(&self) -> Matrix4<f32> {
         Matrix4::from_translation(Vector3 { z: -self.dist_origin, y: 0.0, x: 0.0 }) *
             Matrix4::from_angle_x(Rad(self.angle_from_ground)) *
@@ -142,6 +235,37 @@ impl Camera {
     }
 }
 
+/// Something asked of the app from the input thread, handled on the next
+/// frame.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Action {
+    /// Save what's on screen, rendered `supersample` times larger than the
+    /// window if that's more than one.
+    Screenshot { supersample: u32 },
+    TogglePause,
+    /// Simulate a single step, for stepping through while paused.
+    Step,
+    /// Multiply how fast simulated time passes by this. Only from the keyboard,
+    /// so only off Android.
+    ScaleTime(f64),
+    /// Replace the world with one from a fresh random seed.
+    NewWorld,
+    /// A tap at a position on screen, in pixels.
+    Tap { position: [f64; 2] },
+}
+
+#[derive(Default, Debug)]
+pub struct Actions(Mutex<Vec<Action>>);
+
+impl Actions {
+    pub fn push(&self, action: Action) {
+        self.0.lock().expect(log!("Actions should never be poisoned")).push(action);
+    }
+    pub fn take(&self) -> Vec<Action> {
+        std::mem::replace(&mut *self.0.lock().expect(log!("Actions should never be poisoned")), Vec::new())
+    }
+}
+
 #[derive(Clone, Copy)]
 struct Finger {
     direction: Vector2<f64>,
@@ -171,16 +295,13 @@ impl Finger {
     }
 }
 
-macro_rules! log {
-    ($msg:literal) => {
-        concat!("[", file!(), ":", line!(), "]: ", $msg)
-    }
-}
-
-pub fn spawn<T: AppImpl>(app: &mut AppContainer<T>, camera: Arc<Mutex<Camera>>) {
+pub fn spawn<T: AppImpl>(app: &mut AppContainer<T>, camera: Arc<Mutex<Camera>>, actions: Arc<Actions>) {
+    #[cfg(not(target_os = "android"))]
+    let mut desktop = crate::desktop::DesktopInput::default();
     let mut fingers: Vec<i64> = Vec::new();
     let mut primary: Option<Finger> = None;
     let mut secondary: Option<Finger> = None;
+    let mut gesture = Gesture::default();
     let resolve_finger_with_two = |
         first: (Vector2<f64>, Vector2<f64>, &mut Finger),
         second: (Vector2<f64>, Vector2<f64>, &mut Finger),
@@ -203,12 +324,19 @@ pub fn spawn<T: AppImpl>(app: &mut AppContainer<T>, camera: Arc<Mutex<Camera>>)
     };
     app.spawn_user_thread(move |e: InputEvent| -> () {
         if let InputEvent::Piston(input) = e {
+            #[cfg(not(target_os = "android"))]
+            {
+                if desktop.handle(&input, &camera, &actions) {
+                    return;
+                }
+            }
             match input {
                 Input::Move(motion) => match motion {
                     Motion::Touch(touch) => {
                         match touch.touch {
                             Touch::Start => {
                                 fingers.push(touch.id);
+                                gesture.start(touch.id, touch.position(), Instant::now());
                                 if fingers.len() == 1 {
                                     primary = Some(Finger::with_pos(touch.position()));
                                 } else if fingers.len() == 2 {
@@ -239,10 +367,14 @@ pub fn spawn<T: AppImpl>(app: &mut AppContainer<T>, camera: Arc<Mutex<Camera>>)
                                         break;
                                     }
                                 }
+                                if let Some(action) = gesture.end(touch.id, touch.touch == Touch::Cancel, Instant::now()) {
+                                    actions.push(action);
+                                }
                             },
                             Touch::Move => {
                                 assert!(!touch.is_3d, "3d touch is not supported!");
                                 assert_eq!(touch.device, 0, "Touch inputs from other devices are not supported!");
+                                gesture.moved(touch.id, touch.position());
                                 match (fingers.get(0).cloned(), fingers.get(1).cloned()) {
                                     (Some(x), None) if x == touch.id => {
                                         let primary = if let Some(primary) = primary.as_mut() {
@@ -0,0 +1,125 @@
+use std::env;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use piston::input::{Input, Motion, Button, ButtonArgs, ButtonState, Key, MouseButton};
+use cgmath::{Vector2, MetricSpace};
+use crate::controls::{Camera, Actions, Action, TAP_SLOP};
+
+/// Pixels the camera pans per key press.
+const KEY_PAN: f64 = 40.0;
+/// Radians the camera turns per key press.
+const KEY_TURN: f32 = 0.1;
+/// Zoom distance per scroll wheel notch or key press.
+const ZOOM_STEP: f32 = 1.0;
+/// Scale of supersampled screenshots.
+const SUPERSAMPLE: u32 = 2;
+/// How much `]` speeds up and `[` slows down simulated time.
+const TIME_SCALE_STEP: f64 = 2.0;
+
+/// Where assets are read from, `$TREES_ASSETS` if set and the crate's
+/// `assets/` directory otherwise.
+pub fn asset_dir() -> PathBuf {
+    env::var_os("TREES_ASSETS")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("assets"))
+}
+
+/// Maps mouse and keyboard input onto the same camera movements as touches:
+/// dragging with the left button pans, dragging with the right button tilts
+/// and orbits, and the wheel zooms. Clicking without dragging taps. WASD/arrows pan, Q/E turn and +/- zoom.
+/// F12 takes a screenshot, F11 a supersampled one. Space pauses, `.` steps
+/// once and `[`/`]` slow down and speed up simulated time. N makes a new
+/// random world.
+///
+/// `controls::spawn` hands it every input off Android, before the touch
+/// handling.
+#[derive(Default)]
+pub struct DesktopInput {
+    cursor: Option<[f64; 2]>,
+    left: bool,
+    right: bool,
+    /// Where the left button went down, while it could still be a click.
+    click: Option<[f64; 2]>,
+}
+
+impl DesktopInput {
+    /// Returns `false` for touch input, which is left to the touch handling.
+    pub fn handle(&mut self, input: &Input, camera: &Arc<Mutex<Camera>>, actions: &Actions) -> bool {
+        match input {
+            Input::Move(Motion::Touch(_)) => false,
+            Input::Move(Motion::MouseCursor(position)) => {
+                if let Some(old) = self.cursor {
+                    let old: Vector2<f64> = old.into();
+                    let new: Vector2<f64> = (*position).into();
+                    let mut camera = camera.lock().expect("Camera should always be present");
+                    if self.left {
+                        camera.pan(old, new);
+                    } else if self.right {
+                        camera.two_fingers_same_direction(new, old);
+                    }
+                }
+                if let Some(start) = self.click {
+                    let start: Vector2<f64> = start.into();
+                    if start.distance2((*position).into()) > TAP_SLOP * TAP_SLOP {
+                        self.click = None;
+                    }
+                }
+                self.cursor = Some(*position);
+                true
+            },
+            Input::Move(Motion::MouseScroll([_, y])) => {
+                camera.lock().expect("Camera should always be present").zoom_by(-*y as f32 * ZOOM_STEP);
+                true
+            },
+            Input::Button(ButtonArgs { state, button: Button::Mouse(mouse), .. }) => {
+                let pressed = *state == ButtonState::Press;
+                match mouse {
+                    MouseButton::Left => {
+                        self.left = pressed;
+                        if pressed {
+                            self.click = self.cursor;
+                        } else if let Some(position) = self.click.take() {
+                            actions.push(Action::Tap { position });
+                        }
+                    },
+                    MouseButton::Right => self.right = pressed,
+                    _ => {},
+                }
+                true
+            },
+            Input::Button(ButtonArgs { state: ButtonState::Press, button: Button::Keyboard(key), .. }) => {
+                let mut guard = camera.lock().expect("Camera should always be present");
+                let camera = &mut *guard;
+                let pan = |camera: &mut Camera, x: f64, y: f64| camera.pan(Vector2::new(0.0, 0.0), Vector2::new(x, y));
+                match key {
+                    Key::W | Key::Up => pan(camera, 0.0, -KEY_PAN),
+                    Key::S | Key::Down => pan(camera, 0.0, KEY_PAN),
+                    Key::A | Key::Left => pan(camera, -KEY_PAN, 0.0),
+                    Key::D | Key::Right => pan(camera, KEY_PAN, 0.0),
+                    Key::Q => camera.turn(-KEY_TURN),
+                    Key::E => camera.turn(KEY_TURN),
+                    Key::Equals | Key::NumPadPlus => camera.zoom_by(-ZOOM_STEP),
+                    Key::Minus | Key::NumPadMinus => camera.zoom_by(ZOOM_STEP),
+                    Key::F12 => actions.push(Action::Screenshot { supersample: 1 }),
+                    Key::F11 => actions.push(Action::Screenshot { supersample: SUPERSAMPLE }),
+                    Key::Space => actions.push(Action::TogglePause),
+                    Key::Period => actions.push(Action::Step),
+                    Key::LeftBracket => actions.push(Action::ScaleTime(1.0 / TIME_SCALE_STEP)),
+                    Key::RightBracket => actions.push(Action::ScaleTime(TIME_SCALE_STEP)),
+                    Key::N => actions.push(Action::NewWorld),
+                    _ => {},
+                }
+                true
+            },
+            Input::Focus(false) | Input::Cursor(false) => {
+                self.left = false;
+                self.right = false;
+                self.cursor = None;
+                self.click = None;
+                true
+            },
+            // Resizes reach the app through `on_size_change` instead.
+            _ => true,
+        }
+    }
+}
@@ -1,12 +1,11 @@
 use cgmath::{Vector3, Zero};
 use android_base::{Drawable, Transform, ViewProj, Transforms};
-use crate::shader::LightShader;
-use opengl_graphics::{GlGraphics, Texture};
+use crate::shader::{LightShader, NO_CLIP, LIGHT_COLOUR};
+use crate::weather::Atmosphere;
+use opengl_graphics::GlGraphics;
 use graphics::Context;
-use crate::fs::load as fs_load;
-use image::DynamicImage;
-use image::png::PNGDecoder;
-use texture::TextureSettings;
+use crate::fs::AssetError;
+use crate::textures::{TextureManager, TextureHandle};
 
 pub const POINTS_N_PER_VERTEX: &'static [[f32; 4]] = &[
     // -z
@@ -107,18 +106,28 @@ pub const COLOURS_N_PER_VERTEX: &'static [[f32; 4]] = &[
 
 pub struct Cube {
     pub light: Vector3<f32>,
+    /// See `clip_plane` in `basic_light/fragment.glsl`.
+    pub clip_plane: [f32; 4],
+    pub atmosphere: Atmosphere,
     transform: Transform,
-    tex: Texture,
+    tex: TextureHandle,
 }
 
 impl Cube {
-    pub fn new() -> Self {
-        let file = fs_load("texture.png").unwrap();
-        let decoder = PNGDecoder::new(&file[..]).unwrap();
-        let image = DynamicImage::from_decoder(decoder).unwrap();
-        let texture = Texture::from_image(&image.to_rgba(), &TextureSettings::new().convert_gamma(true));
+    pub fn new(textures: &mut TextureManager) -> Result<Self, AssetError> {
+        Ok(Self::with_texture(textures.get("texture.png")?))
+    }
+
+    /// An untextured cube, for when the texture can't be loaded.
+    pub fn plain(textures: &mut TextureManager) -> Self {
+        Self::with_texture(textures.plain())
+    }
+
+    fn with_texture(texture: TextureHandle) -> Self {
         Self {
             light: Vector3::zero(),
+            clip_plane: NO_CLIP,
+            atmosphere: Atmosphere::default(),
             tex: texture,
             transform: Transform::identity(),
         }
@@ -142,7 +151,7 @@ impl Drawable for Cube {
             &context.draw_state,
             POINTS_N_PER_VERTEX,
             Some(INDICES_N_PER_VERTEX),
-            Some((&self.tex, UV_N_PER_VERTEX)),
+            Some((&*self.tex, UV_N_PER_VERTEX)),
             Some(COLOURS_N_PER_VERTEX),
             Some(NORMALS_N_PER_VERTEX),
             |shader, gl| {
@@ -151,6 +160,10 @@ impl Drawable for Cube {
                 shader.world_matrix_uni.set(gl, lock.current().as_ref());
                 shader.projection_matrix_uni.set(gl, cache.projection_ref());
                 shader.eye_uni.set(gl, cache.eye().as_ref());
+                shader.clip_plane_uni.set(gl, &self.clip_plane);
+                shader.light_colour_uni.set(gl, &self.atmosphere.light_colour(LIGHT_COLOUR));
+                shader.fog_colour_uni.set(gl, &self.atmosphere.fog_colour());
+                shader.fog_density_uni.set(gl, self.atmosphere.fog_density);
             });
     }
 }
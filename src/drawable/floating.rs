@@ -0,0 +1,271 @@
+use cgmath::{Vector3, Quaternion, Matrix4, InnerSpace, Rotation, Rotation3, Rad, Zero, One};
+use android_base::{Drawable, ViewProj, Transforms};
+use opengl_graphics::GlGraphics;
+use graphics::Context;
+use rand::Rng;
+use crate::shader::{LightShader, NO_CLIP, LIGHT_COLOUR};
+use crate::drawable::{Water, POINTS_N_PER_VERTEX, INDICES_N_PER_VERTEX, UV_N_PER_VERTEX, NORMALS_N_PER_VERTEX};
+use crate::textures::{TextureManager, TextureHandle};
+use crate::seed::{WorldSeed, SeedPurpose};
+use crate::weather::Atmosphere;
+
+const GRAVITY: f32 = 9.81;
+/// Kilograms per cubic metre; anything lighter floats.
+pub const WATER_DENSITY: f32 = 1000.0;
+/// Fraction of their speed through the water, or the wind out of it, bodies
+/// lose per second.
+const WATER_DRAG: f32 = 3.0;
+const AIR_DRAG: f32 = 0.05;
+/// Fraction of their spin bodies lose per second.
+const ANGULAR_DRAG: f32 = 2.0;
+/// Thin bodies bob quickly, so they're stepped at least this finely.
+const MAX_SUBSTEP: f32 = 1.0 / 240.0;
+/// Bodies scattered over the water by `Floaters::scatter`.
+const SCATTER_COUNT: usize = 12;
+
+/// A box floating on the water, pushed up at the middle of each of its
+/// eight octants by as much water as that octant displaces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloatingBody {
+    pub position: Vector3<f32>,
+    pub velocity: Vector3<f32>,
+    pub orientation: Quaternion<f32>,
+    pub angular_velocity: Vector3<f32>,
+    pub half_extents: Vector3<f32>,
+    /// Kilograms per cubic metre.
+    pub density: f32,
+    pub colour: [f32; 4],
+}
+
+impl FloatingBody {
+    pub fn new(position: Vector3<f32>, half_extents: Vector3<f32>, density: f32, colour: [f32; 4]) -> Self {
+        Self {
+            position,
+            velocity: Vector3::zero(),
+            orientation: Quaternion::one(),
+            angular_velocity: Vector3::zero(),
+            half_extents,
+            density,
+            colour,
+        }
+    }
+
+    fn volume(&self) -> f32 {
+        let h = self.half_extents;
+        8.0 * h.x * h.y * h.z
+    }
+
+    fn mass(&self) -> f32 {
+        self.density * self.volume()
+    }
+
+    /// Moments of inertia about the box's own axes.
+    fn inertia(&self) -> Vector3<f32> {
+        let (h, m) = (self.half_extents, self.mass() / 3.0);
+        Vector3::new(m * (h.y * h.y + h.z * h.z), m * (h.x * h.x + h.z * h.z), m * (h.x * h.x + h.y * h.y))
+    }
+
+    /// Model matrix for a cube spanning -1 to 1.
+    pub fn matrix(&self) -> Matrix4<f32> {
+        let h = self.half_extents;
+        Matrix4::from_translation(self.position)
+            * Matrix4::from(self.orientation)
+            * Matrix4::from_nonuniform_scale(h.x, h.y, h.z)
+    }
+
+    /// Simulates `dt` seconds against `water` at `time`, pushed along by
+    /// `wind` where out of the water.
+    pub fn step(&mut self, dt: f32, water: &Water, time: f32, wind: Vector3<f32>) {
+        let substeps = (dt / MAX_SUBSTEP).ceil().max(1.0);
+        let dt = dt / substeps;
+        for i in 0..substeps as usize {
+            self.substep(dt, water, time + dt * i as f32, wind);
+        }
+    }
+
+    fn substep(&mut self, dt: f32, water: &Water, time: f32, wind: Vector3<f32>) {
+        let mass = self.mass();
+        let h = self.half_extents;
+        let octant_volume = self.volume() / 8.0;
+        let mut force = Vector3::new(0.0, -mass * GRAVITY, 0.0);
+        let mut torque = Vector3::zero();
+        for &(sx, sy, sz) in &[
+            (-1.0, -1.0, -1.0), (1.0, -1.0, -1.0), (-1.0, 1.0, -1.0), (1.0, 1.0, -1.0),
+            (-1.0, -1.0, 1.0), (1.0, -1.0, 1.0), (-1.0, 1.0, 1.0), (1.0, 1.0, 1.0),
+        ] {
+            let arm = self.orientation.rotate_vector(Vector3::new(sx * h.x, sy * h.y, sz * h.z) * 0.5);
+            let point = self.position + arm;
+            let surface = water.height_at(point.x, point.z, time);
+            // Octants are taken as level slabs `h.y` tall.
+            let submerged = ((surface - point.y) / h.y + 0.5).max(0.0).min(1.0);
+            let velocity = self.velocity + self.angular_velocity.cross(arm);
+            let mut push = -velocity * WATER_DRAG * submerged * mass / 8.0;
+            push += (wind - velocity) * AIR_DRAG * (1.0 - submerged) * mass / 8.0;
+            if submerged > 0.0 {
+                // Along the surface normal, so bodies slide down the waves.
+                let normal = water.normal_at(point.x, point.z, time);
+                push += normal * WATER_DENSITY * GRAVITY * octant_volume * submerged;
+            }
+            force += push;
+            torque += arm.cross(push);
+        }
+
+        self.velocity += force / mass * dt;
+        let local_torque = self.orientation.conjugate().rotate_vector(torque);
+        let inertia = self.inertia();
+        let local_acceleration = Vector3::new(
+            local_torque.x / inertia.x,
+            local_torque.y / inertia.y,
+            local_torque.z / inertia.z,
+        );
+        self.angular_velocity += self.orientation.rotate_vector(local_acceleration) * dt;
+        self.angular_velocity *= (1.0 - ANGULAR_DRAG * dt).max(0.0);
+
+        // Washed up bodies are turned back towards the middle.
+        let outward = Vector3::new(self.position.x, 0.0, self.position.z);
+        if !water.contains(self.position.x, self.position.z) && self.velocity.dot(outward) > 0.0 {
+            self.velocity.x = -self.velocity.x;
+            self.velocity.z = -self.velocity.z;
+        }
+
+        self.position += self.velocity * dt;
+        let spin = Quaternion::from_sv(0.0, self.angular_velocity) * self.orientation;
+        self.orientation = (self.orientation + spin * (0.5 * dt)).normalize();
+    }
+}
+
+/// Everything floating on the water, drawn as boxes.
+pub struct Floaters {
+    pub bodies: Vec<FloatingBody>,
+    pub light: Vector3<f32>,
+    pub clip_plane: [f32; 4],
+    pub atmosphere: Atmosphere,
+    tex: TextureHandle,
+}
+
+impl Floaters {
+    pub fn new(textures: &mut TextureManager) -> Self {
+        Self {
+            bodies: Vec::new(),
+            light: Vector3::zero(),
+            clip_plane: NO_CLIP,
+            atmosphere: Atmosphere::default(),
+            tex: textures.plain(),
+        }
+    }
+
+    /// Replaces the bodies with boats, seeds and leaves dropped within
+    /// `radius` world units of the middle, the same for the same seed.
+    pub fn scatter(&mut self, seed: WorldSeed, radius: f32) {
+        self.bodies = scattered(seed, radius);
+    }
+
+    pub fn step(&mut self, dt: f32, water: &Water, time: f32, wind: Vector3<f32>) {
+        for body in &mut self.bodies {
+            body.step(dt, water, time, wind);
+        }
+    }
+}
+
+/// The bodies `Floaters::scatter` drops.
+fn scattered(seed: WorldSeed, radius: f32) -> Vec<FloatingBody> {
+    let mut rng = seed.rng(SeedPurpose::Scattering);
+    (0..SCATTER_COUNT)
+        .map(|_| {
+            let angle = rng.gen_range(0.0, std::f32::consts::PI * 2.0);
+            let distance = radius * rng.gen::<f32>().sqrt();
+            let position = Vector3::new(distance * angle.cos(), 0.5, distance * angle.sin());
+            let mut body = match rng.gen_range(0, 3) {
+                0 => FloatingBody::new(position, Vector3::new(0.4, 0.12, 0.18), 350.0, [0.55, 0.35, 0.2, 1.0]),
+                1 => FloatingBody::new(position, Vector3::new(0.05, 0.05, 0.05), 600.0, [0.45, 0.4, 0.15, 1.0]),
+                _ => FloatingBody::new(position, Vector3::new(0.12, 0.01, 0.08), 700.0, [0.3, 0.6, 0.15, 1.0]),
+            };
+            body.orientation = Quaternion::from_angle_y(Rad(rng.gen_range(0.0, std::f32::consts::PI * 2.0)));
+            body
+        })
+        .collect()
+}
+
+impl Drawable for Floaters {
+    type Shader = LightShader;
+    fn draw_with(
+        &mut self,
+        data: &mut LightShader,
+        graphics: &mut GlGraphics,
+        context: &Context,
+        cache: &mut ViewProj,
+        transforms: &mut Transforms
+    ) {
+        let lock = transforms.push_none();
+        data.set_eye(cache.eye());
+        for body in &self.bodies {
+            let colours = vec![body.colour; POINTS_N_PER_VERTEX.len()];
+            let model = lock.current() * body.matrix();
+            graphics.shader_draw(
+                data,
+                &context.draw_state,
+                POINTS_N_PER_VERTEX,
+                Some(INDICES_N_PER_VERTEX),
+                Some((&*self.tex, UV_N_PER_VERTEX)),
+                Some(&colours),
+                Some(NORMALS_N_PER_VERTEX),
+                |shader, gl| {
+                    shader.light_uni.set(gl, self.light.as_ref());
+                    shader.view_matrix_uni.set(gl, cache.view_ref());
+                    shader.world_matrix_uni.set(gl, model.as_ref());
+                    shader.projection_matrix_uni.set(gl, cache.projection_ref());
+                    shader.eye_uni.set(gl, cache.eye().as_ref());
+                    shader.clip_plane_uni.set(gl, &self.clip_plane);
+                    shader.light_colour_uni.set(gl, &self.atmosphere.light_colour(LIGHT_COLOUR));
+                    shader.fog_colour_uni.set(gl, &self.atmosphere.fog_colour());
+                    shader.fog_density_uni.set(gl, self.atmosphere.fog_density);
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawable::{Waves, WaterShape, WaterResolution};
+
+    fn still_water() -> Water {
+        let mut water = Water::create(WaterShape::Circle { radius: 4.0 }, WaterResolution::Uniform { spacing: 1.0 }).unwrap();
+        water.waves = Waves::new(Vec::new()).unwrap();
+        water
+    }
+
+    #[test]
+    fn half_as_dense_floats_half_under() {
+        let water = still_water();
+        let mut body = FloatingBody::new(Vector3::new(0.0, 0.3, 0.0), Vector3::new(0.5, 0.5, 0.5), WATER_DENSITY / 2.0, [1.0; 4]);
+        for step in 0..600 {
+            body.step(1.0 / 60.0, &water, step as f32 / 60.0, Vector3::zero());
+        }
+        // The middle of the box sits at the surface.
+        assert!(body.position.y.abs() < 0.02, "settled at {}", body.position.y);
+        assert!(body.velocity.magnitude() < 0.01);
+    }
+
+    #[test]
+    fn bodies_out_of_the_water_fall() {
+        let water = still_water();
+        let mut body = FloatingBody::new(Vector3::new(1.0, 3.0, -1.0), Vector3::new(0.2, 0.2, 0.2), 500.0, [1.0; 4]);
+        body.step(0.5, &water, 0.0, Vector3::zero());
+        // Nearly the 1.23 free fall would take it, less a little air drag.
+        assert!(body.position.y < 3.0 - 1.1 && body.position.y > 3.0 - 1.25, "at {}", body.position.y);
+        assert!(body.velocity.y < -4.5);
+    }
+
+    #[test]
+    fn scattering_is_the_same_for_the_same_seed() {
+        let bodies = scattered(WorldSeed(7), 5.0);
+        assert_eq!(bodies.len(), SCATTER_COUNT);
+        assert_eq!(bodies, scattered(WorldSeed(7), 5.0));
+        assert_ne!(bodies, scattered(WorldSeed(8), 5.0));
+        for body in &bodies {
+            let Vector3 { x, z, .. } = body.position;
+            assert!((x * x + z * z).sqrt() <= 5.0);
+        }
+    }
+}
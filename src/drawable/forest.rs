@@ -0,0 +1,184 @@
+use cgmath::{Vector3, Vector4, Matrix4, Zero, SquareMatrix};
+use android_base::{Drawable, ViewProj, Transforms};
+use opengl_graphics::GlGraphics;
+use graphics::Context;
+use crate::shader::{LightShader, NO_CLIP, LIGHT_COLOUR};
+use crate::drawable::{POINTS_N_PER_VERTEX, INDICES_N_PER_VERTEX, UV_N_PER_VERTEX, NORMALS_N_PER_VERTEX, Emitter, ParticleLook, ParticleSystem};
+use crate::textures::{TextureManager, TextureHandle};
+use crate::ecology::{Ecology, Tree};
+use crate::seasons::Foliage;
+use crate::weather::Atmosphere;
+
+const TRUNK_COLOUR: [f32; 4] = [0.4, 0.28, 0.18, 1.0];
+/// Trunks are this wide for every unit of height.
+const TRUNK_RATIO: f32 = 0.03;
+/// Crowns with fewer leaves than this aren't drawn.
+const BARE: f32 = 0.02;
+/// How far trees lean for each unit of wind speed, per unit of height.
+const SWAY: f32 = 0.02;
+/// Boxes drawn together, few enough to index their vertices with `u16`s.
+const BATCH: usize = 1000;
+
+/// The ecology's trees, drawn as a box trunk under a box crown that fills
+/// out with leaves through the year.
+pub struct Forest {
+    pub ecology: Ecology,
+    /// See `Calendar::time_of_year`.
+    pub time_of_year: f32,
+    /// Simulated time, for gusts.
+    pub time: f32,
+    /// See `Conditions::wind`.
+    pub wind: [f32; 2],
+    pub light: Vector3<f32>,
+    pub clip_plane: [f32; 4],
+    pub atmosphere: Atmosphere,
+    tex: TextureHandle,
+    /// Every box's vertices, in world space, kept between draws.
+    points: Vec<[f32; 4]>,
+    uvs: Vec<[f32; 2]>,
+    colours: Vec<[f32; 4]>,
+    normals: Vec<[f32; 3]>,
+    /// Indices for a whole batch of boxes.
+    indices: Vec<u16>,
+}
+
+impl Forest {
+    pub fn new(ecology: Ecology, textures: &mut TextureManager) -> Self {
+        Self {
+            ecology,
+            time_of_year: 0.0,
+            time: 0.0,
+            wind: [0.0; 2],
+            light: Vector3::zero(),
+            clip_plane: NO_CLIP,
+            atmosphere: Atmosphere::default(),
+            tex: textures.plain(),
+            points: Vec::new(),
+            uvs: Vec::new(),
+            colours: Vec::new(),
+            normals: Vec::new(),
+            indices: (0..BATCH)
+                .flat_map(|i| INDICES_N_PER_VERTEX.iter().map(move |&index| (i * POINTS_N_PER_VERTEX.len()) as u16 + index))
+                .collect(),
+        }
+    }
+
+    /// Middle and half size of the crown `tree` has with `foliage`, or
+    /// `None` once it's bare.
+    fn crown(&self, tree: &Tree, foliage: &Foliage) -> Option<(Vector3<f32>, Vector3<f32>)> {
+        if foliage.leaf_density <= BARE {
+            return None;
+        }
+        // Buds and thinning leaves still show as a smaller crown.
+        let radius = tree.crown_radius(&self.ecology.species()[tree.species].habit) * (0.4 + 0.6 * foliage.leaf_density);
+        let [x, z] = tree.position;
+        Some((Vector3::new(x, tree.height * 0.65, z), Vector3::new(radius, tree.height * 0.35, radius)))
+    }
+
+    /// Where every living tree is dropping leaves from, for
+    /// `ParticleSystem::sources`.
+    pub fn leaf_sources(&self) -> Vec<(Emitter, ParticleLook)> {
+        let species = self.ecology.species();
+        let foliage: Vec<_> = species.iter().map(|species| species.foliage(self.time_of_year)).collect();
+        self.ecology
+            .trees()
+            .iter()
+            .filter(|tree| foliage[tree.species].leaf_drop > 0.0)
+            .filter_map(|tree| {
+                let foliage = &foliage[tree.species];
+                let (centre, half_extents) = self.crown(tree, foliage)?;
+                Some(ParticleSystem::leaf(centre, half_extents, foliage.leaf_drop, foliage.leaf_colour, species[tree.species].fallen_colour))
+            })
+            .collect()
+    }
+
+    /// Model matrices and colours of the boxes making up every tree.
+    fn boxes(&self) -> Vec<(Matrix4<f32>, [f32; 4])> {
+        let foliage: Vec<_> = self.ecology.species().iter().map(|species| species.foliage(self.time_of_year)).collect();
+        let mut boxes = Vec::new();
+        for tree in self.ecology.trees() {
+            let [x, z] = tree.position;
+            let trunk = (tree.height * TRUNK_RATIO).max(0.01);
+            let sway = self.sway(x, z);
+            boxes.push((
+                sway * Matrix4::from_translation(Vector3::new(x, tree.height * 0.25, z))
+                    * Matrix4::from_nonuniform_scale(trunk, tree.height * 0.25, trunk),
+                TRUNK_COLOUR,
+            ));
+            let foliage = &foliage[tree.species];
+            if let Some((centre, half)) = self.crown(tree, foliage) {
+                boxes.push((
+                    sway * Matrix4::from_translation(centre) * Matrix4::from_nonuniform_scale(half.x, half.y, half.z),
+                    foliage.leaf_colour,
+                ));
+            }
+        }
+        boxes
+    }
+
+    /// Leans the tree rooted at `(x, z)` downwind, further the higher up,
+    /// with gusts that reach each tree at a slightly different time.
+    fn sway(&self, x: f32, z: f32) -> Matrix4<f32> {
+        let gust = 0.75 + 0.25 * (self.time * 1.7 + x * 0.9 + z * 1.3).sin();
+        let [dx, dz] = [self.wind[0] * SWAY * gust, self.wind[1] * SWAY * gust];
+        let mut shear = Matrix4::identity();
+        shear.y.x = dx;
+        shear.y.z = dz;
+        Matrix4::from_translation(Vector3::new(x, 0.0, z)) * shear * Matrix4::from_translation(Vector3::new(-x, 0.0, -z))
+    }
+}
+
+impl Drawable for Forest {
+    type Shader = LightShader;
+    fn draw_with(
+        &mut self,
+        data: &mut LightShader,
+        graphics: &mut GlGraphics,
+        context: &Context,
+        cache: &mut ViewProj,
+        transforms: &mut Transforms
+    ) {
+        // Boxes are drawn a batch at a time, moved into place here rather
+        // than by the shader. Normals are transformed the same way it would.
+        let boxes = self.boxes();
+        self.points.clear();
+        self.uvs.clear();
+        self.colours.clear();
+        self.normals.clear();
+        for (matrix, colour) in boxes {
+            for (point, normal) in POINTS_N_PER_VERTEX.iter().zip(NORMALS_N_PER_VERTEX) {
+                self.points.push((matrix * Vector4::from(*point)).into());
+                self.normals.push((matrix * Vector3::from(*normal).extend(0.0)).truncate().into());
+            }
+            self.uvs.extend_from_slice(UV_N_PER_VERTEX);
+            self.colours.extend(std::iter::repeat(colour).take(POINTS_N_PER_VERTEX.len()));
+        }
+        let lock = transforms.push_none();
+        let model = lock.current();
+        data.set_eye(cache.eye());
+        let per_batch = BATCH * POINTS_N_PER_VERTEX.len();
+        for start in (0..self.points.len()).step_by(per_batch) {
+            let end = (start + per_batch).min(self.points.len());
+            let boxes = (end - start) / POINTS_N_PER_VERTEX.len();
+            graphics.shader_draw(
+                data,
+                &context.draw_state,
+                &self.points[start..end],
+                Some(&self.indices[..boxes * INDICES_N_PER_VERTEX.len()]),
+                Some((&*self.tex, &self.uvs[start..end])),
+                Some(&self.colours[start..end]),
+                Some(&self.normals[start..end]),
+                |shader, gl| {
+                    shader.light_uni.set(gl, self.light.as_ref());
+                    shader.view_matrix_uni.set(gl, cache.view_ref());
+                    shader.world_matrix_uni.set(gl, model.as_ref());
+                    shader.projection_matrix_uni.set(gl, cache.projection_ref());
+                    shader.eye_uni.set(gl, cache.eye().as_ref());
+                    shader.clip_plane_uni.set(gl, &self.clip_plane);
+                    shader.light_colour_uni.set(gl, &self.atmosphere.light_colour(LIGHT_COLOUR));
+                    shader.fog_colour_uni.set(gl, &self.atmosphere.fog_colour());
+                    shader.fog_density_uni.set(gl, self.atmosphere.fog_density);
+                });
+        }
+    }
+}
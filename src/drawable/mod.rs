@@ -1,9 +1,25 @@
 mod cube;
 mod axis;
 mod water;
+mod water_noise;
+mod water_lod;
+mod water_shape;
+mod waves;
+mod ripples;
+mod floating;
+mod particles;
+mod forest;
 mod world;
 
 pub use cube::*;
 pub use axis::*;
 pub use water::*;
+pub use water_noise::*;
+pub use water_lod::*;
+pub use water_shape::*;
+pub use waves::*;
+pub use ripples::*;
+pub use floating::*;
+pub use particles::*;
+pub use forest::*;
 pub use world::*;
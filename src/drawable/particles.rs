@@ -0,0 +1,514 @@
+use cgmath::{Vector3, Zero};
+use android_base::{Drawable, ViewProj, Transforms};
+use opengl_graphics::GlGraphics;
+use opengl_graphics::gl;
+use graphics::Context;
+use rand::Rng;
+use rand_pcg::Pcg32;
+use crate::shader::ParticleShader;
+use crate::drawable::Water;
+use crate::seed::{WorldSeed, SeedPurpose};
+use crate::textures::{TextureManager, TextureArrayHandle};
+
+const GRAVITY: f32 = 9.81;
+/// Particles that fall this far below still water without landing on it
+/// are gone.
+const FLOOR: f32 = -5.0;
+/// How fast fluttering particles sway, in radians a second.
+const FLUTTER_RATE: f32 = 3.0;
+/// The shapes of raindrops, snowflakes and leaves, in that order, as the
+/// layers of one texture array.
+pub const SPRITES: [&str; 3] = ["particles/drop.png", "particles/flake.png", "particles/leaf.png"];
+
+/// Where and how particles are let loose.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Emitter {
+    /// Middle of the box particles start in, in world units.
+    pub centre: Vector3<f32>,
+    pub half_extents: Vector3<f32>,
+    /// Particles a second.
+    pub rate: f32,
+    pub velocity: Vector3<f32>,
+    /// Largest speed added to `velocity` in a random direction.
+    pub spread: f32,
+    /// Shortest and longest a particle lasts, in seconds.
+    pub lifetime: (f32, f32),
+}
+
+/// How particles look, from when they're let loose to when their lifetime
+/// runs out.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ParticleLook {
+    pub start_colour: [f32; 4],
+    pub end_colour: [f32; 4],
+    /// In world units.
+    pub start_size: f32,
+    pub end_size: f32,
+}
+
+/// What happens when a particle reaches the water.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Landing {
+    /// Ripples the water, see `Ripples::impulse`.
+    Splash { strength: f32, radius: f32 },
+    /// Disappears without a trace.
+    Sink,
+}
+
+/// A particle that landed with `Landing::Splash`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Splash {
+    pub x: f32,
+    pub z: f32,
+    pub strength: f32,
+    pub radius: f32,
+}
+
+#[derive(Clone, Debug)]
+struct Particle {
+    position: Vector3<f32>,
+    velocity: Vector3<f32>,
+    age: f32,
+    lifetime: f32,
+    /// Where in its sway a fluttering particle is.
+    phase: f32,
+    /// That of whatever let it loose.
+    look: ParticleLook,
+}
+
+#[derive(Clone, Debug)]
+pub struct ParticleSystem {
+    pub emitter: Emitter,
+    pub look: ParticleLook,
+    /// More emitters letting particles loose alongside `emitter`, each
+    /// with its own look.
+    pub sources: Vec<(Emitter, ParticleLook)>,
+    /// Fraction of gravity the particles feel.
+    pub gravity: f32,
+    /// How quickly particles take on the wind's speed, per second. Falling
+    /// particles level out at `gravity * 9.81 / drag`.
+    pub drag: f32,
+    /// Sideways speed of swaying back and forth, like a leaf.
+    pub flutter: f32,
+    pub landing: Landing,
+    pub max_particles: usize,
+    particles: Vec<Particle>,
+    /// Part of a particle left over from the last step's emission.
+    owed: f32,
+}
+
+impl ParticleSystem {
+    pub fn new(emitter: Emitter, look: ParticleLook) -> Self {
+        Self {
+            emitter,
+            look,
+            sources: Vec::new(),
+            gravity: 1.0,
+            drag: 0.0,
+            flutter: 0.0,
+            landing: Landing::Sink,
+            max_particles: 1000,
+            particles: Vec::new(),
+            owed: 0.0,
+        }
+    }
+
+    /// Drops falling over a wide area, that ripple the water. Off until
+    /// `emitter.rate` is set.
+    pub fn rain() -> Self {
+        let mut rain = Self::new(
+            Emitter {
+                centre: Vector3::new(0.0, 12.0, 0.0),
+                half_extents: Vector3::new(12.0, 1.0, 12.0),
+                rate: 0.0,
+                velocity: Vector3::new(0.0, -8.0, 0.0),
+                spread: 0.5,
+                lifetime: (3.0, 3.0),
+            },
+            ParticleLook {
+                start_colour: [0.7, 0.75, 0.85, 0.6],
+                end_colour: [0.7, 0.75, 0.85, 0.6],
+                start_size: 0.03,
+                end_size: 0.03,
+            },
+        );
+        rain.drag = 0.5;
+        rain.landing = Landing::Splash { strength: 0.02, radius: 0.15 };
+        rain.max_particles = 4000;
+        rain
+    }
+
+    /// Flakes drifting down on the wind. Off until `emitter.rate` is set.
+    pub fn snow() -> Self {
+        let mut snow = Self::new(
+            Emitter {
+                centre: Vector3::new(0.0, 10.0, 0.0),
+                half_extents: Vector3::new(12.0, 1.0, 12.0),
+                rate: 0.0,
+                velocity: Vector3::new(0.0, -1.0, 0.0),
+                spread: 0.3,
+                lifetime: (14.0, 16.0),
+            },
+            ParticleLook {
+                start_colour: [1.0, 1.0, 1.0, 0.9],
+                end_colour: [1.0, 1.0, 1.0, 0.0],
+                start_size: 0.06,
+                end_size: 0.05,
+            },
+        );
+        snow.gravity = 0.3;
+        snow.drag = 3.0;
+        snow.flutter = 0.3;
+        snow.max_particles = 4000;
+        snow
+    }
+
+    /// Leaves swaying down and fading as they fall. Off until `sources`
+    /// are given for the crowns they fall from, see `ParticleSystem::leaf`.
+    pub fn leaves() -> Self {
+        let mut leaves = Self::new(
+            Emitter {
+                centre: Vector3::new(0.0, 2.5, 0.0),
+                half_extents: Vector3::new(1.5, 0.5, 1.5),
+                rate: 0.0,
+                velocity: Vector3::zero(),
+                spread: 0.2,
+                lifetime: (6.0, 9.0),
+            },
+            ParticleLook {
+                start_colour: [0.45, 0.6, 0.15, 1.0],
+                end_colour: [0.55, 0.3, 0.1, 1.0],
+                start_size: 0.12,
+                end_size: 0.12,
+            },
+        );
+        leaves.gravity = 0.5;
+        leaves.drag = 2.0;
+        leaves.flutter = 0.8;
+        leaves.landing = Landing::Splash { strength: 0.01, radius: 0.2 };
+        leaves.max_particles = 1000;
+        leaves
+    }
+
+    /// A source for `leaves` dropping `rate` leaves a second from the box
+    /// `centre` plus or minus `half_extents`, fading from `colour` to
+    /// `fallen_colour`.
+    pub fn leaf(centre: Vector3<f32>, half_extents: Vector3<f32>, rate: f32, colour: [f32; 4], fallen_colour: [f32; 4]) -> (Emitter, ParticleLook) {
+        let leaves = Self::leaves();
+        (
+            Emitter { centre, half_extents, rate, ..leaves.emitter },
+            ParticleLook { start_colour: colour, end_colour: fallen_colour, ..leaves.look },
+        )
+    }
+
+    pub fn len(&self) -> usize {
+        self.particles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.clear();
+        self.owed = 0.0;
+    }
+
+    /// Lets a particle loose from `emitter`, or one of `sources` picked by
+    /// their rates given `index` between 0 and the sum of every rate.
+    fn emit(&mut self, mut index: f32, rng: &mut Pcg32) {
+        let (mut emitter, mut look) = (&self.emitter, self.look);
+        index -= emitter.rate.max(0.0);
+        for (source, source_look) in &self.sources {
+            if index < 0.0 {
+                break;
+            }
+            emitter = source;
+            look = *source_look;
+            index -= source.rate.max(0.0);
+        }
+        let mut offset = || rng.gen_range(-1.0f32, 1.0);
+        let position = emitter.centre + Vector3::new(
+            offset() * emitter.half_extents.x,
+            offset() * emitter.half_extents.y,
+            offset() * emitter.half_extents.z,
+        );
+        let velocity = emitter.velocity + Vector3::new(offset(), offset(), offset()) * emitter.spread;
+        let (shortest, longest) = emitter.lifetime;
+        let lifetime = shortest + (longest - shortest) * rng.gen::<f32>();
+        let phase = rng.gen_range(0.0, std::f32::consts::PI * 2.0);
+        self.particles.push(Particle { position, velocity, age: 0.0, lifetime, phase, look });
+    }
+
+    /// Lets loose this step's particles and moves everything on by `dt`
+    /// seconds, adding any that splash into `water` at `time` to `splashes`.
+    pub fn step(&mut self, dt: f32, wind: Vector3<f32>, rng: &mut Pcg32, water: &Water, time: f32, splashes: &mut Vec<Splash>) {
+        let rate = self.emitter.rate.max(0.0) + self.sources.iter().map(|(source, _)| source.rate.max(0.0)).sum::<f32>();
+        self.owed += rate * dt;
+        let room = self.max_particles.saturating_sub(self.particles.len());
+        let count = (self.owed.floor() as usize).min(room);
+        self.owed -= self.owed.floor();
+        for _ in 0..count {
+            // Without sources there's nothing to pick between.
+            let index = if self.sources.is_empty() { 0.0 } else { rng.gen::<f32>() * rate };
+            self.emit(index, rng);
+        }
+
+        let (gravity, drag, flutter, landing) = (self.gravity * GRAVITY, self.drag, self.flutter, self.landing);
+        let mut i = 0;
+        while i < self.particles.len() {
+            let particle = &mut self.particles[i];
+            particle.age += dt;
+            particle.velocity.y -= gravity * dt;
+            particle.velocity += (wind - particle.velocity) * (drag * dt).min(1.0);
+            let (sin, cos) = (particle.age * FLUTTER_RATE + particle.phase).sin_cos();
+            particle.position += (particle.velocity + Vector3::new(sin, 0.0, cos) * flutter) * dt;
+
+            let Vector3 { x, y, z } = particle.position;
+            let landed = water.contains(x, z) && y <= water.height_at(x, z, time);
+            if landed {
+                if let Landing::Splash { strength, radius } = landing {
+                    splashes.push(Splash { x, z, strength, radius });
+                }
+            }
+            if landed || particle.age >= particle.lifetime || y < FLOOR {
+                self.particles.swap_remove(i);
+            } else {
+                i += 1;
+            }
+        }
+    }
+
+    /// Appends a point per particle, with its size in w.
+    fn vertices(&self, positions: &mut Vec<[f32; 4]>, colours: &mut Vec<[f32; 4]>) {
+        for particle in &self.particles {
+            let look = &particle.look;
+            let t = (particle.age / particle.lifetime).min(1.0);
+            let size = look.start_size + (look.end_size - look.start_size) * t;
+            let mut colour = [0.0; 4];
+            for (i, channel) in colour.iter_mut().enumerate() {
+                *channel = look.start_colour[i] + (look.end_colour[i] - look.start_colour[i]) * t;
+            }
+            let Vector3 { x, y, z } = particle.position;
+            positions.push([x, y, z, size]);
+            colours.push(colour);
+        }
+    }
+}
+
+/// The world's rain, snow and falling leaves.
+pub struct Particles {
+    pub rain: ParticleSystem,
+    pub snow: ParticleSystem,
+    pub leaves: ParticleSystem,
+    /// World units a second the air is moving.
+    pub wind: Vector3<f32>,
+    rng: Pcg32,
+    /// `SPRITES`, or `None` to draw round points.
+    sprites: Option<TextureArrayHandle>,
+    positions: Vec<[f32; 4]>,
+    colours: Vec<[f32; 4]>,
+}
+
+impl Particles {
+    pub fn new(seed: WorldSeed, textures: &mut TextureManager) -> Self {
+        let sprites = textures.array(&SPRITES).map_err(|e| println!("Drawing round particles: {}", e)).ok();
+        Self {
+            rain: ParticleSystem::rain(),
+            snow: ParticleSystem::snow(),
+            leaves: ParticleSystem::leaves(),
+            wind: Vector3::zero(),
+            rng: seed.rng(SeedPurpose::Particles),
+            sprites,
+            positions: Vec::new(),
+            colours: Vec::new(),
+        }
+    }
+
+    /// Starts over, letting particles loose the same way for the same seed.
+    pub fn reseed(&mut self, seed: WorldSeed) {
+        self.rng = seed.rng(SeedPurpose::Particles);
+        self.rain.clear();
+        self.snow.clear();
+        self.leaves.clear();
+    }
+
+    /// Keeps the rain and snow falling around the world space point
+    /// `(x, z)`.
+    pub fn follow(&mut self, x: f32, z: f32) {
+        for emitter in &mut [&mut self.rain.emitter, &mut self.snow.emitter] {
+            emitter.centre.x = x;
+            emitter.centre.z = z;
+        }
+    }
+
+    /// Steps every system, returning where particles splashed into the
+    /// water.
+    pub fn step(&mut self, dt: f32, water: &Water, time: f32) -> Vec<Splash> {
+        let mut splashes = Vec::new();
+        let wind = self.wind;
+        let Self { rain, snow, leaves, rng, .. } = self;
+        for system in &mut [rain, snow, leaves] {
+            system.step(dt, wind, rng, water, time, &mut splashes);
+        }
+        splashes
+    }
+}
+
+impl Drawable for Particles {
+    type Shader = ParticleShader;
+    fn draw_with(
+        &mut self,
+        data: &mut ParticleShader,
+        graphics: &mut GlGraphics,
+        context: &Context,
+        cache: &mut ViewProj,
+        transforms: &mut Transforms
+    ) {
+        let _lock = transforms.push_none();
+        let mut viewport = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        }
+        data.sprites = self.sprites.as_ref().map_or(0, |sprites| sprites.get_id());
+        let has_sprites = self.sprites.is_some();
+        // One draw per system, each with its own layer of `SPRITES`.
+        for (layer, system) in [&self.rain, &self.snow, &self.leaves].iter().enumerate() {
+            self.positions.clear();
+            self.colours.clear();
+            system.vertices(&mut self.positions, &mut self.colours);
+            if self.positions.is_empty() {
+                continue;
+            }
+            graphics.shader_draw(
+                data,
+                &context.draw_state,
+                &self.positions,
+                None,
+                None,
+                Some(&self.colours),
+                None,
+                |shader, gl| {
+                    shader.view_matrix_uni.set(gl, cache.view_ref());
+                    shader.projection_matrix_uni.set(gl, cache.projection_ref());
+                    shader.viewport_height_uni.set(gl, viewport[3] as f32);
+                    shader.sprite_layer_uni.set(gl, if has_sprites { layer as f32 } else { -1.0 });
+                });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use crate::drawable::{WaterShape, WaterResolution};
+
+    const DT: f32 = 0.25;
+
+    fn water() -> Water {
+        Water::create(WaterShape::Circle { radius: 2.0 }, WaterResolution::Uniform { spacing: 1.0 }).unwrap()
+    }
+
+    /// A system letting loose one particle a step of `DT`, still in the air
+    /// at `centre`.
+    fn one_a_step(centre: Vector3<f32>) -> ParticleSystem {
+        let mut system = ParticleSystem::new(
+            Emitter {
+                centre,
+                half_extents: Vector3::zero(),
+                rate: 1.0 / DT,
+                velocity: Vector3::zero(),
+                spread: 0.0,
+                lifetime: (1.0, 1.0),
+            },
+            ParticleLook {
+                start_colour: [1.0; 4],
+                end_colour: [1.0; 4],
+                start_size: 0.1,
+                end_size: 0.1,
+            },
+        );
+        system.gravity = 0.0;
+        system
+    }
+
+    #[test]
+    fn same_seed_same_particles() {
+        let water = water();
+        let run = || {
+            let mut system = ParticleSystem::leaves();
+            system.sources = vec![
+                ParticleSystem::leaf(Vector3::new(0.0, 2.5, 0.0), Vector3::new(1.5, 0.5, 1.5), 1.5, [0.4, 0.6, 0.1, 1.0], [0.5, 0.3, 0.1, 1.0]),
+                ParticleSystem::leaf(Vector3::new(4.0, 3.0, 1.0), Vector3::new(1.0, 0.5, 1.0), 3.0, [0.8, 0.5, 0.1, 1.0], [0.4, 0.3, 0.2, 1.0]),
+            ];
+            let mut rng = Pcg32::seed_from_u64(3);
+            let mut splashes = Vec::new();
+            for step in 0..200 {
+                system.step(1.0 / 60.0, Vector3::new(0.5, 0.0, 0.0), &mut rng, &water, step as f32 / 60.0, &mut splashes);
+            }
+            let (mut positions, mut colours) = (Vec::new(), Vec::new());
+            system.vertices(&mut positions, &mut colours);
+            (positions, colours, splashes)
+        };
+        let first = run();
+        assert!(!first.0.is_empty());
+        assert_eq!(first, run());
+    }
+
+    #[test]
+    fn particles_expire_at_their_lifetime() {
+        let water = water();
+        let mut system = one_a_step(Vector3::new(50.0, 1.0, 50.0));
+        let mut rng = Pcg32::seed_from_u64(0);
+        let mut splashes = Vec::new();
+        system.step(DT, Vector3::zero(), &mut rng, &water, 0.0, &mut splashes);
+        system.emitter.rate = 0.0;
+        assert_eq!(system.len(), 1);
+        // It ages a step as it's let loose. A quarter is exact in binary, so
+        // the fourth step takes it to exactly its lifetime.
+        for _ in 0..2 {
+            system.step(DT, Vector3::zero(), &mut rng, &water, 0.0, &mut splashes);
+            assert_eq!(system.len(), 1);
+        }
+        system.step(DT, Vector3::zero(), &mut rng, &water, 0.0, &mut splashes);
+        assert!(system.is_empty());
+        assert!(splashes.is_empty());
+    }
+
+    #[test]
+    fn sources_keep_their_own_look() {
+        let water = water();
+        let mut system = one_a_step(Vector3::new(50.0, 1.0, 50.0));
+        system.emitter.rate = 0.0;
+        let (red, blue) = ([1.0, 0.0, 0.0, 1.0], [0.0, 0.0, 1.0, 1.0]);
+        let source = |rate: f32, colour: [f32; 4]| {
+            let emitter = Emitter { rate, ..system.emitter.clone() };
+            (emitter, ParticleLook { start_colour: colour, end_colour: colour, ..system.look })
+        };
+        system.sources = vec![source(0.0, blue), source(1.0 / DT, red), source(1.0 / DT, blue)];
+        let mut rng = Pcg32::seed_from_u64(0);
+        let mut splashes = Vec::new();
+        for _ in 0..3 {
+            system.step(DT, Vector3::zero(), &mut rng, &water, 0.0, &mut splashes);
+        }
+        let (mut positions, mut colours) = (Vec::new(), Vec::new());
+        system.vertices(&mut positions, &mut colours);
+        assert_eq!(colours.len(), 6);
+        assert!(colours.contains(&red) && colours.contains(&blue));
+        assert!(colours.iter().all(|&colour| colour == red || colour == blue));
+    }
+
+    #[test]
+    fn particles_landing_in_water_splash() {
+        let water = water();
+        let mut system = one_a_step(Vector3::new(0.0, 1.0, 0.0));
+        system.emitter.velocity = Vector3::new(0.0, -8.0, 0.0);
+        system.landing = Landing::Splash { strength: 0.5, radius: 0.25 };
+        let mut rng = Pcg32::seed_from_u64(0);
+        let mut splashes = Vec::new();
+        system.step(DT, Vector3::zero(), &mut rng, &water, 0.0, &mut splashes);
+        assert_eq!(system.len(), 0);
+        assert_eq!(splashes, vec![Splash { x: 0.0, z: 0.0, strength: 0.5, radius: 0.25 }]);
+    }
+}
@@ -0,0 +1,210 @@
+/// Cells along each side of the ripple grid.
+pub const RIPPLE_RESOLUTION: usize = 128;
+/// Ripple heights either side of still water that fit in the texture.
+pub const RIPPLE_RANGE: f32 = 0.5;
+/// Keeps each substep of the simulation stable, below 1/√2.
+const MAX_COURANT: f32 = 0.5;
+
+/// A height field of ripples over the water, simulated with the 2D wave
+/// equation on a square grid centred on the origin. Cells outside the
+/// water's disc are held flat, so ripples reflect off its edge.
+pub struct Ripples {
+    resolution: usize,
+    /// Half the width of the grid in world units.
+    extent: f32,
+    radius: f32,
+    current: Vec<f32>,
+    previous: Vec<f32>,
+    /// Fraction of a ripple's height lost per second.
+    pub damping: f32,
+    /// How fast ripples spread, in world units per second.
+    pub speed: f32,
+}
+
+impl Ripples {
+    /// Ripples over a disc of `radius` world units.
+    pub fn new(radius: f32, resolution: usize) -> Self {
+        let resolution = resolution.max(2);
+        Self {
+            resolution,
+            extent: radius,
+            radius,
+            current: vec![0.0; resolution * resolution],
+            previous: vec![0.0; resolution * resolution],
+            damping: 0.6,
+            speed: 4.0,
+        }
+    }
+
+    pub fn resolution(&self) -> usize {
+        self.resolution
+    }
+
+    pub fn extent(&self) -> f32 {
+        self.extent
+    }
+
+    fn cell_size(&self) -> f32 {
+        self.extent * 2.0 / self.resolution as f32
+    }
+
+    /// World position of the centre of cell `(i, j)`.
+    fn cell_position(&self, i: usize, j: usize) -> (f32, f32) {
+        let cell = self.cell_size();
+        ((i as f32 + 0.5) * cell - self.extent, (j as f32 + 0.5) * cell - self.extent)
+    }
+
+    /// Drops something at `(x, z)`, pushing the water within `radius` down by
+    /// up to `strength`.
+    pub fn impulse(&mut self, x: f32, z: f32, strength: f32, radius: f32) {
+        let radius = radius.max(self.cell_size());
+        for j in 0..self.resolution {
+            for i in 0..self.resolution {
+                let (cx, cz) = self.cell_position(i, j);
+                let distance_sq = (cx - x).powi(2) + (cz - z).powi(2);
+                if distance_sq < radius * radius && cx * cx + cz * cz < self.radius * self.radius {
+                    // Smooth falloff so the impulse doesn't ring at grid scale.
+                    let falloff = 0.5 + 0.5 * (std::f32::consts::PI * distance_sq.sqrt() / radius).cos();
+                    // Both steps, so the water starts out still rather than
+                    // already moving down.
+                    let index = i + j * self.resolution;
+                    self.current[index] -= strength * falloff;
+                    self.previous[index] -= strength * falloff;
+                }
+            }
+        }
+    }
+
+    /// Flattens the water.
+    pub fn clear(&mut self) {
+        self.current.iter_mut().chain(&mut self.previous).for_each(|height| *height = 0.0);
+    }
+
+    /// Advances the ripples by `dt` seconds, in as many substeps as stability
+    /// needs.
+    pub fn step(&mut self, dt: f32) {
+        let courant = self.speed * dt / self.cell_size();
+        let substeps = (courant / MAX_COURANT).ceil().max(1.0) as usize;
+        let dt = dt / substeps as f32;
+        for _ in 0..substeps {
+            self.substep(dt);
+        }
+    }
+
+    fn substep(&mut self, dt: f32) {
+        let n = self.resolution;
+        let cell = self.cell_size();
+        let c2 = (self.speed * dt / cell).powi(2);
+        let keep = (1.0 - self.damping * dt).max(0.0);
+        let (extent, radius_sq) = (self.extent, self.radius * self.radius);
+        let current = &self.current;
+        let previous = &mut self.previous;
+        let at = |i: usize, j: usize| current[i + j * n];
+        for j in 0..n {
+            let z = (j as f32 + 0.5) * cell - extent;
+            for i in 0..n {
+                let index = i + j * n;
+                let x = (i as f32 + 0.5) * cell - extent;
+                if x * x + z * z >= radius_sq {
+                    previous[index] = 0.0;
+                    continue;
+                }
+                let here = at(i, j);
+                let left = if i > 0 { at(i - 1, j) } else { 0.0 };
+                let right = if i + 1 < n { at(i + 1, j) } else { 0.0 };
+                let down = if j > 0 { at(i, j - 1) } else { 0.0 };
+                let up = if j + 1 < n { at(i, j + 1) } else { 0.0 };
+                let laplacian = left + right + down + up - 4.0 * here;
+                // The new height overwrites the one from two steps ago.
+                previous[index] = (2.0 * here - previous[index] + c2 * laplacian) * keep;
+            }
+        }
+        std::mem::swap(&mut self.current, &mut self.previous);
+    }
+
+    /// Height of the ripples at `(x, z)`, interpolated between cells.
+    pub fn height_at(&self, x: f32, z: f32) -> f32 {
+        let n = self.resolution;
+        let cell = self.cell_size();
+        let u = ((x + self.extent) / cell - 0.5).max(0.0).min((n - 1) as f32);
+        let v = ((z + self.extent) / cell - 0.5).max(0.0).min((n - 1) as f32);
+        let (i, j) = (u.floor() as usize, v.floor() as usize);
+        let (i1, j1) = ((i + 1).min(n - 1), (j + 1).min(n - 1));
+        let (fu, fv) = (u - i as f32, v - j as f32);
+        let at = |i: usize, j: usize| self.current[i + j * n];
+        let near = at(i, j) + (at(i1, j) - at(i, j)) * fu;
+        let far = at(i, j1) + (at(i1, j1) - at(i, j1)) * fu;
+        near + (far - near) * fv
+    }
+
+    /// Heights packed into bytes for the shader, 0 and 255 being
+    /// `±RIPPLE_RANGE` and still water rounding to 128, half a step above
+    /// the 127.5 `ripple_offset` decodes as flat.
+    pub fn texels(&self) -> Vec<u8> {
+        self.current
+            .iter()
+            .map(|&height| ((height / RIPPLE_RANGE).max(-1.0).min(1.0) * 127.5 + 127.5).round() as u8)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RADIUS: f32 = 4.0;
+
+    fn ripples() -> Ripples {
+        let mut ripples = Ripples::new(RADIUS, 64);
+        ripples.impulse(0.0, 0.0, 0.2, 0.5);
+        ripples
+    }
+
+    #[test]
+    fn impulses_spread_then_die_down() {
+        let mut ripples = ripples();
+        assert!(ripples.height_at(0.0, 0.0) < -0.1);
+        assert_eq!(ripples.height_at(2.0, 0.0), 0.0);
+        let mut reached = false;
+        for _ in 0..30 {
+            ripples.step(1.0 / 60.0);
+            reached |= ripples.height_at(2.0, 0.0).abs() > 1e-3;
+        }
+        assert!(reached, "the ripple should have reached 2 units out");
+        for _ in 0..1200 {
+            ripples.step(1.0 / 60.0);
+        }
+        let highest = ripples.current.iter().fold(0.0f32, |highest, height| highest.max(height.abs()));
+        assert!(highest < 1e-3, "still {} high after 20 seconds", highest);
+    }
+
+    #[test]
+    fn clear_flattens_everything() {
+        let mut ripples = ripples();
+        ripples.step(0.1);
+        ripples.clear();
+        assert!(ripples.current.iter().chain(&ripples.previous).all(|&height| height == 0.0));
+        ripples.step(0.1);
+        assert!(ripples.current.iter().all(|&height| height == 0.0));
+    }
+
+    #[test]
+    fn nothing_moves_outside_the_disc() {
+        let mut ripples = Ripples::new(RADIUS, 64);
+        ripples.impulse(3.5, 3.5, 0.2, 2.0);
+        ripples.impulse(3.0, 0.0, 0.2, 2.0);
+        for _ in 0..60 {
+            ripples.step(1.0 / 60.0);
+            for &(x, z) in &[(3.5, 3.5), (-3.6, 3.6), (3.8, -3.8)] {
+                assert_eq!(ripples.height_at(x, z), 0.0, "at ({}, {})", x, z);
+            }
+        }
+    }
+
+    #[test]
+    fn texels_span_the_range() {
+        let mut ripples = Ripples::new(RADIUS, 2);
+        ripples.current = vec![0.0, -RIPPLE_RANGE, RIPPLE_RANGE, 2.0 * RIPPLE_RANGE];
+        assert_eq!(ripples.texels(), vec![128, 0, 255, 255]);
+    }
+}
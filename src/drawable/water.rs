@@ -1,41 +1,113 @@
 use std::collections::HashMap;
 use opengl_graphics::GlGraphics;
+use opengl_graphics::gl::types::GLuint;
 use cgmath::{Vector3, Rad, Matrix4, Zero};
 use android_base::{Drawable, Transform, ViewProj, Transforms};
-use crate::shader::WaterLight;
+use crate::shader::{WaterLight, Texture3D, Wrap};
+use crate::seed::{WorldSeed, SeedPurpose};
+use crate::weather::Atmosphere;
+use crate::drawable::{NoiseJob, NoiseSettings, Waves, Ripples, LodSettings, WaterShape, WaterResolution, RIPPLE_RESOLUTION, RIPPLE_RANGE};
 use graphics::Context;
 use std::f32::consts::{PI, SQRT_2};
 
+/// The water's transform scales its mesh by this much.
+const MESH_SCALE: f32 = 2.0;
+/// Furthest the noise moves the surface up or down, in world units. It's
+/// handed to the water shader as `NOISE_HEIGHT`, and kept well under the
+/// waves so `height_at` can leave it out.
+pub const NOISE_HEIGHT: f32 = 0.05;
+const COLOUR: [f32; 4] = [0.3, 0.89, 0.87, 0.1];
+/// `light_colour` of the water on a clear day, greyer than the rest of the
+/// scene's `LIGHT_COLOUR`.
+const WATER_LIGHT_COLOUR: [f32; 4] = [0.8, 0.8, 0.8, 1.0];
+
+/// What the water shows of the rest of the scene, drawn each frame.
+#[derive(Clone, Copy, Debug)]
+pub struct ReflectionTextures {
+    /// Colour of the scene mirrored in still water.
+    pub reflection: GLuint,
+    /// Colour and depth of the scene under still water.
+    pub refraction: GLuint,
+    pub refraction_depth: GLuint,
+}
+
 pub struct Water {
     points: Vec<[f32; 4]>,
     indices: Vec<u16>,
     colours: Vec<[f32; 4]>,
+    /// How far from the middle the water reaches.
     radius: f32,
+    shape: WaterShape,
     pub light: Vector3<f32>,
     transform: Transform,
-    pub time: f32
+    pub time: f32,
+    pub waves: Waves,
+    pub ripples: Ripples,
+    /// Used from the next `reseed`.
+    pub noise_settings: NoiseSettings,
+    /// Noise still being made, the shader keeps what it has until then.
+    noise_job: Option<NoiseJob>,
+    /// Noise waiting to be handed to the shader on the next draw.
+    pending_noise: Option<(Vec<u8>, usize)>,
+    /// The water is plainly shaded without these, and has no depth colour
+    /// or foam.
+    pub reflection_textures: Option<ReflectionTextures>,
+    /// Tint of water a little deep, tending to `deep_colour` the deeper it
+    /// gets.
+    pub shallow_colour: [f32; 3],
+    pub deep_colour: [f32; 3],
+    /// Water shallower than this foams against whatever is in it.
+    pub foam_depth: f32,
+    pub atmosphere: Atmosphere,
+    /// Set for a mesh that's finest around the camera's focus, see
+    /// `set_focus`.
+    lod: Option<LodSettings>,
+    /// Mesh units the LOD mesh is centred on.
+    lod_centre: [f32; 2],
 }
 
 impl Water {
-    pub fn create(width: usize, full_radius: f32) -> Self {
-        let width = (width + (width % 3)) as isize;
-        if width == 0 {
-            panic!("Creating empty water mesh!");
+    pub fn create(shape: WaterShape, resolution: WaterResolution) -> Result<Self, String> {
+        shape.validate()?;
+        let (points, indices) = match resolution {
+            WaterResolution::Uniform { spacing } => Self::lattice(&shape, spacing)?,
+            WaterResolution::Lod(settings) => settings.mesh([0.0; 2], |x, z| shape.contains(x, z))?,
+        };
+        if indices.is_empty() {
+            return Err(format!("{:?} water has no triangles at {:?}", shape, resolution));
+        }
+        let mut water = Self::from_mesh(points, indices, shape);
+        if let WaterResolution::Lod(settings) = resolution {
+            water.lod = Some(settings);
+        }
+        Ok(water)
+    }
+
+    /// Fans of six triangles around every third vertex of a lattice, which
+    /// tile into hexagons as long as the rows don't start on a fan. They're
+    /// wound so their normals point up, see `facing`.
+    fn lattice(shape: &WaterShape, spacing: f32) -> Result<(Vec<[f32; 4]>, Vec<u16>), String> {
+        const X_SCL: f32 = ((SQRT_2 * 2.0) - 2.0) / SQRT_2;
+        if !(spacing > 0.0) {
+            return Err(format!("Water lattice spacing should be positive, not {}", spacing));
+        }
+        let mut width = 2 * (shape.bounding_radius() / (spacing * X_SCL)).ceil() as isize + 2;
+        if width % 3 == 0 {
+            width += 1;
         }
         let mut value_map = HashMap::<(isize, isize), u16>::new();
         let mut points = Vec::<[f32; 4]>::new();
         let (sin, cos) = (PI / 4.0).sin_cos();
         for i_idx in 0..width {
             let i = i_idx as f32 - (width as f32 / 2.0) + 0.5;
-                for j_idx in 0..width {
+            for j_idx in 0..width {
                 let j = j_idx as f32 - (width as f32 / 2.0) + 0.5;
-
-                const X_SCL: f32 = ((SQRT_2 * 2.0) - 2.0) / SQRT_2;
-
-                let point = [X_SCL * (i * cos - j * sin), (i * sin + j * cos)];
-                let dist = (point[0] * point[0] + point[1] * point[1]).sqrt();
-                if dist < full_radius {
-                    value_map.insert((i_idx as isize, j_idx as isize), points.len() as u16);
+                let point = [spacing * X_SCL * (i * cos - j * sin), spacing * (i * sin + j * cos)];
+                if shape.contains(point[0], point[1]) {
+                    if points.len() > u16::max_value() as usize {
+                        return Err(format!("Water mesh has more than {} vertices", u16::max_value() as usize + 1));
+                    }
+                    value_map.insert((i_idx, j_idx), points.len() as u16);
                     points.push([point[0], 0.0, point[1], 1.0]);
                 }
             }
@@ -52,38 +124,131 @@ impl Water {
                         let f = value_map.get(&(i - 1, j)).cloned();
                         let g = value_map.get(&(i, j - 1)).cloned();
                         if let (Some(g), Some(b)) = (g, b) {
-                            indices.extend_from_slice(&[a, g, b]);
+                            indices.extend_from_slice(&[a, b, g]);
                         }
                         if let (Some(b), Some(c)) = (b, c) {
-                            indices.extend_from_slice(&[a, b, c]);
+                            indices.extend_from_slice(&[a, c, b]);
                         }
                         if let (Some(c), Some(d)) = (c, d) {
-                            indices.extend_from_slice(&[a, c, d]);
+                            indices.extend_from_slice(&[a, d, c]);
                         }
                         if let (Some(d), Some(e)) = (d, e) {
-                            indices.extend_from_slice(&[a, d, e]);
+                            indices.extend_from_slice(&[a, e, d]);
                         }
                         if let (Some(e), Some(f)) = (e, f) {
-                            indices.extend_from_slice(&[a, e, f]);
+                            indices.extend_from_slice(&[a, f, e]);
                         }
                         if let (Some(f), Some(g)) = (f, g) {
-                            indices.extend_from_slice(&[a, f, g]);
+                            indices.extend_from_slice(&[a, g, f]);
                         }
                     }
                 }
             }
         }
+        Ok((points, indices))
+    }
+
+    fn from_mesh(points: Vec<[f32; 4]>, indices: Vec<u16>, shape: WaterShape) -> Self {
+        let full_radius = shape.bounding_radius();
         let len = points.len();
         let mut transform = Transform::identity();
-        transform.scale(2.0);
+        transform.scale(MESH_SCALE);
         Self {
             points,
             indices,
             radius: full_radius,
-            colours: vec![[0.3, 0.89, 0.87, 0.1]; len],
+            shape,
+            colours: vec![COLOUR; len],
             light: Vector3::zero(),
             time: 0.0,
+            waves: Waves::default(),
+            ripples: Ripples::new(full_radius * MESH_SCALE, RIPPLE_RESOLUTION),
             transform,
+            noise_settings: NoiseSettings::from_env(),
+            noise_job: None,
+            pending_noise: None,
+            reflection_textures: None,
+            shallow_colour: [0.3, 0.89, 0.87],
+            deep_colour: [0.04, 0.22, 0.35],
+            foam_depth: 0.35,
+            atmosphere: Atmosphere::default(),
+            lod: None,
+            lod_centre: [0.0; 2],
+        }
+    }
+
+    /// Recentres a LOD mesh on the world space point `(x, z)`, as close as
+    /// it can while staying over the water.
+    pub fn set_focus(&mut self, x: f32, z: f32) {
+        let settings = match self.lod {
+            Some(settings) => settings,
+            None => return,
+        };
+        let (mut x, mut z) = (x / MESH_SCALE, z / MESH_SCALE);
+        let distance = (x * x + z * z).sqrt();
+        if distance > self.radius {
+            x *= self.radius / distance;
+            z *= self.radius / distance;
+        }
+        let centre = settings.snap([x, z]);
+        if centre == self.lod_centre {
+            return;
+        }
+        let shape = &self.shape;
+        match settings.mesh(centre, |x, z| shape.contains(x, z)) {
+            Ok((points, indices)) => {
+                self.colours = vec![COLOUR; points.len()];
+                self.points = points;
+                self.indices = indices;
+                self.lod_centre = centre;
+            },
+            Err(e) => println!("Could not rebuild the water mesh: {}", e),
+        }
+    }
+
+    /// World space height of the surface above `(x, z)` at `time`. The noise
+    /// is left out, so this is within `NOISE_HEIGHT` of what's drawn.
+    pub fn height_at(&self, x: f32, z: f32, time: f32) -> f32 {
+        self.waves.height_at(x, z, time) + self.ripples.height_at(x, z)
+    }
+    /// Whether the world space point `(x, z)` is over the water.
+    pub fn contains(&self, x: f32, z: f32) -> bool {
+        self.shape.contains(x / MESH_SCALE, z / MESH_SCALE)
+    }
+    /// World space unit normal of the surface above `(x, z)` at `time`.
+    pub fn normal_at(&self, x: f32, z: f32, time: f32) -> Vector3<f32> {
+        self.waves.normal_at(x, z, time).into()
+    }
+    pub fn reseed(&mut self, seed: WorldSeed) {
+        self.noise_job = Some(NoiseJob::spawn(seed.derive_u32(SeedPurpose::WaterNoise), self.noise_settings));
+        self.pending_noise = None;
+    }
+    /// Blocks until the noise from the last `reseed` is ready, for renders
+    /// that can't show the placeholder.
+    pub fn wait_for_noise(&mut self) {
+        if let Some(job) = self.noise_job.take() {
+            let size = job.size();
+            match job.wait() {
+                Ok(noise) => self.pending_noise = Some((noise, size)),
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+    fn poll_noise(&mut self) {
+        let ready = match &self.noise_job {
+            Some(job) => job.try_take().map(|noise| noise.map(|noise| (noise, job.size()))),
+            None => return,
+        };
+        match ready {
+            Ok(Some(noise)) => {
+                self.pending_noise = Some(noise);
+                self.noise_job = None;
+            },
+            Ok(None) => {},
+            Err(e) => {
+                println!("{}", e);
+                self.noise_job = None;
+            },
         }
     }
 }
@@ -98,6 +263,33 @@ impl Drawable for Water {
         cache: &mut ViewProj,
         transforms: &mut Transforms
     ) {
+        self.poll_noise();
+        if let Some((noise, size)) = self.pending_noise.take() {
+            match Texture3D::from_data(&noise, [size; 3]) {
+                Ok(texture) => data.perlin = texture,
+                Err(e) => println!("Could not upload water noise: {}", e),
+            }
+        }
+        let resolution = self.ripples.resolution();
+        let ripple_texels = self.ripples.texels();
+        if data.ripples.size() == [resolution, resolution, 1] {
+            if let Err(e) = data.ripples.update_with(&ripple_texels) {
+                println!("Could not upload ripples: {}", e);
+            }
+        } else {
+            match Texture3D::builder().wrap(Wrap::ClampToEdge).build(&ripple_texels, [resolution, resolution, 1]) {
+                Ok(texture) => data.ripples = texture,
+                Err(e) => println!("Could not upload ripples: {}", e),
+            }
+        }
+        let textures = self.reflection_textures.unwrap_or(ReflectionTextures {
+            reflection: 0,
+            refraction: 0,
+            refraction_depth: 0,
+        });
+        data.reflection = textures.reflection;
+        data.refraction = textures.refraction;
+        data.refraction_depth = textures.refraction_depth;
         let lock = transforms.push_transform(self.transform.clone());
         let time = self.time * 0.05;
         let scaler = {
@@ -124,8 +316,79 @@ impl Drawable for Water {
                 shader.projection_matrix_uni.set(gl, cache.projection_ref());
                 shader.eye_uni.set(gl, cache.eye().as_ref());
                 shader.scaler_matrix_uni.set(gl, scaler.as_ref());
-                shader.light_colour_uni.set(gl, &[0.8, 0.8, 0.8, 1.0]);
+                shader.light_colour_uni.set(gl, &self.atmosphere.light_colour(WATER_LIGHT_COLOUR));
+                let waves = self.waves.waves();
+                for (i, (shape, motion)) in shader.wave_shape_unis.iter().zip(&shader.wave_motion_unis).enumerate() {
+                    match waves.get(i) {
+                        Some(wave) => {
+                            shape.set(gl, &wave.shape());
+                            motion.set(gl, &wave.motion());
+                        },
+                        None => {
+                            shape.set(gl, &[1.0, 0.0, 0.0, 1.0]);
+                            motion.set(gl, &[0.0; 4]);
+                        },
+                    }
+                }
+                shader.wave_count_uni.set(gl, waves.len() as f32);
+                shader.wave_time_uni.set(gl, self.time);
+                shader.ripple_extent_uni.set(gl, self.ripples.extent());
+                shader.ripple_range_uni.set(gl, RIPPLE_RANGE);
+                shader.reflections_uni.set(gl, if self.reflection_textures.is_some() { 1.0 } else { 0.0 });
+                shader.shallow_colour_uni.set(gl, &self.shallow_colour);
+                shader.deep_colour_uni.set(gl, &self.deep_colour);
+                shader.foam_depth_uni.set(gl, self.foam_depth);
+                shader.fog_colour_uni.set(gl, &self.atmosphere.fog_colour());
+                shader.fog_density_uni.set(gl, self.atmosphere.fog_density);
             }
         );
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::drawable::facing;
+
+    fn error(shape: WaterShape, resolution: WaterResolution) -> String {
+        match Water::create(shape, resolution) {
+            Ok(_) => panic!("Expected the water to be rejected"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn lattice_faces_up() {
+        for shape in vec![WaterShape::Circle { radius: 3.0 }, WaterShape::Rectangle { half_width: 2.0, half_depth: 1.0 }] {
+            let (points, indices) = Water::lattice(&shape, 1.0).unwrap();
+            for t in indices.chunks(3) {
+                let corner = |i: u16| [points[i as usize][0], points[i as usize][2]];
+                assert!(facing([corner(t[0]), corner(t[1]), corner(t[2])]) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn lattice_counts() {
+        let count = |shape: WaterShape| {
+            let (points, indices) = Water::lattice(&shape, 1.0).unwrap();
+            (points.len(), indices.len() / 3)
+        };
+        assert_eq!(count(WaterShape::Circle { radius: 3.0 }), (48, 72));
+        assert_eq!(count(WaterShape::Rectangle { half_width: 2.0, half_depth: 1.0 }), (14, 20));
+    }
+
+    #[test]
+    fn create_rejects_bad_water() {
+        let uniform = WaterResolution::Uniform { spacing: 1.0 };
+        assert!(error(WaterShape::Polygon(vec![[0.0, 0.0], [1.0, 0.0]]), uniform).contains("3 corners"));
+        // Three corners in a line have nothing inside them.
+        let flat = WaterShape::Polygon(vec![[-1.0, 0.0], [0.0, 0.0], [1.0, 0.0]]);
+        assert!(error(flat, uniform).contains("no triangles"));
+        let circle = WaterShape::Circle { radius: 3.0 };
+        assert!(error(circle.clone(), WaterResolution::Uniform { spacing: 0.0 }).contains("spacing"));
+        assert!(error(circle.clone(), WaterResolution::Uniform { spacing: -1.0 }).contains("spacing"));
+        assert!(error(circle, WaterResolution::Lod(LodSettings { levels: 0, ..LodSettings::default() })).contains("levels"));
+        assert!(error(WaterShape::Circle { radius: 0.0 }, uniform).contains("radius"));
+    }
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+
+/// How a level-of-detail water mesh is laid out: square rings of cells
+/// around a centre, each ring's cells twice the size of the ring inside it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct LodSettings {
+    /// Rings, counting the solid square in the middle.
+    pub levels: usize,
+    /// Side of the cells in the middle, in mesh units.
+    pub cell_size: f32,
+    /// Cells from the centre to the outside of each ring, in that ring's
+    /// cells. Must be even so the hole in each ring lines up with the
+    /// level inside it.
+    pub ring_cells: usize,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        // Covers 32 units either side, enough for the default water from
+        // anywhere over it.
+        Self {
+            levels: 5,
+            cell_size: 0.25,
+            ring_cells: 8,
+        }
+    }
+}
+
+impl LodSettings {
+    fn validate(&self) -> Result<(), String> {
+        if self.levels == 0 || self.levels > 16 {
+            return Err(format!("Water LOD needs between 1 and 16 levels, not {}", self.levels));
+        }
+        if !(self.cell_size > 0.0) {
+            return Err(format!("Water LOD cells should have a positive size, not {}", self.cell_size));
+        }
+        if self.ring_cells < 2 || self.ring_cells % 2 != 0 {
+            return Err(format!("Water LOD rings need an even number of cells, not {}", self.ring_cells));
+        }
+        Ok(())
+    }
+
+    /// Size of the cells in the outermost ring.
+    fn coarsest_cell(&self) -> f32 {
+        self.cell_size * (1 << (self.levels - 1)) as f32
+    }
+
+    /// `focus` rounded to the outermost ring's cells. Centring the mesh
+    /// there keeps every level's vertices on the same spots as it moves, so
+    /// the surface doesn't swim.
+    pub fn snap(&self, focus: [f32; 2]) -> [f32; 2] {
+        let step = self.coarsest_cell();
+        [(focus[0] / step).round() * step, (focus[1] / step).round() * step]
+    }
+
+    /// Triangles around `centre`, leaving out any with a corner `inside`
+    /// rejects. Where a ring meets the finer one inside it, its cells are
+    /// split to the finer vertices on their shared edge, so there are no
+    /// T-junctions for cracks to open at.
+    pub fn mesh<F: Fn(f32, f32) -> bool>(&self, centre: [f32; 2], inside: F) -> Result<(Vec<[f32; 4]>, Vec<u16>), String> {
+        self.validate()?;
+        let mut builder = MeshBuilder {
+            centre,
+            cell_size: self.cell_size,
+            inside,
+            vertices: HashMap::new(),
+            points: Vec::new(),
+            indices: Vec::new(),
+        };
+        let half = self.ring_cells as i32;
+        for level in 0..self.levels {
+            let step = 1 << level;
+            let in_hole = |ci: i32, cj: i32| {
+                level > 0 && ci >= -half / 2 && ci < half / 2 && cj >= -half / 2 && cj < half / 2
+            };
+            for cj in -half..half {
+                for ci in -half..half {
+                    if in_hole(ci, cj) {
+                        continue;
+                    }
+                    // Corners anticlockwise, then the neighbour past the edge
+                    // leaving each corner.
+                    let (x0, z0, x1, z1) = (ci * step, cj * step, (ci + 1) * step, (cj + 1) * step);
+                    let corners = [(x0, z0), (x1, z0), (x1, z1), (x0, z1)];
+                    let neighbours = [(ci, cj - 1), (ci + 1, cj), (ci, cj + 1), (ci - 1, cj)];
+                    match neighbours.iter().position(|&(i, j)| in_hole(i, j)) {
+                        Some(edge) => {
+                            let a = corners[edge];
+                            let b = corners[(edge + 1) % 4];
+                            let c = corners[(edge + 2) % 4];
+                            let d = corners[(edge + 3) % 4];
+                            let m = ((a.0 + b.0) / 2, (a.1 + b.1) / 2);
+                            builder.triangle([m, b, c])?;
+                            builder.triangle([m, c, d])?;
+                            builder.triangle([m, d, a])?;
+                        },
+                        None => {
+                            builder.triangle([corners[0], corners[1], corners[2]])?;
+                            builder.triangle([corners[0], corners[2], corners[3]])?;
+                        },
+                    }
+                }
+            }
+        }
+        Ok((builder.points, builder.indices))
+    }
+}
+
+/// The y of the normal the geometry shader gives a triangle with these x, z
+/// corners, `(b - a) × (c - a)`, positive when it faces up.
+pub fn facing([a, b, c]: [[f32; 2]; 3]) -> f32 {
+    (b[1] - a[1]) * (c[0] - a[0]) - (b[0] - a[0]) * (c[1] - a[1])
+}
+
+struct MeshBuilder<F> {
+    centre: [f32; 2],
+    cell_size: f32,
+    inside: F,
+    /// Vertices by position in the finest cells from the centre, so each
+    /// is shared by every triangle that touches it.
+    vertices: HashMap<(i32, i32), u16>,
+    points: Vec<[f32; 4]>,
+    indices: Vec<u16>,
+}
+
+impl<F: Fn(f32, f32) -> bool> MeshBuilder<F> {
+    fn position(&self, (i, j): (i32, i32)) -> [f32; 2] {
+        [self.centre[0] + i as f32 * self.cell_size, self.centre[1] + j as f32 * self.cell_size]
+    }
+
+    fn vertex(&mut self, key: (i32, i32)) -> Result<u16, String> {
+        if let Some(&index) = self.vertices.get(&key) {
+            return Ok(index);
+        }
+        if self.points.len() > u16::max_value() as usize {
+            return Err(format!("Water mesh has more than {} vertices", u16::max_value() as usize + 1));
+        }
+        let [x, z] = self.position(key);
+        let index = self.points.len() as u16;
+        self.points.push([x, 0.0, z, 1.0]);
+        self.vertices.insert(key, index);
+        Ok(index)
+    }
+
+    fn triangle(&mut self, keys: [(i32, i32); 3]) -> Result<(), String> {
+        let positions = [self.position(keys[0]), self.position(keys[1]), self.position(keys[2])];
+        if !positions.iter().all(|&[x, z]| (self.inside)(x, z)) {
+            return Ok(());
+        }
+        // Wound like `Water::create`'s lattice, so the geometry shader's face
+        // normals all point up.
+        let keys = if facing(positions) < 0.0 { [keys[0], keys[2], keys[1]] } else { keys };
+        for &key in &keys {
+            let index = self.vertex(key)?;
+            self.indices.push(index);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    fn corners(points: &[[f32; 4]], triangle: &[u16]) -> [[f32; 2]; 3] {
+        let corner = |i: u16| [points[i as usize][0], points[i as usize][2]];
+        [corner(triangle[0]), corner(triangle[1]), corner(triangle[2])]
+    }
+
+    #[test]
+    fn every_triangle_faces_up() {
+        let (points, indices) = LodSettings::default().mesh([0.5, -1.0], |x, z| x * x + z * z < 100.0).unwrap();
+        assert!(!indices.is_empty());
+        for triangle in indices.chunks(3) {
+            assert!(facing(corners(&points, triangle)) > 0.0, "{:?} faces down", corners(&points, triangle));
+        }
+    }
+
+    #[test]
+    fn counts_for_two_levels() {
+        let settings = LodSettings { levels: 2, cell_size: 1.0, ring_cells: 2 };
+        let (points, indices) = settings.mesh([0.0; 2], |_, _| true).unwrap();
+        // A 5x5 grid in the middle, and 16 more corners around it. The
+        // middle has 16 cells of two triangles. The ring has 12 cells: the 8
+        // touching the middle are split in three, the corners in two.
+        assert_eq!(points.len(), 25 + 16);
+        assert_eq!(indices.len() / 3, 32 + 8 * 3 + 4 * 2);
+    }
+
+    #[test]
+    fn rings_meet_without_cracks() {
+        let settings = LodSettings { levels: 4, cell_size: 0.5, ring_cells: 4 };
+        let (points, indices) = settings.mesh([0.0; 2], |_, _| true).unwrap();
+        let edges = indices
+            .chunks(3)
+            .flat_map(|t| vec![(t[0], t[1]), (t[1], t[2]), (t[2], t[0])])
+            .collect::<HashSet<_>>();
+        // Every edge inside is shared with a triangle wound the same way, so
+        // runs the other way there. Only the outside edge is left.
+        let outside = settings.ring_cells as f32 * settings.coarsest_cell();
+        for &(a, b) in &edges {
+            if !edges.contains(&(b, a)) {
+                let (a, b) = (points[a as usize], points[b as usize]);
+                let on_outside = |i: usize| a[i].abs() == outside && b[i].abs() == outside && a[i] == b[i];
+                assert!(on_outside(0) || on_outside(2), "Unmatched edge from {:?} to {:?}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_settings_are_rejected() {
+        let mesh = |settings: LodSettings| settings.mesh([0.0; 2], |_, _| true);
+        assert!(mesh(LodSettings { levels: 0, ..LodSettings::default() }).is_err());
+        assert!(mesh(LodSettings { cell_size: 0.0, ..LodSettings::default() }).is_err());
+        assert!(mesh(LodSettings { ring_cells: 3, ..LodSettings::default() }).is_err());
+    }
+}
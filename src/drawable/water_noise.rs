@@ -0,0 +1,265 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::thread;
+use rand::SeedableRng;
+use rand::seq::SliceRandom;
+use rand_pcg::Pcg32;
+
+/// Edge length of the cube of noise the water's surface moves through.
+pub const NOISE_SIZE: usize = 100;
+
+/// How the water's noise volume is made.
+///
+/// The noise repeats every `size` texels along each axis, so the texture can
+/// wrap with `REPEAT` and no seams. `frequency` is how many noise cells fit
+/// in one repeat; being whole is what makes the noise periodic.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoiseSettings {
+    pub size: usize,
+    pub frequency: u32,
+}
+
+impl Default for NoiseSettings {
+    fn default() -> Self {
+        Self {
+            size: NOISE_SIZE,
+            frequency: 50,
+        }
+    }
+}
+
+impl NoiseSettings {
+    /// The defaults, overridden by `TREES_WATER_NOISE_SIZE` and
+    /// `TREES_WATER_NOISE_FREQUENCY` where they're set and valid.
+    pub fn from_env() -> Self {
+        let mut settings = Self::default();
+        let read = |name: &str| std::env::var(name).ok().and_then(|v| v.trim().parse::<u32>().ok()).filter(|&v| v > 0);
+        if let Some(size) = read("TREES_WATER_NOISE_SIZE") {
+            settings.size = size as usize;
+        }
+        if let Some(frequency) = read("TREES_WATER_NOISE_FREQUENCY") {
+            settings.frequency = frequency;
+        }
+        settings
+    }
+}
+
+/// Gradient (Perlin) noise whose lattice wraps every `period` cells.
+struct PeriodicNoise {
+    permutation: [u8; 256],
+    period: i64,
+}
+
+impl PeriodicNoise {
+    fn new(seed: u32, period: u32) -> Self {
+        let mut permutation = [0u8; 256];
+        for (i, p) in permutation.iter_mut().enumerate() {
+            *p = i as u8;
+        }
+        permutation.shuffle(&mut Pcg32::seed_from_u64(seed as u64));
+        Self {
+            permutation,
+            period: period.max(1) as i64,
+        }
+    }
+
+    fn hash(&self, x: i64, y: i64, z: i64) -> u8 {
+        let wrap = |v: i64| (v.rem_euclid(self.period) & 255) as usize;
+        let p = &self.permutation;
+        p[(p[(p[wrap(x)] as usize + wrap(y)) & 255] as usize + wrap(z)) & 255]
+    }
+
+    /// One of the 12 edge gradients of a cube, dotted with the offset.
+    fn gradient(hash: u8, x: f64, y: f64, z: f64) -> f64 {
+        match hash % 12 {
+            0 => x + y,
+            1 => -x + y,
+            2 => x - y,
+            3 => -x - y,
+            4 => x + z,
+            5 => -x + z,
+            6 => x - z,
+            7 => -x - z,
+            8 => y + z,
+            9 => -y + z,
+            10 => y - z,
+            _ => -y - z,
+        }
+    }
+
+    /// Roughly -1 to 1, at lattice coordinates.
+    fn get(&self, [x, y, z]: [f64; 3]) -> f64 {
+        let (x0, y0, z0) = (x.floor(), y.floor(), z.floor());
+        let (fx, fy, fz) = (x - x0, y - y0, z - z0);
+        let (ix, iy, iz) = (x0 as i64, y0 as i64, z0 as i64);
+        let fade = |t: f64| t * t * t * (t * (t * 6.0 - 15.0) + 10.0);
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let corner = |dx: i64, dy: i64, dz: i64| Self::gradient(
+            self.hash(ix + dx, iy + dy, iz + dz),
+            fx - dx as f64,
+            fy - dy as f64,
+            fz - dz as f64,
+        );
+        let (u, v, w) = (fade(fx), fade(fy), fade(fz));
+        lerp(
+            lerp(lerp(corner(0, 0, 0), corner(1, 0, 0), u), lerp(corner(0, 1, 0), corner(1, 1, 0), u), v),
+            lerp(lerp(corner(0, 0, 1), corner(1, 0, 1), u), lerp(corner(0, 1, 1), corner(1, 1, 1), u), v),
+            w,
+        )
+    }
+}
+
+/// `size`³ bytes of periodic noise, x fastest then y then z.
+///
+/// Texels are sampled at their centres. Perlin noise is always zero on its
+/// lattice, so sampling at corners would leave a flat 128 wherever a texel
+/// lines up with a cell corner, which with two texels a cell is a quarter of
+/// them.
+pub fn noise_data(seed: u32, settings: NoiseSettings) -> Vec<u8> {
+    let size = settings.size;
+    let noise = PeriodicNoise::new(seed, settings.frequency);
+    let scale = settings.frequency as f64 / size as f64;
+    let mut data = vec![0u8; size * size * size];
+    for k in 0..size {
+        let k_val = (k as f64 + 0.5) * scale;
+        for j in 0..size {
+            let j_val = (j as f64 + 0.5) * scale;
+            let offset = size * j + size * size * k;
+            for i in 0..size {
+                let i_val = (i as f64 + 0.5) * scale;
+                let value = noise.get([i_val, j_val, k_val]) * 128.0 + 128.0;
+                data[offset + i] = value.max(0.0).min(255.0) as u8;
+            }
+        }
+    }
+    data
+}
+
+/// Where a volume is cached. The version goes up whenever `noise_data` would
+/// produce something different for the same seed and size.
+fn cache_path(seed: u32, settings: NoiseSettings) -> PathBuf {
+    crate::fs::storage_dir()
+        .join("cache")
+        .join(format!("water-noise-v3-{:08x}-{}-{}.bin", seed, settings.size, settings.frequency))
+}
+
+fn load_or_generate(seed: u32, settings: NoiseSettings) -> Vec<u8> {
+    let size = settings.size;
+    let path = cache_path(seed, settings);
+    match std::fs::read(&path) {
+        Ok(data) if data.len() == size * size * size => return data,
+        Ok(_) => println!("Regenerating truncated water noise {:?}", path),
+        Err(_) => {},
+    }
+    let data = noise_data(seed, settings);
+    let written = path
+        .parent()
+        .map_or(Ok(()), std::fs::create_dir_all)
+        .and_then(|_| std::fs::write(&path, &data));
+    if let Err(e) = written {
+        println!("Could not cache water noise to {:?}: {}", path, e);
+    }
+    data
+}
+
+/// A noise volume being loaded from the cache or generated on a worker
+/// thread.
+pub struct NoiseJob {
+    size: usize,
+    receiver: Receiver<Vec<u8>>,
+}
+
+impl NoiseJob {
+    pub fn spawn(seed: u32, settings: NoiseSettings) -> Self {
+        let (sender, receiver) = channel();
+        thread::spawn(move || {
+            // The job may have been replaced by now, in which case there's
+            // nobody to tell.
+            let _ = sender.send(load_or_generate(seed, settings));
+        });
+        Self {
+            size: settings.size,
+            receiver,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// The volume if it's ready, without waiting. `Err` if the worker died
+    /// without producing one.
+    pub fn try_take(&self) -> Result<Option<Vec<u8>>, String> {
+        match self.receiver.try_recv() {
+            Ok(data) => Ok(Some(data)),
+            Err(TryRecvError::Empty) => Ok(None),
+            Err(TryRecvError::Disconnected) => Err("Water noise worker stopped".to_string()),
+        }
+    }
+
+    /// Blocks until the volume is ready.
+    pub fn wait(self) -> Result<Vec<u8>, String> {
+        self.receiver.recv().map_err(|_| "Water noise worker stopped".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SETTINGS: NoiseSettings = NoiseSettings { size: 20, frequency: 10 };
+
+    #[test]
+    fn same_seed_same_noise() {
+        assert_eq!(noise_data(7, SETTINGS), noise_data(7, SETTINGS));
+        assert_ne!(noise_data(7, SETTINGS), noise_data(8, SETTINGS));
+    }
+
+    #[test]
+    fn lattice_corners_are_not_sampled() {
+        let data = noise_data(7, SETTINGS);
+        let flat = data.iter().filter(|&&v| v == 128).count();
+        assert!(flat * 50 < data.len(), "{} of {} texels are exactly 128", flat, data.len());
+    }
+
+    #[test]
+    fn noise_repeats_every_period() {
+        let noise = PeriodicNoise::new(7, 10);
+        for &p in &[[0.3, 1.7, 4.2], [9.9, 0.01, 5.5], [-2.25, 13.0, 7.75]] {
+            for axis in 0..3 {
+                let mut q = p;
+                q[axis] += 10.0;
+                assert!((noise.get(p) - noise.get(q)).abs() < 1e-9, "{:?} along axis {}", p, axis);
+            }
+        }
+    }
+
+    #[test]
+    fn opposite_edges_meet_smoothly() {
+        let size = SETTINGS.size;
+        let data = noise_data(7, SETTINGS);
+        let at = |i: usize, j: usize, k: usize| data[i + size * j + size * size * k] as i32;
+        // Index of texel `n` along `axis`, the other two being `a` and `b`.
+        let index = |axis: usize, n: usize, a: usize, b: usize| match axis {
+            0 => (n, a, b),
+            1 => (a, n, b),
+            _ => (a, b, n),
+        };
+        for axis in 0..3 {
+            let (mut seam, mut inside) = (0, 0);
+            for a in 0..size {
+                for b in 0..size {
+                    let step = |from: usize, to: usize| {
+                        let (from, to) = (index(axis, from, a, b), index(axis, to, a, b));
+                        (at(from.0, from.1, from.2) - at(to.0, to.1, to.2)).abs()
+                    };
+                    seam = seam.max(step(size - 1, 0));
+                    for n in 1..size {
+                        inside = inside.max(step(n - 1, n));
+                    }
+                }
+            }
+            assert!(seam <= inside, "a jump of {} across the edge along axis {}, but at most {} inside", seam, axis, inside);
+        }
+    }
+}
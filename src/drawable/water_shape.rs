@@ -0,0 +1,157 @@
+use crate::drawable::LodSettings;
+
+/// Heights over a square grid centred on the origin, for water that fills
+/// the low ground.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heightfield {
+    /// Samples along each side.
+    pub size: usize,
+    /// Half the width of the grid, in mesh units.
+    pub extent: f32,
+    /// `size * size` heights, row by row along x.
+    pub heights: Vec<f32>,
+}
+
+impl Heightfield {
+    fn validate(&self) -> Result<(), String> {
+        if self.size < 2 {
+            return Err(format!("A heightfield needs at least 2 samples a side, not {}", self.size));
+        }
+        if self.heights.len() != self.size * self.size {
+            return Err(format!(
+                "A {0}x{0} heightfield needs {1} heights, not {2}",
+                self.size, self.size * self.size, self.heights.len()
+            ));
+        }
+        if !(self.extent > 0.0) {
+            return Err(format!("A heightfield should have a positive extent, not {}", self.extent));
+        }
+        Ok(())
+    }
+
+    /// Height at `(x, z)`, interpolated between samples, or `None` off the
+    /// grid.
+    pub fn height_at(&self, x: f32, z: f32) -> Option<f32> {
+        let n = self.size;
+        let cell = self.extent * 2.0 / (n - 1) as f32;
+        let (u, v) = ((x + self.extent) / cell, (z + self.extent) / cell);
+        if !(u >= 0.0 && v >= 0.0 && u <= (n - 1) as f32 && v <= (n - 1) as f32) {
+            return None;
+        }
+        let (i, j) = ((u.floor() as usize).min(n - 2), (v.floor() as usize).min(n - 2));
+        let (fu, fv) = (u - i as f32, v - j as f32);
+        let at = |i: usize, j: usize| self.heights[i + j * n];
+        let near = at(i, j) + (at(i + 1, j) - at(i, j)) * fu;
+        let far = at(i, j + 1) + (at(i + 1, j + 1) - at(i, j + 1)) * fu;
+        Some(near + (far - near) * fv)
+    }
+}
+
+/// The outline of a body of water, in mesh units around the water's origin.
+#[derive(Clone, Debug, PartialEq)]
+pub enum WaterShape {
+    Circle { radius: f32 },
+    Rectangle { half_width: f32, half_depth: f32 },
+    /// Corners of a simple polygon, in either order.
+    Polygon(Vec<[f32; 2]>),
+    /// Wherever the ground is below `sea_level`.
+    Contour { ground: Heightfield, sea_level: f32 },
+}
+
+impl WaterShape {
+    pub fn validate(&self) -> Result<(), String> {
+        match self {
+            WaterShape::Circle { radius } if !(*radius > 0.0) =>
+                Err(format!("Water circles should have a positive radius, not {}", radius)),
+            WaterShape::Rectangle { half_width, half_depth } if !(*half_width > 0.0 && *half_depth > 0.0) =>
+                Err(format!("Water rectangles should have a positive size, not {}x{}", half_width * 2.0, half_depth * 2.0)),
+            WaterShape::Polygon(corners) if corners.len() < 3 =>
+                Err(format!("Water polygons need at least 3 corners, not {}", corners.len())),
+            WaterShape::Contour { ground, .. } => ground.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn contains(&self, x: f32, z: f32) -> bool {
+        match self {
+            WaterShape::Circle { radius } => x * x + z * z < radius * radius,
+            WaterShape::Rectangle { half_width, half_depth } => x.abs() < *half_width && z.abs() < *half_depth,
+            WaterShape::Polygon(corners) => {
+                // Even-odd rule, counting edges crossed heading along +x.
+                let mut inside = false;
+                let mut previous = corners[corners.len() - 1];
+                for &corner in corners {
+                    let ([x0, z0], [x1, z1]) = (previous, corner);
+                    if (z0 > z) != (z1 > z) && x < x0 + (z - z0) / (z1 - z0) * (x1 - x0) {
+                        inside = !inside;
+                    }
+                    previous = corner;
+                }
+                inside
+            },
+            WaterShape::Contour { ground, sea_level } => ground
+                .height_at(x, z)
+                .map_or(false, |height| height < *sea_level),
+        }
+    }
+
+    /// Distance from the origin to the furthest the water could reach.
+    pub fn bounding_radius(&self) -> f32 {
+        match self {
+            WaterShape::Circle { radius } => *radius,
+            WaterShape::Rectangle { half_width, half_depth } => (half_width * half_width + half_depth * half_depth).sqrt(),
+            WaterShape::Polygon(corners) => corners
+                .iter()
+                .map(|[x, z]| (x * x + z * z).sqrt())
+                .fold(0.0, f32::max),
+            WaterShape::Contour { ground, .. } => ground.extent * std::f32::consts::SQRT_2,
+        }
+    }
+}
+
+/// How finely the water is triangulated.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WaterResolution {
+    /// An even lattice of hexagons, `spacing` times the size of the one the
+    /// water started out with.
+    Uniform { spacing: f32 },
+    /// Finest around the camera's focus, see `Water::set_focus`.
+    Lod(LodSettings),
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn polygons_contain_either_winding() {
+        let square = vec![[-1.0, -1.0], [1.0, -1.0], [1.0, 1.0], [-1.0, 1.0]];
+        let reversed = square.iter().rev().cloned().collect::<Vec<_>>();
+        for corners in vec![square, reversed] {
+            let shape = WaterShape::Polygon(corners);
+            assert!(shape.contains(0.5, -0.5));
+            assert!(!shape.contains(1.5, 0.0));
+            assert_eq!(shape.bounding_radius(), std::f32::consts::SQRT_2);
+        }
+    }
+
+    #[test]
+    fn contours_follow_the_ground() {
+        let ground = Heightfield { size: 2, extent: 1.0, heights: vec![0.0, 1.0, 0.0, 1.0] };
+        assert_eq!(ground.height_at(0.0, 0.3), Some(0.5));
+        assert_eq!(ground.height_at(1.5, 0.0), None);
+        let shape = WaterShape::Contour { ground, sea_level: 0.5 };
+        assert!(shape.contains(-0.5, 0.0));
+        assert!(!shape.contains(0.5, 0.0));
+    }
+
+    #[test]
+    fn degenerate_shapes_are_rejected() {
+        assert!(WaterShape::Circle { radius: -1.0 }.validate().is_err());
+        assert!(WaterShape::Rectangle { half_width: 1.0, half_depth: 0.0 }.validate().is_err());
+        assert!(WaterShape::Polygon(vec![[0.0, 0.0], [1.0, 1.0]]).validate().is_err());
+        let ground = Heightfield { size: 3, extent: 1.0, heights: vec![0.0; 4] };
+        assert!(WaterShape::Contour { ground, sea_level: 0.0 }.validate().is_err());
+        assert!(WaterShape::Circle { radius: 1.0 }.validate().is_ok());
+    }
+}
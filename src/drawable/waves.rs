@@ -0,0 +1,230 @@
+use std::f32::consts::PI;
+
+/// Waves the water shader can take, handed to `gerstner.glsl` as `MAX_WAVES`.
+pub const MAX_WAVES: usize = 4;
+const GRAVITY: f32 = 9.81;
+/// Rounds of working back from a displaced point to where it started, see
+/// `Waves::height_at`.
+const INVERSION_ITERATIONS: usize = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GerstnerWave {
+    /// Which way the wave travels across x and z; needn't be normalised.
+    pub direction: [f32; 2],
+    /// Height of the crests above still water.
+    pub amplitude: f32,
+    /// Distance between crests.
+    pub wavelength: f32,
+    /// 0 for rolling sine waves up to 1 for crests on the point of looping.
+    pub steepness: f32,
+    /// Offset in radians, to stop waves lining up.
+    pub phase: f32,
+}
+
+impl GerstnerWave {
+    fn wavenumber(&self) -> f32 {
+        2.0 * PI / self.wavelength
+    }
+
+    /// Phase speed on deep water.
+    fn speed(&self) -> f32 {
+        (GRAVITY / self.wavenumber()).sqrt()
+    }
+
+    fn unit_direction(&self) -> [f32; 2] {
+        let [x, z] = self.direction;
+        let length = (x * x + z * z).sqrt();
+        if length > 0.0 { [x / length, z / length] } else { [1.0, 0.0] }
+    }
+
+    /// `wave_shape` in `gerstner.glsl`.
+    pub fn shape(&self) -> [f32; 4] {
+        let [x, z] = self.unit_direction();
+        [x, z, self.amplitude, self.wavelength]
+    }
+
+    /// `wave_motion` in `gerstner.glsl`.
+    pub fn motion(&self) -> [f32; 4] {
+        [self.steepness, self.phase, 0.0, 0.0]
+    }
+}
+
+/// A sum of Gerstner waves, evaluated the same way as `gerstner.glsl` so
+/// the CPU can ask where the surface the GPU draws is.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Waves {
+    waves: Vec<GerstnerWave>,
+}
+
+impl Default for Waves {
+    fn default() -> Self {
+        Self {
+            waves: vec![
+                GerstnerWave { direction: [1.0, 0.3], amplitude: 0.15, wavelength: 6.0, steepness: 0.5, phase: 0.0 },
+                GerstnerWave { direction: [-0.4, 1.0], amplitude: 0.1, wavelength: 3.5, steepness: 0.4, phase: 1.3 },
+                GerstnerWave { direction: [0.7, -0.6], amplitude: 0.05, wavelength: 1.7, steepness: 0.3, phase: 2.9 },
+            ],
+        }
+    }
+}
+
+impl Waves {
+    pub fn new(waves: Vec<GerstnerWave>) -> Result<Self, String> {
+        if waves.len() > MAX_WAVES {
+            return Err(format!("The water shader takes at most {} waves, not {}", MAX_WAVES, waves.len()));
+        }
+        if let Some(wave) = waves.iter().find(|wave| !(wave.wavelength > 0.0)) {
+            return Err(format!("Wave wavelengths should be positive, not {}", wave.wavelength));
+        }
+        if let Some(wave) = waves.iter().find(|wave| !(0.0..=1.0).contains(&wave.steepness)) {
+            return Err(format!("Wave steepness should be between 0 and 1, not {}", wave.steepness));
+        }
+        Ok(Self { waves })
+    }
+
+    pub fn waves(&self) -> &[GerstnerWave] {
+        &self.waves
+    }
+
+    /// These waves with every amplitude multiplied by `factor`.
+    pub fn scaled(&self, factor: f32) -> Waves {
+        let mut waves = self.clone();
+        for wave in &mut waves.waves {
+            wave.amplitude *= factor;
+        }
+        waves
+    }
+
+    /// These waves turned together so the first runs with `wind`, which
+    /// blows along x and z. Still air leaves them as they are.
+    pub fn blown(&self, wind: [f32; 2]) -> Waves {
+        let mut waves = self.clone();
+        let first = match self.waves.first() {
+            Some(first) if wind != [0.0; 2] => first.unit_direction(),
+            _ => return waves,
+        };
+        let turn = wind[1].atan2(wind[0]) - first[1].atan2(first[0]);
+        let (sin, cos) = turn.sin_cos();
+        for wave in &mut waves.waves {
+            let [x, z] = wave.direction;
+            wave.direction = [x * cos - z * sin, x * sin + z * cos];
+        }
+        waves
+    }
+
+    /// Horizontal share of each wave's motion, spread so that even with every
+    /// wave at full steepness the surface can't fold over itself.
+    fn sideways(&self, wave: &GerstnerWave) -> f32 {
+        wave.steepness / (wave.wavenumber() * self.waves.len() as f32)
+    }
+
+    fn angle(wave: &GerstnerWave, [dx, dz]: [f32; 2], x: f32, z: f32, time: f32) -> f32 {
+        wave.wavenumber() * (dx * x + dz * z - wave.speed() * time) + wave.phase
+    }
+
+    /// How far the still water at `(x, z)` has moved at `time`.
+    pub fn offset(&self, x: f32, z: f32, time: f32) -> [f32; 3] {
+        let mut offset = [0.0; 3];
+        for wave in &self.waves {
+            let direction = wave.unit_direction();
+            let (sin, cos) = Self::angle(wave, direction, x, z, time).sin_cos();
+            let sideways = self.sideways(wave);
+            offset[0] += direction[0] * sideways * cos;
+            offset[1] += wave.amplitude * sin;
+            offset[2] += direction[1] * sideways * cos;
+        }
+        offset
+    }
+
+    /// Where still water started out that's now above `(x, z)`.
+    fn source(&self, x: f32, z: f32, time: f32) -> (f32, f32) {
+        let (mut source_x, mut source_z) = (x, z);
+        for _ in 0..INVERSION_ITERATIONS {
+            let [dx, _, dz] = self.offset(source_x, source_z, time);
+            source_x = x - dx;
+            source_z = z - dz;
+        }
+        (source_x, source_z)
+    }
+
+    /// Height of the surface above still water at `(x, z)`.
+    pub fn height_at(&self, x: f32, z: f32, time: f32) -> f32 {
+        let (x, z) = self.source(x, z, time);
+        self.offset(x, z, time)[1]
+    }
+
+    /// Unit normal of the surface at `(x, z)`.
+    pub fn normal_at(&self, x: f32, z: f32, time: f32) -> [f32; 3] {
+        let (x, z) = self.source(x, z, time);
+        let mut normal = [0.0, 1.0, 0.0];
+        for wave in &self.waves {
+            let direction = wave.unit_direction();
+            let k = wave.wavenumber();
+            let (sin, cos) = Self::angle(wave, direction, x, z, time).sin_cos();
+            normal[0] -= direction[0] * k * wave.amplitude * cos;
+            normal[1] -= k * self.sideways(wave) * sin;
+            normal[2] -= direction[1] * k * wave.amplitude * cos;
+        }
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        [normal[0] / length, normal[1] / length, normal[2] / length]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIME: f32 = 2.7;
+
+    fn wave(steepness: f32) -> GerstnerWave {
+        GerstnerWave { direction: [1.0, 0.0], amplitude: 0.1, wavelength: 2.0, steepness, phase: 0.0 }
+    }
+
+    #[test]
+    fn height_follows_displaced_water() {
+        let waves = Waves::default();
+        for &(x, z) in &[(0.0, 0.0), (1.3, -2.1), (-4.0, 3.7)] {
+            let [dx, dy, dz] = waves.offset(x, z, TIME);
+            let height = waves.height_at(x + dx, z + dz, TIME);
+            assert!((height - dy).abs() < 1e-3, "{} at ({}, {}) should be {}", height, x, z, dy);
+        }
+    }
+
+    #[test]
+    fn normals_are_unit_and_face_up() {
+        let waves = Waves::default();
+        for i in 0..50 {
+            let (x, z) = (i as f32 * 0.37 - 9.0, i as f32 * -0.21 + 4.0);
+            let [nx, ny, nz] = waves.normal_at(x, z, TIME);
+            assert!(((nx * nx + ny * ny + nz * nz).sqrt() - 1.0).abs() < 1e-5);
+            assert!(ny > 0.0, "{:?} at ({}, {})", [nx, ny, nz], x, z);
+        }
+        assert_eq!(Waves::new(vec![]).unwrap().normal_at(1.0, 2.0, TIME), [0.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn new_rejects_waves_the_shader_cannot_draw() {
+        assert!(Waves::new(vec![wave(0.5); MAX_WAVES]).is_ok());
+        assert!(Waves::new(vec![wave(0.5); MAX_WAVES + 1]).is_err());
+        assert!(Waves::new(vec![wave(1.0), wave(0.0)]).is_ok());
+        assert!(Waves::new(vec![wave(0.5), wave(1.5)]).is_err());
+        assert!(Waves::new(vec![wave(-0.1)]).is_err());
+        assert!(Waves::new(vec![GerstnerWave { wavelength: 0.0, ..wave(0.5) }]).is_err());
+    }
+
+    #[test]
+    fn waves_turn_together_with_the_wind() {
+        let calm = Waves::default();
+        assert_eq!(calm.blown([0.0; 2]), calm);
+        let blown = calm.blown([0.0, -3.0]);
+        let first = blown.waves[0].unit_direction();
+        assert!(first[0].abs() < 1e-5 && (first[1] + 1.0).abs() < 1e-5, "{:?}", first);
+        // Every pair keeps the angle between them.
+        let dot = |a: [f32; 2], b: [f32; 2]| a[0] * b[0] + a[1] * b[1];
+        for (before, after) in calm.waves.iter().zip(&blown.waves).skip(1) {
+            let (a, b) = (dot(calm.waves[0].direction, before.direction), dot(blown.waves[0].direction, after.direction));
+            assert!((a - b).abs() < 1e-5);
+            assert_eq!(before.amplitude, after.amplitude);
+        }
+    }
+}
@@ -1,52 +1,324 @@
 use android_base::{Drawable, ViewProj, Transforms, ShaderContext};
 use opengl_graphics::GlGraphics;
 use graphics::Context;
-use crate::drawable::{Water, Cube, Axis};
+use crate::drawable::{Water, Cube, Axis, Floaters, Particles, Forest, Waves, ReflectionTextures, LodSettings, WaterShape, WaterResolution};
 use std::sync::{Arc, Mutex};
-use cgmath::Vector3;
+use cgmath::{Vector3, Matrix4};
 use crate::controls::Camera;
+use crate::textures::TextureManager;
+use crate::seed::WorldSeed;
+use crate::offscreen::RenderTarget;
+use crate::shader::NO_CLIP;
+use crate::weather::{Weather, WeatherKind};
+use crate::seasons::{Calendar, Season, Species};
+use crate::ecology::{Ecology, Soil, ForestStats};
+
+/// How far the camera is kept above the waves.
+const CAMERA_CLEARANCE: f32 = 0.3;
+/// How deep and wide a tap pushes the water.
+const TAP_STRENGTH: f32 = 0.3;
+const TAP_RADIUS: f32 = 0.8;
+/// How far from the middle floating things are dropped, in world units.
+const FLOAT_RADIUS: f32 = 10.0;
+/// How far either side of the middle trees can grow, and how finely the
+/// soil's moisture is worked out over that.
+const FOREST_EXTENT: f32 = 20.0;
+const SOIL_CELLS: usize = 80;
+/// Land this far from the water is about a third as damp as the shore.
+const MOISTURE_REACH: f32 = 4.0;
+/// Reflections and refractions are drawn at this fraction of the window's
+/// size, the noise blurs them anyway.
+const REFLECTION_SCALE: usize = 2;
 
 pub struct World {
     water: Water,
     cube: Cube,
+    floaters: Floaters,
+    /// `None` if the trees couldn't be planted.
+    forest: Option<Forest>,
+    particles: Particles,
+    weather: Weather,
+    calendar: Calendar,
+    /// Season as of the last step, to notice it turning.
+    season: Season,
+    /// Waves on a clear day, heightened by rougher weather and turned to
+    /// run with the wind.
+    calm_waves: Waves,
     axis: Axis,
     light: Vector3<f32>,
-    time: f32,
-    camera: Arc<Mutex<Camera>>
+    seed: WorldSeed,
+    /// Simulated time, moved on only by `step`.
+    time: f64,
+    camera: Arc<Mutex<Camera>>,
+    /// Projection to switch to on the next draw, for when rendering to a
+    /// target with a different aspect ratio to the window.
+    projection: Option<Matrix4<f32>>,
+    /// Targets the scene is drawn into mirrored in the water and from under
+    /// it, `None` if they couldn't be made.
+    reflections: Option<(RenderTarget, RenderTarget)>,
+}
+
+/// Weather for `seed`, starting out as `TREES_WEATHER` says if it's set.
+fn weather(seed: WorldSeed) -> Weather {
+    let mut weather = Weather::new(seed);
+    if let Ok(kind) = std::env::var("TREES_WEATHER") {
+        match kind.parse::<WeatherKind>() {
+            Ok(kind) => weather.set_kind(kind),
+            Err(e) => println!("Ignoring TREES_WEATHER: {}", e),
+        }
+    }
+    weather
+}
+
+/// The calendar `TREES_YEAR_LENGTH` and `TREES_YEAR_START` describe, see
+/// `Calendar::from_env`.
+fn calendar() -> Calendar {
+    Calendar::from_env().unwrap_or_else(|e| {
+        println!("Ignoring calendar settings: {}", e);
+        Calendar::default()
+    })
+}
+
+/// Switches the view partway through drawing the world's children.
+struct SetView(Matrix4<f32>);
+
+impl Drawable for SetView {
+    type Shader = ();
+    fn draw_with(&mut self, _shader: &mut (), _graphics: &mut GlGraphics, _context: &Context, cache: &mut ViewProj, _transforms: &mut Transforms) {
+        cache.view = self.0;
+    }
+    fn draw_children(&mut self, _context: &mut ShaderContext) {}
 }
 
 impl World {
-    pub fn new(cam: Arc<Mutex<Camera>>) -> Self {
+    pub fn new(cam: Arc<Mutex<Camera>>, textures: &mut TextureManager, seed: WorldSeed) -> Self {
+        let cube = Cube::new(textures).unwrap_or_else(|e| {
+            println!("Falling back to a plain cube: {}", e);
+            Cube::plain(textures)
+        });
+        let water = Water::create(WaterShape::Circle { radius: 8.0 }, WaterResolution::Lod(LodSettings::default()))
+            .or_else(|e| {
+                println!("Falling back to uniform water: {}", e);
+                Water::create(WaterShape::Circle { radius: 8.0 }, WaterResolution::Uniform { spacing: 1.0 })
+            })
+            .expect("Could not create the water");
+        let forest = match Soil::new(FOREST_EXTENT, SOIL_CELLS, MOISTURE_REACH, |x, z| water.contains(x, z))
+            .and_then(|soil| Ecology::new(seed, vec![Species::oak(), Species::birch(), Species::pine()], soil))
+        {
+            Ok(ecology) => Some(Forest::new(ecology, textures)),
+            Err(e) => {
+                println!("Growing no trees: {}", e);
+                None
+            },
+        };
+        let calendar = calendar();
         let mut this = Self {
             light: Vector3 { x: 0.0, y: 10.0, z: 0.0 },
-            cube: Cube::new(),
-            water: Water::create(70, 8.0),
+            cube,
+            floaters: Floaters::new(textures),
+            forest,
+            particles: Particles::new(seed, textures),
+            weather: weather(seed),
+            calendar,
+            season: calendar.season(0.0),
+            calm_waves: Waves::default(),
+            water,
             axis: Axis::new([0.0; 3]),
+            seed,
             time: 0.0,
-            camera: cam
+            camera: cam,
+            projection: None,
+            reflections: None,
         };
         this.cube.light = this.light;
+        this.floaters.light = this.light;
+        if let Some(forest) = &mut this.forest {
+            forest.light = this.light;
+        }
         this.water.light = this.light;
         this.axis.set_light(this.light.into());
+        this.water.reseed(seed);
+        this.floaters.scatter(seed, FLOAT_RADIUS);
         this
     }
-    pub fn update(&mut self, new_time: f32) {
-        self.time = new_time;
-        self.water.time = new_time;
+    pub fn seed(&self) -> WorldSeed {
+        self.seed
+    }
+    /// Rebuilds everything procedural from `seed`, starting over from time
+    /// zero.
+    pub fn regenerate(&mut self, seed: WorldSeed) {
+        self.seed = seed;
+        self.time = 0.0;
+        self.calendar = calendar();
+        self.season = self.calendar.season(0.0);
+        self.water.reseed(seed);
+        self.water.ripples.clear();
+        self.floaters.scatter(seed, FLOAT_RADIUS);
+        self.particles.reseed(seed);
+        self.weather = weather(seed);
+        if let Some(forest) = &mut self.forest {
+            forest.ecology.reseed(seed);
+        }
+    }
+    /// Blocks until everything generated in the background is ready.
+    pub fn wait_for_generation(&mut self) {
+        self.water.wait_for_noise();
+    }
+    /// Simulates one fixed step of `dt` seconds.
+    pub fn step(&mut self, dt: f64) {
+        self.time += dt;
+        self.weather.step(dt, self.calendar.season(self.time));
+        let conditions = self.weather.conditions();
+        let wind = Vector3::new(conditions.wind[0], 0.0, conditions.wind[1]);
+        self.particles.wind = wind;
+        self.particles.rain.emitter.rate = conditions.rain;
+        self.particles.snow.emitter.rate = conditions.snow;
+        self.water.waves = self.calm_waves.scaled(conditions.roughness).blown(conditions.wind);
+        let (light, sky) = self.calendar.tint(self.time);
+        let atmosphere = conditions.atmosphere.tinted(light, sky);
+        self.water.atmosphere = atmosphere;
+        self.cube.atmosphere = atmosphere;
+        self.floaters.atmosphere = atmosphere;
+        if let Some(forest) = &mut self.forest {
+            forest.atmosphere = atmosphere;
+            forest.time_of_year = self.calendar.time_of_year(self.time);
+            forest.time = self.time as f32;
+            forest.wind = conditions.wind;
+            forest.ecology.step(dt, &self.calendar, self.time);
+            self.particles.leaves.sources = forest.leaf_sources();
+        }
+        let season = self.season();
+        if season != self.season {
+            self.season = season;
+            let year = self.calendar.year(self.time);
+            match self.forest_stats() {
+                Some(stats) => println!("{:?} in year {}: {}", self.season, year, stats),
+                None => println!("{:?} in year {}", self.season, year),
+            }
+        }
+
+        self.water.ripples.step(dt as f32);
+        self.floaters.step(dt as f32, &self.water, self.time as f32, wind);
+        for splash in self.particles.step(dt as f32, &self.water, self.time as f32) {
+            self.water.ripples.impulse(splash.x, splash.z, splash.strength, splash.radius);
+        }
+    }
+    /// Ripples the water where a tap at `position` on a `size` window lands.
+    pub fn tap(&mut self, position: [f64; 2], size: (usize, usize), projection: Matrix4<f32>) {
+        let ray = self.camera.lock().unwrap().screen_ray(position, size, projection);
+        if let Some((origin, direction)) = ray {
+            // Still water is the y = 0 plane.
+            if direction.y < 0.0 {
+                let distance = -origin.y / direction.y;
+                let hit = origin + direction * distance;
+                self.water.ripples.impulse(hit.x, hit.z, TAP_STRENGTH, TAP_RADIUS);
+            }
+        }
+    }
+    /// Takes however many steps of `dt` land closest to `time`.
+    pub fn advance_to(&mut self, time: f64, dt: f64) {
+        let steps = ((time - self.time) / dt).round().max(0.0) as u64;
+        for _ in 0..steps {
+            self.step(dt);
+        }
+    }
+    /// Sets the time things are drawn at, which may fall between steps.
+    pub fn update(&mut self, render_time: f64) {
+        self.water.time = render_time as f32;
+        let mut camera = self.camera.lock().unwrap();
+        let focus = camera.focus();
+        self.water.set_focus(focus.x, focus.z);
+        self.particles.follow(focus.x, focus.z);
+        let eye = camera.eye_position();
+        camera.keep_above(self.water.height_at(eye.x, eye.z, render_time as f32) + CAMERA_CLEARANCE);
     }
     pub fn size_change(&mut self, size: &(usize, usize)) {
         self.camera.lock().unwrap().size(*size);
+        let (width, height) = ((size.0 / REFLECTION_SCALE) as u32, (size.1 / REFLECTION_SCALE) as u32);
+        self.reflections = match (RenderTarget::textured(width, height), RenderTarget::textured(width, height)) {
+            (Ok(reflection), Ok(refraction)) => Some((reflection, refraction)),
+            (Err(e), _) | (_, Err(e)) => {
+                println!("Drawing water without reflections: {}", e);
+                None
+            },
+        };
+    }
+    pub fn season(&self) -> Season {
+        self.calendar.season(self.time)
+    }
+    /// Counts of the forest's trees and what's become of them, `None` if
+    /// there's no forest.
+    pub fn forest_stats(&self) -> Option<&ForestStats> {
+        self.forest.as_ref().map(|forest| forest.ecology.stats())
+    }
+    /// What's behind everything, as the weather and season have it.
+    pub fn sky_colour(&self) -> [f32; 4] {
+        self.water.atmosphere.sky
+    }
+    pub fn camera(&self) -> &Arc<Mutex<Camera>> {
+        &self.camera
+    }
+    pub fn set_projection(&mut self, projection: Matrix4<f32>) {
+        self.projection = Some(projection);
     }
 }
 
 impl Drawable for World {
     type Shader = ();
     fn draw_with(&mut self, _shader: &mut (), _graphics: &mut GlGraphics, _context: &Context, cache: &mut ViewProj, _transforms: &mut Transforms) {
+        if let Some(projection) = self.projection.take() {
+            cache.set_projection(projection);
+        }
         cache.view = self.camera.lock().unwrap().mat();
     }
     fn draw_children(&mut self, context: &mut ShaderContext) {
+        self.water.reflection_textures = None;
+        if let Some((reflection, refraction)) = &self.reflections {
+            let view = self.camera.lock().unwrap().mat();
+            {
+                // Everything above still water, flipped under it.
+                let _bound = reflection.bind(self.water.atmosphere.sky);
+                context.draw(&mut SetView(view * Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0)));
+                self.cube.clip_plane = [0.0, 1.0, 0.0, 0.0];
+                self.floaters.clip_plane = [0.0, 1.0, 0.0, 0.0];
+                context.draw(&mut self.cube);
+                context.draw(&mut self.floaters);
+                if let Some(forest) = &mut self.forest {
+                    forest.clip_plane = [0.0, 1.0, 0.0, 0.0];
+                    context.draw(forest);
+                }
+            }
+            {
+                // Everything below still water, as seen through it.
+                let _bound = refraction.bind(self.water.atmosphere.sky);
+                context.draw(&mut SetView(view));
+                self.cube.clip_plane = [0.0, -1.0, 0.0, 0.0];
+                self.floaters.clip_plane = [0.0, -1.0, 0.0, 0.0];
+                context.draw(&mut self.cube);
+                context.draw(&mut self.floaters);
+                if let Some(forest) = &mut self.forest {
+                    forest.clip_plane = [0.0, -1.0, 0.0, 0.0];
+                    context.draw(forest);
+                }
+            }
+            self.cube.clip_plane = NO_CLIP;
+            self.floaters.clip_plane = NO_CLIP;
+            if let Some(forest) = &mut self.forest {
+                forest.clip_plane = NO_CLIP;
+            }
+            if let (Some(reflection), Some(refraction), Some(refraction_depth)) =
+                (reflection.colour_texture(), refraction.colour_texture(), refraction.depth_texture())
+            {
+                self.water.reflection_textures = Some(ReflectionTextures { reflection, refraction, refraction_depth });
+            }
+        }
         context.draw(&mut self.cube);
+        context.draw(&mut self.floaters);
+        if let Some(forest) = &mut self.forest {
+            context.draw(forest);
+        }
         context.draw(&mut self.water);
+        context.draw(&mut self.particles);
         context.draw(&mut self.axis);
     }
 }
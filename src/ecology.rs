@@ -0,0 +1,501 @@
+use std::fmt;
+use rand::Rng;
+use rand_pcg::Pcg32;
+use crate::seasons::{Calendar, Species};
+use crate::seed::{WorldSeed, SeedPurpose};
+
+/// Simulated seconds between ticks of the ecology, which changes far too
+/// slowly to need every step.
+const TICK: f64 = 1.0;
+/// Trees planted by `Ecology::reseed`.
+const INITIAL_TREES: usize = 40;
+/// Height of a tree that's just sprouted.
+const SEEDLING_HEIGHT: f32 = 0.05;
+/// Fraction of the light a full crown of leaves stops.
+const CROWN_OPACITY: f32 = 0.7;
+/// Seeds can't sprout closer than this to another trunk.
+const TRUNK_SPACING: f32 = 0.6;
+/// Chance a seed landing on soil that's as damp as it gets sprouts.
+const GERMINATION: f32 = 0.5;
+/// Health lost per year going short of light or water, and regained
+/// otherwise.
+const STRESS: f32 = 3.0;
+const RECOVERY: f32 = 1.0;
+
+/// How a kind of tree grows, seeds and dies, in world units and years.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Habit {
+    pub max_height: f32,
+    /// Crown radius per unit of height.
+    pub crown_ratio: f32,
+    /// Height a year a seedling puts on, slowing as it nears `max_height`.
+    pub growth: f32,
+    /// Age trees start dropping seeds at.
+    pub maturity: f32,
+    /// Typical age trees die at.
+    pub lifespan: f32,
+    /// How far from the trunk seeds land.
+    pub seed_radius: f32,
+    pub seeds_per_year: f32,
+    /// Least fraction of full sunlight the tree can live on.
+    pub shade_tolerance: f32,
+    /// Least soil moisture the tree can live on, see `Soil::moisture`.
+    pub thirst: f32,
+}
+
+impl Habit {
+    pub fn validate(&self) -> Result<(), String> {
+        let positive = [
+            ("max height", self.max_height),
+            ("crown ratio", self.crown_ratio),
+            ("growth", self.growth),
+            ("lifespan", self.lifespan),
+            ("seed radius", self.seed_radius),
+        ];
+        if let Some((name, value)) = positive.iter().find(|(_, value)| !(*value > 0.0)) {
+            return Err(format!("A tree's {} should be positive, not {}", name, value));
+        }
+        let non_negative = [
+            ("maturity", self.maturity),
+            ("seeds per year", self.seeds_per_year),
+            ("shade tolerance", self.shade_tolerance),
+            ("thirst", self.thirst),
+        ];
+        if let Some((name, value)) = non_negative.iter().find(|(_, value)| !(*value >= 0.0)) {
+            return Err(format!("A tree's {} shouldn't be negative, not {}", name, value));
+        }
+        Ok(())
+    }
+
+    /// Height reached after `years` of unbroken growth from a seedling.
+    fn height_at(&self, years: f32) -> f32 {
+        self.max_height - (self.max_height - SEEDLING_HEIGHT) * (-self.growth * years / self.max_height).exp()
+    }
+}
+
+/// Square of ground the forest grows on, knowing where the water is and
+/// how damp the land is.
+#[derive(Clone, Debug)]
+pub struct Soil {
+    size: usize,
+    /// Half the width of the square in world units.
+    extent: f32,
+    moisture: Vec<f32>,
+}
+
+impl Soil {
+    /// `size` cells along each side of a square `extent` world units either
+    /// side of the origin, with `is_water` telling which world space points
+    /// are under water. Land dries out with distance from the water, to a
+    /// third as damp `reach` world units away.
+    pub fn new(extent: f32, size: usize, reach: f32, is_water: impl Fn(f32, f32) -> bool) -> Result<Self, String> {
+        if !(extent > 0.0) {
+            return Err(format!("Soil should reach a positive distance, not {}", extent));
+        }
+        if size < 2 {
+            return Err(format!("Soil needs at least 2 cells a side, not {}", size));
+        }
+        if !(reach > 0.0) {
+            return Err(format!("Soil moisture should reach a positive distance, not {}", reach));
+        }
+        let cell = extent * 2.0 / size as f32;
+        let mut distance = vec![std::f32::INFINITY; size * size];
+        for j in 0..size {
+            for i in 0..size {
+                let x = (i as f32 + 0.5) * cell - extent;
+                let z = (j as f32 + 0.5) * cell - extent;
+                if is_water(x, z) {
+                    distance[j * size + i] = 0.0;
+                }
+            }
+        }
+        // Two passes of a chamfer distance transform, down then back up.
+        let diagonal = std::f32::consts::SQRT_2;
+        let forward = [(-1, -1, diagonal), (0, -1, 1.0), (1, -1, diagonal), (-1, 0, 1.0)];
+        let mut relax = |i: usize, j: usize, neighbours: &[(isize, isize, f32)]| {
+            for &(di, dj, weight) in neighbours {
+                let (ni, nj) = (i as isize + di, j as isize + dj);
+                if ni >= 0 && nj >= 0 && (ni as usize) < size && (nj as usize) < size {
+                    let through = distance[nj as usize * size + ni as usize] + weight * cell;
+                    if through < distance[j * size + i] {
+                        distance[j * size + i] = through;
+                    }
+                }
+            }
+        };
+        for j in 0..size {
+            for i in 0..size {
+                relax(i, j, &forward);
+            }
+        }
+        let backward: Vec<_> = forward.iter().map(|&(di, dj, weight)| (-di, -dj, weight)).collect();
+        for j in (0..size).rev() {
+            for i in (0..size).rev() {
+                relax(i, j, &backward);
+            }
+        }
+        let moisture = distance.iter().map(|distance| (-distance / reach).exp()).collect();
+        Ok(Self { size, extent, moisture })
+    }
+
+    pub fn extent(&self) -> f32 {
+        self.extent
+    }
+
+    fn cell(&self, x: f32, z: f32) -> Option<usize> {
+        let scale = self.size as f32 / (self.extent * 2.0);
+        let (i, j) = (((x + self.extent) * scale).floor(), ((z + self.extent) * scale).floor());
+        if i < 0.0 || j < 0.0 || i >= self.size as f32 || j >= self.size as f32 {
+            return None;
+        }
+        Some(j as usize * self.size + i as usize)
+    }
+
+    /// From 1 at the water's edge falling towards 0 further inland, `None`
+    /// off the edge of the soil.
+    pub fn moisture(&self, x: f32, z: f32) -> Option<f32> {
+        self.cell(x, z).map(|cell| self.moisture[cell])
+    }
+
+    /// Whether there's land at `(x, z)` for a tree to grow on.
+    pub fn is_land(&self, x: f32, z: f32) -> bool {
+        self.moisture(x, z).map_or(false, |moisture| moisture < 1.0)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tree {
+    /// Index into `Ecology::species`.
+    pub species: usize,
+    /// Where the trunk stands, in world space x and z.
+    pub position: [f32; 2],
+    /// In years.
+    pub age: f32,
+    /// Age this tree will die of old age at.
+    pub lifespan: f32,
+    pub height: f32,
+    /// Fraction of full health; the tree dies once it runs out.
+    pub health: f32,
+    /// Fraction of full sunlight reaching the crown at the last tick.
+    pub light: f32,
+}
+
+impl Tree {
+    pub fn crown_radius(&self, habit: &Habit) -> f32 {
+        self.height * habit.crown_ratio
+    }
+}
+
+/// Running totals and a census of the forest as of the last tick.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ForestStats {
+    pub trees: usize,
+    /// Trees old enough to drop seeds.
+    pub mature: usize,
+    /// Living trees of each of `Ecology::species`.
+    pub by_species: Vec<usize>,
+    pub mean_height: f32,
+    pub seeds_dropped: u64,
+    pub germinated: u64,
+    pub died_of_age: u64,
+    pub died_of_shade: u64,
+    pub died_of_drought: u64,
+}
+
+/// A one line summary for the log, e.g. `34 trees (12 mature, 3.1 tall on
+/// average), 80 sprouted and 46 died so far`.
+impl fmt::Display for ForestStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} trees ({} mature, {:.1} tall on average), {} sprouted and {} died so far",
+            self.trees,
+            self.mature,
+            self.mean_height,
+            self.germinated,
+            self.died_of_age + self.died_of_shade + self.died_of_drought
+        )
+    }
+}
+
+/// Trees that seed, compete for light, water and space, and die, ticking
+/// over as simulated time passes. The same seed and steps give the same
+/// forest.
+pub struct Ecology {
+    species: Vec<Species>,
+    /// Seeds don't sprout while the forest has this many trees.
+    pub max_trees: usize,
+    trees: Vec<Tree>,
+    soil: Soil,
+    rng: Pcg32,
+    stats: ForestStats,
+    /// Simulated seconds not yet ticked over.
+    pending: f64,
+}
+
+impl Ecology {
+    pub fn new(seed: WorldSeed, species: Vec<Species>, soil: Soil) -> Result<Self, String> {
+        if species.is_empty() {
+            return Err("A forest needs at least one species of tree".to_string());
+        }
+        for species in &species {
+            species.validate()?;
+        }
+        let mut ecology = Self {
+            species,
+            max_trees: 300,
+            trees: Vec::new(),
+            soil,
+            rng: seed.rng(SeedPurpose::Trees),
+            stats: ForestStats::default(),
+            pending: 0.0,
+        };
+        ecology.reseed(seed);
+        Ok(ecology)
+    }
+
+    /// Starts over with a scattering of trees of every age, the same for
+    /// the same seed.
+    pub fn reseed(&mut self, seed: WorldSeed) {
+        self.rng = seed.rng(SeedPurpose::Trees);
+        self.trees.clear();
+        self.stats = ForestStats::default();
+        self.pending = 0.0;
+        let extent = self.soil.extent();
+        for _ in 0..INITIAL_TREES {
+            let species = self.rng.gen_range(0, self.species.len());
+            let position = [self.rng.gen_range(-extent, extent), self.rng.gen_range(-extent, extent)];
+            let habit = self.species[species].habit;
+            let damp = self.soil.moisture(position[0], position[1]).map_or(false, |moisture| moisture >= habit.thirst);
+            if !damp || !self.has_room(position) {
+                continue;
+            }
+            let lifespan = habit.lifespan * self.rng.gen_range(0.7f32, 1.3);
+            let age = lifespan * self.rng.gen_range(0.0f32, 0.6);
+            self.trees.push(Tree {
+                species,
+                position,
+                age,
+                lifespan,
+                height: habit.height_at(age),
+                health: 1.0,
+                light: 1.0,
+            });
+        }
+        self.census();
+    }
+
+    pub fn species(&self) -> &[Species] {
+        &self.species
+    }
+
+    pub fn trees(&self) -> &[Tree] {
+        &self.trees
+    }
+
+    pub fn stats(&self) -> &ForestStats {
+        &self.stats
+    }
+
+    /// Moves the forest on by `dt` seconds, ending at simulated `time` as
+    /// `calendar` has it.
+    pub fn step(&mut self, dt: f64, calendar: &Calendar, time: f64) {
+        self.pending += dt;
+        let years = (TICK / calendar.year_length()) as f32;
+        while self.pending >= TICK {
+            self.pending -= TICK;
+            self.tick(years, calendar.time_of_year(time - self.pending));
+        }
+    }
+
+    /// Whether a seed at `position` has land and space to grow.
+    fn has_room(&self, [x, z]: [f32; 2]) -> bool {
+        self.soil.is_land(x, z) && self.trees.iter().all(|tree| {
+            let (dx, dz) = (tree.position[0] - x, tree.position[1] - z);
+            dx * dx + dz * dz >= TRUNK_SPACING * TRUNK_SPACING
+        })
+    }
+
+    /// Fraction of full sunlight reaching each tree through the crowns of
+    /// taller neighbours.
+    fn light(&self, time_of_year: f32) -> Vec<f32> {
+        let density: Vec<f32> = self.species.iter().map(|species| species.foliage(time_of_year).leaf_density).collect();
+        self.trees
+            .iter()
+            .map(|tree| {
+                let radius = tree.crown_radius(&self.species[tree.species].habit).max(SEEDLING_HEIGHT);
+                let shade: f32 = self.trees
+                    .iter()
+                    .filter(|other| other.height > tree.height)
+                    .map(|other| {
+                        let (dx, dz) = (other.position[0] - tree.position[0], other.position[1] - tree.position[1]);
+                        let reach = other.crown_radius(&self.species[other.species].habit) + radius;
+                        let covered = ((reach - (dx * dx + dz * dz).sqrt()) / (radius * 2.0)).max(0.0).min(1.0);
+                        covered * density[other.species] * CROWN_OPACITY
+                    })
+                    .sum();
+                (1.0 - shade).max(0.0)
+            })
+            .collect()
+    }
+
+    fn tick(&mut self, years: f32, time_of_year: f32) {
+        let light = self.light(time_of_year);
+        for (tree, light) in self.trees.iter_mut().zip(light) {
+            let species = &self.species[tree.species];
+            let habit = &species.habit;
+            let moisture = self.soil.moisture(tree.position[0], tree.position[1]).unwrap_or(0.0);
+            tree.age += years;
+            tree.light = light;
+            let vigour = light.min(moisture);
+            let growth = habit.growth * species.foliage(time_of_year).growth_rate * vigour;
+            tree.height += growth * years * (1.0 - tree.height / habit.max_height).max(0.0);
+            if light < habit.shade_tolerance || moisture < habit.thirst {
+                tree.health -= STRESS * years;
+            } else {
+                tree.health = (tree.health + RECOVERY * years).min(1.0);
+            }
+        }
+
+        let mut seeds = Vec::new();
+        for tree in &self.trees {
+            let habit = &self.species[tree.species].habit;
+            if tree.age < habit.maturity {
+                continue;
+            }
+            let expected = habit.seeds_per_year * years;
+            let count = expected.floor() as usize + (self.rng.gen::<f32>() < expected.fract()) as usize;
+            for _ in 0..count {
+                let angle = self.rng.gen_range(0.0, std::f32::consts::PI * 2.0);
+                let distance = habit.seed_radius * self.rng.gen::<f32>().sqrt();
+                seeds.push((tree.species, [tree.position[0] + distance * angle.cos(), tree.position[1] + distance * angle.sin()]));
+            }
+        }
+        self.stats.seeds_dropped += seeds.len() as u64;
+        for (species, position) in seeds {
+            let moisture = self.soil.moisture(position[0], position[1]).unwrap_or(0.0);
+            let sprouts = self.rng.gen::<f32>() < GERMINATION * moisture;
+            if !sprouts || self.trees.len() >= self.max_trees || !self.has_room(position) {
+                continue;
+            }
+            let lifespan = self.species[species].habit.lifespan * self.rng.gen_range(0.7f32, 1.3);
+            self.trees.push(Tree {
+                species,
+                position,
+                age: 0.0,
+                lifespan,
+                height: SEEDLING_HEIGHT,
+                health: 1.0,
+                light: 1.0,
+            });
+            self.stats.germinated += 1;
+        }
+
+        let Self { species, trees, stats, .. } = self;
+        trees.retain(|tree| {
+            if tree.age >= tree.lifespan {
+                stats.died_of_age += 1;
+                false
+            } else if tree.health <= 0.0 {
+                // Whatever it was short of last.
+                if tree.light < species[tree.species].habit.shade_tolerance {
+                    stats.died_of_shade += 1;
+                } else {
+                    stats.died_of_drought += 1;
+                }
+                false
+            } else {
+                true
+            }
+        });
+        self.census();
+    }
+
+    fn census(&mut self) {
+        let mut by_species = vec![0; self.species.len()];
+        let mut mature = 0;
+        let mut height = 0.0;
+        for tree in &self.trees {
+            by_species[tree.species] += 1;
+            if tree.age >= self.species[tree.species].habit.maturity {
+                mature += 1;
+            }
+            height += tree.height;
+        }
+        self.stats.trees = self.trees.len();
+        self.stats.mature = mature;
+        self.stats.by_species = by_species;
+        self.stats.mean_height = if self.trees.is_empty() { 0.0 } else { height / self.trees.len() as f32 };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Land everywhere but a strip of water along the far edge, damp enough
+    /// for any tree.
+    fn soil() -> Soil {
+        Soil::new(10.0, 20, 100.0, |x, _| x > 9.0).unwrap()
+    }
+
+    #[test]
+    fn stats_summarise_in_a_line() {
+        let stats = ForestStats {
+            trees: 34,
+            mature: 12,
+            by_species: vec![20, 10, 4],
+            mean_height: 3.08,
+            seeds_dropped: 500,
+            germinated: 80,
+            died_of_age: 30,
+            died_of_shade: 10,
+            died_of_drought: 6,
+        };
+        assert_eq!(stats.to_string(), "34 trees (12 mature, 3.1 tall on average), 80 sprouted and 46 died so far");
+    }
+
+    #[test]
+    fn same_seed_same_forest() {
+        let calendar = Calendar::new(60.0, 0.0).unwrap();
+        let grow = |seed: u64| {
+            let mut ecology = Ecology::new(WorldSeed(seed), vec![Species::oak(), Species::birch(), Species::pine()], soil()).unwrap();
+            for step in 1..=600 {
+                ecology.step(1.0, &calendar, step as f64);
+            }
+            ecology.stats().clone()
+        };
+        let stats = grow(3);
+        assert!(stats.germinated > 0, "{:?}", stats);
+        assert_eq!(stats, grow(3));
+        assert_ne!(stats, grow(4));
+    }
+
+    #[test]
+    fn seedlings_die_in_deep_shade() {
+        let mut ecology = Ecology::new(WorldSeed(1), vec![Species::oak(), Species::birch()], soil()).unwrap();
+        let tree = |species: usize, position: [f32; 2], height: f32| Tree {
+            species,
+            position,
+            age: 1.0,
+            lifespan: 50.0,
+            height,
+            health: 1.0,
+            light: 1.0,
+        };
+        // A young oak in full leaf, too young to seed, over a birch seedling,
+        // and another seedling out in the open.
+        ecology.trees = vec![
+            tree(0, [0.0, 0.0], 6.0),
+            tree(1, [0.2, 0.0], SEEDLING_HEIGHT),
+            tree(1, [-6.0, 0.0], SEEDLING_HEIGHT),
+        ];
+        for _ in 0..10 {
+            ecology.tick(0.05, 0.375);
+        }
+        let positions: Vec<_> = ecology.trees().iter().map(|tree| tree.position).collect();
+        assert_eq!(positions, vec![[0.0, 0.0], [-6.0, 0.0]]);
+        assert_eq!(ecology.stats().died_of_shade, 1);
+        assert_eq!(ecology.stats().died_of_drought, 0);
+    }
+}
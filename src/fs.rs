@@ -1,7 +1,179 @@
-use std::path::Path;
-use android_glue::AssetError;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
 
-pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, AssetError> {
-    let filename = path.as_ref().to_str().expect("Can't convert path to &str");
-    android_glue::load_asset(filename)
+#[derive(Debug)]
+pub enum AssetError {
+    NotFound(PathBuf),
+    /// The asset exists but has nothing in it.
+    Empty(PathBuf),
+    /// The path can't be handed to the platform, e.g. it isn't UTF-8.
+    InvalidPath(PathBuf),
+    Io(PathBuf, io::Error),
+    /// The asset loaded but its contents couldn't be understood.
+    Decode(PathBuf, String),
+}
+
+impl fmt::Display for AssetError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AssetError::NotFound(path) => write!(f, "Asset {:?} not found", path),
+            AssetError::Empty(path) => write!(f, "Asset {:?} is empty", path),
+            AssetError::InvalidPath(path) => write!(f, "Invalid asset path {:?}", path),
+            AssetError::Io(path, e) => write!(f, "Could not read asset {:?}: {}", path, e),
+            AssetError::Decode(path, e) => write!(f, "Could not decode asset {:?}: {}", path, e),
+        }
+    }
+}
+
+impl Error for AssetError {}
+
+/// Somewhere assets can be loaded from by path.
+pub trait AssetSource: Send + Sync {
+    fn load(&self, path: &Path) -> Result<Vec<u8>, AssetError>;
+}
+
+/// Assets packaged into the APK.
+#[cfg(target_os = "android")]
+pub struct ApkAssets;
+
+#[cfg(target_os = "android")]
+impl AssetSource for ApkAssets {
+    fn load(&self, path: &Path) -> Result<Vec<u8>, AssetError> {
+        let filename = path.to_str().ok_or_else(|| AssetError::InvalidPath(path.to_path_buf()))?;
+        match android_glue::load_asset(filename) {
+            Ok(data) => Ok(data),
+            Err(android_glue::AssetError::AssetMissing) => Err(AssetError::NotFound(path.to_path_buf())),
+            Err(android_glue::AssetError::EmptyBuffer) => Err(AssetError::Empty(path.to_path_buf())),
+        }
+    }
+}
+
+/// Assets read from a directory on disk.
+pub struct DirectoryAssets {
+    root: PathBuf,
+}
+
+impl DirectoryAssets {
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl AssetSource for DirectoryAssets {
+    fn load(&self, path: &Path) -> Result<Vec<u8>, AssetError> {
+        std::fs::read(self.root.join(path)).map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => AssetError::NotFound(path.to_path_buf()),
+            _ => AssetError::Io(path.to_path_buf(), e),
+        })
+    }
+}
+
+/// Assets held in memory, for tests and generated content.
+#[derive(Default)]
+pub struct BundleAssets {
+    files: HashMap<PathBuf, Vec<u8>>,
+}
+
+impl BundleAssets {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn insert<P: Into<PathBuf>>(&mut self, path: P, data: Vec<u8>) -> &mut Self {
+        self.files.insert(path.into(), data);
+        self
+    }
+}
+
+impl AssetSource for BundleAssets {
+    fn load(&self, path: &Path) -> Result<Vec<u8>, AssetError> {
+        self.files
+            .get(path)
+            .cloned()
+            .ok_or_else(|| AssetError::NotFound(path.to_path_buf()))
+    }
+}
+
+/// Where the platform keeps the app's assets. Build it once and share it, as
+/// `TextureManager` does.
+pub fn default_source() -> Box<dyn AssetSource> {
+    #[cfg(target_os = "android")]
+    return Box::new(ApkAssets);
+    #[cfg(not(target_os = "android"))]
+    return Box::new(DirectoryAssets::new(crate::desktop::asset_dir()));
+}
+
+/// Where the app can write its own files, `$TREES_STORAGE` if set.
+pub fn storage_dir() -> PathBuf {
+    if let Some(dir) = std::env::var_os("TREES_STORAGE") {
+        return PathBuf::from(dir);
+    }
+    #[cfg(target_os = "android")]
+    return PathBuf::from("/data/data/com.optimistic_peach.trees/files");
+    #[cfg(not(target_os = "android"))]
+    return PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("storage");
+}
+
+/// A scratch directory for tests holding `files`, removed again when
+/// dropped. `name` keeps tests running at the same time apart.
+#[cfg(test)]
+pub(crate) struct TempDir(pub PathBuf);
+
+#[cfg(test)]
+impl TempDir {
+    pub fn new(name: &str, files: &[(&str, &str)]) -> Self {
+        let root = std::env::temp_dir().join(format!("trees-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        for (file, contents) in files {
+            std::fs::write(root.join(file), contents).unwrap();
+        }
+        TempDir(root)
+    }
+}
+
+#[cfg(test)]
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn directory_assets_load_files() {
+        let dir = TempDir::new("fs-load", &[("leaf.txt", "green")]);
+        let assets = DirectoryAssets::new(&dir.0);
+        assert_eq!(assets.load(Path::new("leaf.txt")).unwrap(), b"green");
+    }
+
+    #[test]
+    fn directory_assets_tell_missing_from_unreadable() {
+        let dir = TempDir::new("fs-errors", &[]);
+        std::fs::create_dir(dir.0.join("folder")).unwrap();
+        let assets = DirectoryAssets::new(&dir.0);
+        match assets.load(Path::new("absent.png")) {
+            Err(AssetError::NotFound(path)) => assert_eq!(path, Path::new("absent.png")),
+            other => panic!("Expected NotFound, got {:?}", other),
+        }
+        match assets.load(Path::new("folder")) {
+            Err(AssetError::Io(path, _)) => assert_eq!(path, Path::new("folder")),
+            other => panic!("Expected Io, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bundle_assets_only_have_what_was_inserted() {
+        let mut assets = BundleAssets::new();
+        assets.insert("bark.png", vec![1, 2, 3]);
+        assert_eq!(assets.load(Path::new("bark.png")).unwrap(), vec![1, 2, 3]);
+        match assets.load(Path::new("moss.png")) {
+            Err(AssetError::NotFound(path)) => assert_eq!(path, Path::new("moss.png")),
+            other => panic!("Expected NotFound, got {:?}", other),
+        }
+    }
 }
@@ -2,74 +2,198 @@ use android_base::{AppImpl, UpdateArgs, enable_backtrace, AppContainer, AppConfi
 use graphics::{Context, clear};
 use opengl_graphics::{GlGraphics, GLSL};
 use piston::input::RenderArgs;
-use crate::shader::{LightShader, Xyz, WaterLight, Texture3D};
-use cgmath::{Rad, Vector3};
+use crate::shader::{LightShader, Xyz, WaterLight, ParticleShader};
+use cgmath::{Rad, Vector3, Matrix4};
 use std::f32::consts::FRAC_PI_4;
 use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use std::path::PathBuf;
 
 mod shader;
-mod fs;
+pub mod fs;
+pub mod clock;
+pub mod seed;
+mod save;
 mod drawable;
 mod controls;
-use noise::{OpenSimplex, NoiseFn};
-use crate::controls::{Camera, spawn};
+#[cfg(not(target_os = "android"))]
+mod desktop;
+pub mod offscreen;
+pub mod textures;
+pub mod weather;
+pub mod seasons;
+pub mod ecology;
+use crate::clock::{Clock, TIMESTEP};
+use crate::controls::{Camera, Actions, Action, spawn};
 use crate::drawable::World;
+use crate::textures::TextureManager;
+use crate::save::SaveFile;
+use crate::seed::WorldSeed;
+use crate::offscreen::{RenderTarget, HeadlessRender, Timelapse, TimelapseExport, save_png, read_framebuffer, timestamp, unused_png_path};
+use image::RgbaImage;
+
+/// Handed to every shader. GLES contexts compile GLSL ES 3.20 instead, see
+/// `Target::from_glsl`.
+const GLSL_VERSION: GLSL = GLSL::V3_30;
+
+fn projection(aspect: f32) -> Matrix4<f32> {
+    cgmath::perspective(Rad(FRAC_PI_4), aspect, 0.1, 1000.0)
+}
 
 pub struct App {
     world: World,
-    time: f64,
+    clock: Clock,
+    size: (usize, usize),
+    headless: Option<HeadlessRender>,
+    timelapse: Option<TimelapseExport>,
+    actions: Arc<Actions>,
+    /// `None` if the shaders can't be watched.
+    #[cfg(feature = "hot_reload")]
+    reloaders: Option<crate::shader::ShaderReloaders>,
 }
 
 impl AppImpl for App {
-    type InitializationData = Arc<Mutex<Camera>>;
-    fn new(gl: &mut GlGraphics, data: Self::InitializationData, shaders: &mut ShaderStorage) -> Self {
+    type InitializationData = (Arc<Mutex<Camera>>, Arc<Actions>);
+    fn new(gl: &mut GlGraphics, (camera, actions): Self::InitializationData, shaders: &mut ShaderStorage) -> Self {
         shaders.cache.set_view_pos(Vector3::new(0., 0., 70.));
 //        shaders.cache.rotate_view_axis_angle(Vector3::new(0., 1., 0.), PI / 2.0);
-        shaders.get::<LightShader>(GLSL::V1_20, gl);
-        shaders.get::<Xyz>(GLSL::V1_20, gl);
-        let water_light = shaders.get::<WaterLight>(GLSL::V1_20, gl).0;
-        const NOISE_SIZE: usize = 100;
-        let perlin_data: Vec<u8> = {
-            let mut data = vec![0u8; NOISE_SIZE * NOISE_SIZE * NOISE_SIZE];
-            let noise = OpenSimplex::new();
-            for i in 0..NOISE_SIZE {
-                let i_val = i as f64;
-                for j in 0..NOISE_SIZE {
-                    let j_val = j as f64;
-                    let offset = i + NOISE_SIZE * j;
-                    for k in 0..NOISE_SIZE {
-                        let k_val = k as f64;
-                        let index = offset + NOISE_SIZE * NOISE_SIZE * k;
-                        data[index] = (noise.get([i_val, j_val, k_val]) * 128.0 + 128.0) as u8;
-                    }
-                }
-            }
-            data
+        shaders.get::<LightShader>(GLSL_VERSION, gl);
+        shaders.get::<Xyz>(GLSL_VERSION, gl);
+        shaders.get::<WaterLight>(GLSL_VERSION, gl);
+        shaders.get::<ParticleShader>(GLSL_VERSION, gl);
+        let headless = HeadlessRender::from_env().unwrap_or_else(|e| {
+            println!("Ignoring headless render settings: {}", e);
+            None
+        });
+        let timelapse = Timelapse::from_env()
+            .and_then(|settings| settings.map(TimelapseExport::new).transpose())
+            .unwrap_or_else(|e| {
+                println!("Ignoring timelapse settings: {}", e);
+                None
+            });
+        let save = SaveFile::load(SaveFile::default_path()).unwrap_or_else(|e| {
+            println!("Ignoring save: {}", e);
+            None
+        });
+        let seed = match std::env::var("TREES_SEED") {
+            Ok(seed) => seed.parse().unwrap_or_else(|e| {
+                println!("Ignoring TREES_SEED: {}", e);
+                WorldSeed::random()
+            }),
+            // Renders for comparison shouldn't depend on what was last played.
+            Err(_) if headless.is_some() || timelapse.is_some() => WorldSeed(0),
+            Err(_) => save.as_ref().map(|save| save.seed).unwrap_or_else(WorldSeed::random),
         };
-        water_light.perlin = Texture3D::from_data(&perlin_data, [NOISE_SIZE; 3]).unwrap();
+        if let (Some(save), None, None) = (&save, &headless, &timelapse) {
+            camera.lock().unwrap().set_pose(save.camera);
+        }
+        println!("World seed {}", seed);
         Self {
-            time: 0.0,
-            world: World::new(data)
+            clock: Clock::default(),
+            size: (1, 1),
+            headless,
+            timelapse,
+            actions,
+            world: World::new(camera, &mut TextureManager::new(fs::default_source()), seed),
+            #[cfg(feature = "hot_reload")]
+            reloaders: match crate::shader::ShaderReloaders::new(GLSL_VERSION) {
+                Ok(reloaders) => Some(reloaders),
+                Err(e) => {
+                    println!("Not watching shaders for changes: {}", e);
+                    None
+                },
+            },
         }
     }
 
     fn on_size_change(&mut self, new: &(usize, usize), _old: &(usize, usize), shaders: &mut ShaderStorage) {
         println!("Projection initialized with {:?} as width/height", new);
-        shaders.cache.set_projection(cgmath::perspective(Rad(FRAC_PI_4), new.0 as f32 / new.1 as f32, 0.1, 1000.0));
+        shaders.cache.set_projection(projection(new.0 as f32 / new.1 as f32));
+        self.size = *new;
         self.world.size_change(new);
     }
     fn update(&mut self, args: UpdateArgs, _cfg: &mut AppConfig) {
-        self.time += args.dt;
-        self.world.update(self.time as _);
+        // A timelapse steps the world itself.
+        if self.timelapse.is_some() {
+            return;
+        }
+        for _ in 0..self.clock.advance(args.dt) {
+            self.world.step(self.clock.step());
+        }
+        self.world.update(self.clock.interpolated_time());
     }
     fn draw_shaded(&mut self, mut context: ShaderContext) {
+        #[cfg(feature = "hot_reload")]
+        if let Some(reloaders) = &mut self.reloaders {
+            reloaders.poll(&mut context);
+        }
+        if let Some(render) = self.headless.take() {
+            self.world.wait_for_generation();
+            self.world.camera().lock().unwrap().set_pose(render.pose);
+            self.world.advance_to(render.time as f64, TIMESTEP);
+            self.world.update(render.time as f64);
+            let result = self.render_offscreen(&mut context, render.size)
+                .and_then(|image| save_png(&image, &render.output));
+            match result {
+                Ok(()) => {
+                    println!("Rendered {:?}", render.output);
+                    std::process::exit(0);
+                },
+                Err(e) => {
+                    println!("Headless render failed: {}", e);
+                    std::process::exit(1);
+                },
+            }
+        }
+        if let Some(mut export) = self.timelapse.take() {
+            match self.timelapse_frame(&mut context, &mut export) {
+                Ok(true) => self.timelapse = Some(export),
+                Ok(false) => {
+                    let output = export.settings.output.clone();
+                    export.finish();
+                    println!("Timelapse written to {:?}", output);
+                    std::process::exit(0);
+                },
+                Err(e) => {
+                    println!("Timelapse failed: {}", e);
+                    std::process::exit(1);
+                },
+            }
+        }
         context.draw(&mut self.world);
+        for action in self.actions.take() {
+            match action {
+                Action::Screenshot { supersample } => match self.screenshot(&mut context, supersample) {
+                    Ok(path) => println!("Saved screenshot to {:?}", path),
+                    Err(e) => println!("Screenshot failed: {}", e),
+                },
+                Action::TogglePause => {
+                    self.clock.toggle_pause();
+                    println!("Simulation {}", if self.clock.is_paused() { "paused" } else { "resumed" });
+                },
+                Action::Step => self.clock.single_step(),
+                Action::NewWorld => {
+                    let seed = WorldSeed::random();
+                    println!("New world with seed {}", seed);
+                    self.world.regenerate(seed);
+                    self.save();
+                },
+                Action::Tap { position } => {
+                    let aspect = self.size.0 as f32 / self.size.1 as f32;
+                    self.world.tap(position, self.size, projection(aspect));
+                },
+                Action::ScaleTime(factor) => {
+                    self.clock.set_scale(self.clock.scale() * factor);
+                    println!("Simulating at {}x", self.clock.scale());
+                },
+            }
+        }
     }
-    fn draw_2d(&mut self, _c: Context, gl: &mut GlGraphics, args: RenderArgs, _cfg: &mut AppConfig) {
-        self.time += args.ext_dt;
-        clear([163.0 / 255.0, 250.0 / 255.0, 255.0 / 255.0, 1.], gl);
+    fn draw_2d(&mut self, _c: Context, gl: &mut GlGraphics, _args: RenderArgs, _cfg: &mut AppConfig) {
+        clear(self.world.sky_colour(), gl);
     }
     fn on_die(self) {
+        self.save();
         println!("Dieing!");
     }
     fn cancel_poll(&self) -> bool {
@@ -77,11 +201,69 @@ impl AppImpl for App {
     }
 }
 
+impl App {
+    fn save(&self) {
+        let save = SaveFile {
+            seed: self.world.seed(),
+            camera: self.world.camera().lock().unwrap().pose(),
+        };
+        if let Err(e) = save.save(SaveFile::default_path()) {
+            println!("Could not save: {}", e);
+        }
+    }
+
+    /// Draws the world as it currently is into a fresh target of `size`.
+    fn render_offscreen(&mut self, context: &mut ShaderContext, size: (u32, u32)) -> Result<RgbaImage, String> {
+        let target = RenderTarget::new(size.0, size.1)?;
+        self.world.set_projection(projection(target.aspect()));
+        {
+            let _bound = target.bind(self.world.sky_colour());
+            context.draw(&mut self.world);
+        }
+        self.world.set_projection(projection(self.size.0 as f32 / self.size.1 as f32));
+        Ok(target.read())
+    }
+
+    /// Renders the next frame of a timelapse, returning whether there are
+    /// more to come.
+    fn timelapse_frame(&mut self, context: &mut ShaderContext, export: &mut TimelapseExport) -> Result<bool, String> {
+        let (time, pose) = match export.next_frame() {
+            Some(frame) => frame,
+            None => return Ok(false),
+        };
+        self.world.wait_for_generation();
+        self.world.camera().lock().unwrap().set_pose(pose);
+        self.world.advance_to(time as f64, TIMESTEP);
+        self.world.update(time as f64);
+        let image = self.render_offscreen(context, export.settings.size)?;
+        export.write_frame(image)?;
+        let (done, total) = export.progress();
+        println!("Timelapse frame {}/{}", done, total);
+        Ok(true)
+    }
+
+    /// Saves the frame just drawn under the app's storage, next to any taken
+    /// the same second, re-rendering it offscreen if it should be
+    /// `supersample` times the window's size.
+    fn screenshot(&mut self, context: &mut ShaderContext, supersample: u32) -> Result<PathBuf, String> {
+        let (width, height) = (self.size.0 as u32, self.size.1 as u32);
+        let image = if supersample > 1 {
+            self.render_offscreen(context, (width * supersample, height * supersample))?
+        } else {
+            read_framebuffer(width, height)
+        };
+        let stem = format!("trees-{}", timestamp(SystemTime::now()));
+        let path = unused_png_path(&fs::storage_dir().join("screenshots"), &stem);
+        save_png(&image, &path)?;
+        Ok(path)
+    }
+}
+
 pub fn main() {
     enable_backtrace();
     let camera = Arc::new(Mutex::new(Camera::default()));
-    let cloned_camera = camera.clone();
-    let mut container = AppContainer::<App>::init(AppConfig::new(), cloned_camera);
-    spawn(&mut container, camera);
+    let actions = Arc::new(Actions::default());
+    let mut container = AppContainer::<App>::init(AppConfig::new(), (camera.clone(), actions.clone()));
+    spawn(&mut container, camera, actions);
     container.run();
 }
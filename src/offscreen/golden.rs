@@ -0,0 +1,152 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use image::{Rgba, RgbaImage};
+use crate::offscreen::save_png;
+
+/// How far a render may drift from its golden image and still pass.
+#[derive(Clone, Copy, Debug)]
+pub struct Tolerance {
+    /// Largest difference allowed in any channel of a pixel.
+    pub per_channel: u8,
+    /// Fraction of pixels (0 to 1) allowed to exceed `per_channel`, to absorb
+    /// rasterisation differences along edges.
+    pub mismatched_fraction: f32,
+}
+
+impl Default for Tolerance {
+    fn default() -> Self {
+        Self {
+            per_channel: 8,
+            mismatched_fraction: 0.001,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Comparison {
+    pub mismatched: usize,
+    pub total: usize,
+    pub max_difference: u8,
+    /// Mismatched pixels in red over a dimmed copy of the expected image.
+    pub diff: RgbaImage,
+}
+
+impl Comparison {
+    pub fn passes(&self, tolerance: &Tolerance) -> bool {
+        self.mismatched as f32 <= tolerance.mismatched_fraction * self.total as f32
+    }
+}
+
+pub fn compare(actual: &RgbaImage, expected: &RgbaImage, per_channel: u8) -> Result<Comparison, String> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(format!(
+            "Render is {:?} but the golden image is {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        ));
+    }
+    let mut mismatched = 0;
+    let mut max_difference = 0;
+    let diff = RgbaImage::from_fn(actual.width(), actual.height(), |x, y| {
+        let a = actual.get_pixel(x, y);
+        let e = expected.get_pixel(x, y);
+        let difference = a.0.iter()
+            .zip(e.0.iter())
+            .map(|(&a, &e)| (a as i16 - e as i16).abs() as u8)
+            .max()
+            .unwrap_or(0);
+        max_difference = max_difference.max(difference);
+        if difference > per_channel {
+            mismatched += 1;
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([e[0] / 3, e[1] / 3, e[2] / 3, 255])
+        }
+    });
+    Ok(Comparison {
+        mismatched,
+        total: (actual.width() * actual.height()) as usize,
+        max_difference,
+        diff,
+    })
+}
+
+/// Checks the render at `actual` against the golden image at `golden`.
+///
+/// On failure a `<actual>.diff.png` is written next to the render. With
+/// `TREES_BLESS` set, the render replaces the golden image instead.
+pub fn check(actual: &Path, golden: &Path, tolerance: &Tolerance) -> Result<Comparison, String> {
+    let open = |path: &Path| image::open(path)
+        .map(|image| image.to_rgba())
+        .map_err(|e| format!("Could not open {:?}: {}", path, e));
+    let actual_image = open(actual)?;
+
+    if env::var_os("TREES_BLESS").is_some() {
+        save_png(&actual_image, golden)?;
+        println!("Blessed {:?}", golden);
+    }
+
+    let comparison = compare(&actual_image, &open(golden)?, tolerance.per_channel)?;
+    if !comparison.passes(tolerance) {
+        let diff_path = diff_path(actual);
+        save_png(&comparison.diff, &diff_path)?;
+        return Err(format!(
+            "{:?} differs from {:?} in {} of {} pixels (max channel difference {}), see {:?}",
+            actual,
+            golden,
+            comparison.mismatched,
+            comparison.total,
+            comparison.max_difference,
+            diff_path
+        ));
+    }
+    Ok(comparison)
+}
+
+fn diff_path(actual: &Path) -> PathBuf {
+    let stem = actual.file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    actual.with_file_name(format!("{}.diff.png", stem))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filled(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_pixel(width, height, Rgba([value, value, value, 255]))
+    }
+
+    #[test]
+    fn identical_images_match() {
+        let image = filled(4, 4, 100);
+        let comparison = compare(&image, &image, 0).unwrap();
+        assert_eq!(comparison.mismatched, 0);
+        assert_eq!(comparison.total, 16);
+        assert_eq!(comparison.max_difference, 0);
+        assert!(comparison.passes(&Tolerance { per_channel: 0, mismatched_fraction: 0.0 }));
+    }
+
+    #[test]
+    fn differences_up_to_the_tolerance_pass() {
+        let expected = filled(4, 4, 100);
+        let mut actual = expected.clone();
+        actual.put_pixel(1, 2, Rgba([108, 100, 100, 255]));
+        let comparison = compare(&actual, &expected, 8).unwrap();
+        assert_eq!(comparison.mismatched, 0);
+        assert_eq!(comparison.max_difference, 8);
+
+        actual.put_pixel(1, 2, Rgba([100, 91, 100, 255]));
+        let comparison = compare(&actual, &expected, 8).unwrap();
+        assert_eq!(comparison.mismatched, 1);
+        assert_eq!(comparison.diff.get_pixel(1, 2), &Rgba([255, 0, 0, 255]));
+        assert_eq!(comparison.diff.get_pixel(0, 0), &Rgba([33, 33, 33, 255]));
+        // One pixel in sixteen.
+        assert!(comparison.passes(&Tolerance { per_channel: 8, mismatched_fraction: 1.0 / 16.0 }));
+        assert!(!comparison.passes(&Tolerance { per_channel: 8, mismatched_fraction: 0.06 }));
+    }
+
+    #[test]
+    fn different_sizes_are_rejected() {
+        assert!(compare(&filled(4, 4, 0), &filled(4, 5, 0), 255).is_err());
+    }
+}
@@ -0,0 +1,378 @@
+use std::env;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use opengl_graphics::gl::types::{GLint, GLuint};
+use opengl_graphics::gl;
+use image::RgbaImage;
+use image::imageops::flip_vertical;
+use crate::controls::CameraPose;
+
+pub mod golden;
+mod timelapse;
+
+pub use timelapse::*;
+
+/// A framebuffer with colour and depth attachments of a fixed size.
+pub struct RenderTarget {
+    fbo: GLuint,
+    colour: GLuint,
+    /// Whether `colour` and `depth` are textures that can be sampled rather
+    /// than renderbuffers.
+    textured: bool,
+    depth: GLuint,
+    width: u32,
+    height: u32,
+}
+
+impl RenderTarget {
+    pub fn new(width: u32, height: u32) -> Result<Self, String> {
+        Self::create(width, height, false)
+    }
+
+    /// A target whose colour and depth can be sampled through
+    /// `colour_texture` and `depth_texture`.
+    pub fn textured(width: u32, height: u32) -> Result<Self, String> {
+        Self::create(width, height, true)
+    }
+
+    fn create(width: u32, height: u32, textured: bool) -> Result<Self, String> {
+        if width == 0 || height == 0 {
+            return Err(format!("Can't create a {}x{} render target", width, height));
+        }
+        let mut target = Self {
+            fbo: 0,
+            colour: 0,
+            textured,
+            depth: 0,
+            width,
+            height,
+        };
+        unsafe {
+            let mut previous = 0;
+            gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut previous);
+
+            gl::GenFramebuffers(1, &mut target.fbo);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, target.fbo);
+
+            if textured {
+                gl::GenTextures(1, &mut target.colour);
+                gl::BindTexture(gl::TEXTURE_2D, target.colour);
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::RGBA8 as i32,
+                    width as _,
+                    height as _,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    std::ptr::null());
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, target.colour, 0);
+            } else {
+                gl::GenRenderbuffers(1, &mut target.colour);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, target.colour);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::RGBA8, width as _, height as _);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::RENDERBUFFER, target.colour);
+            }
+
+            if textured {
+                gl::GenTextures(1, &mut target.depth);
+                gl::BindTexture(gl::TEXTURE_2D, target.depth);
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    0,
+                    gl::DEPTH_COMPONENT24 as i32,
+                    width as _,
+                    height as _,
+                    0,
+                    gl::DEPTH_COMPONENT,
+                    gl::UNSIGNED_INT,
+                    std::ptr::null());
+                // Depth can't be filtered on every device.
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+                gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_2D, target.depth, 0);
+            } else {
+                gl::GenRenderbuffers(1, &mut target.depth);
+                gl::BindRenderbuffer(gl::RENDERBUFFER, target.depth);
+                gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, width as _, height as _);
+                gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, target.depth);
+            }
+
+            let status = gl::CheckFramebufferStatus(gl::FRAMEBUFFER);
+            gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous as GLuint);
+            if status != gl::FRAMEBUFFER_COMPLETE {
+                return Err(format!("Render target incomplete: status {:#x}", status));
+            }
+        }
+        Ok(target)
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    pub fn colour_texture(&self) -> Option<GLuint> {
+        if self.textured { Some(self.colour) } else { None }
+    }
+
+    pub fn depth_texture(&self) -> Option<GLuint> {
+        if self.textured { Some(self.depth) } else { None }
+    }
+
+    /// Draws into this target, cleared to `clear_colour`, until the returned
+    /// guard is dropped.
+    pub fn bind(&self, clear_colour: [f32; 4]) -> Bound {
+        let mut previous_fbo = 0;
+        let mut previous_viewport = [0; 4];
+        unsafe {
+            gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut previous_fbo);
+            gl::GetIntegerv(gl::VIEWPORT, previous_viewport.as_mut_ptr());
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            gl::Viewport(0, 0, self.width as _, self.height as _);
+            gl::ClearColor(clear_colour[0], clear_colour[1], clear_colour[2], clear_colour[3]);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        }
+        Bound {
+            previous_fbo,
+            previous_viewport,
+        }
+    }
+
+    /// Reads the colour attachment back, top row first.
+    pub fn read(&self) -> RgbaImage {
+        unsafe {
+            let mut previous = 0;
+            gl::GetIntegerv(gl::FRAMEBUFFER_BINDING, &mut previous);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo);
+            let image = read_framebuffer(self.width, self.height);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, previous as GLuint);
+            image
+        }
+    }
+}
+
+/// Reads the bottom left `width` by `height` pixels of the bound framebuffer,
+/// top row first.
+pub fn read_framebuffer(width: u32, height: u32) -> RgbaImage {
+    let mut pixels = vec![0u8; (width * height * 4) as usize];
+    unsafe {
+        gl::PixelStorei(gl::PACK_ALIGNMENT, 1);
+        gl::ReadPixels(
+            0,
+            0,
+            width as _,
+            height as _,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_mut_ptr() as *mut _);
+    }
+    let image = RgbaImage::from_raw(width, height, pixels)
+        .expect("Pixel buffer is sized for the framebuffer");
+    // GL's rows start at the bottom.
+    flip_vertical(&image)
+}
+
+impl Drop for RenderTarget {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteFramebuffers(1, &self.fbo);
+            if self.textured {
+                gl::DeleteTextures(1, &self.colour);
+                gl::DeleteTextures(1, &self.depth);
+            } else {
+                gl::DeleteRenderbuffers(1, &self.colour);
+                gl::DeleteRenderbuffers(1, &self.depth);
+            }
+        }
+    }
+}
+
+/// Restores the previous framebuffer and viewport when dropped.
+pub struct Bound {
+    previous_fbo: GLint,
+    previous_viewport: [GLint; 4],
+}
+
+impl Drop for Bound {
+    fn drop(&mut self) {
+        let [x, y, width, height] = self.previous_viewport;
+        unsafe {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, self.previous_fbo as GLuint);
+            gl::Viewport(x, y, width, height);
+        }
+    }
+}
+
+/// `YYYYMMDD-HHMMSS` in UTC, for naming files.
+pub fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (days, seconds) = ((seconds / 86400) as i64, seconds % 86400);
+    // Days since the epoch to a civil date, from Howard Hinnant's algorithms.
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// `<stem>.png` in `dir`, or `<stem>-2.png`, `<stem>-3.png` and so on if
+/// that's already taken, so nothing gets overwritten.
+pub fn unused_png_path(dir: &Path, stem: &str) -> PathBuf {
+    let mut path = dir.join(format!("{}.png", stem));
+    let mut count = 1;
+    while path.exists() {
+        count += 1;
+        path = dir.join(format!("{}-{}.png", stem, count));
+    }
+    path
+}
+
+pub fn save_png<P: AsRef<Path>>(image: &RgbaImage, path: P) -> Result<(), String> {
+    let path = path.as_ref();
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Could not create {:?}: {}", parent, e))?;
+    }
+    image.save(path).map_err(|e| format!("Could not save {:?}: {}", path, e))
+}
+
+/// A single frame rendered offscreen and written to a PNG, after which the
+/// app exits.
+///
+/// This still opens a window, as `android_base` has no surfaceless or OSMesa
+/// contexts, so without a display it needs xvfb. Mesa's llvmpipe can stand in
+/// for a GPU: `LIBGL_ALWAYS_SOFTWARE=1 xvfb-run cargo run`.
+/// `ci/golden.sh` renders a fixed scene this way and checks it against
+/// `golden/default.png`.
+///
+/// Configured through the environment:
+/// - `TREES_RENDER_OUTPUT`: the PNG to write; nothing happens without it.
+/// - `TREES_RENDER_SIZE`: `<width>x<height>`, `512x512` by default.
+/// - `TREES_RENDER_TIME`: simulated seconds, `0` by default.
+/// - `TREES_RENDER_CAMERA`: `<angle from ground>,<angle about y>,<distance>,<x>,<z>`,
+///   the default camera otherwise.
+/// - `TREES_SEED`: the world seed, `0` by default rather than the saved one.
+#[derive(Clone, Debug)]
+pub struct HeadlessRender {
+    pub output: PathBuf,
+    pub size: (u32, u32),
+    pub time: f32,
+    pub pose: CameraPose,
+}
+
+impl HeadlessRender {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let output = match env::var_os("TREES_RENDER_OUTPUT") {
+            Some(output) => PathBuf::from(output),
+            None => return Ok(None),
+        };
+        let mut render = Self {
+            output,
+            size: (512, 512),
+            time: 0.0,
+            pose: CameraPose::default(),
+        };
+        if let Ok(size) = env::var("TREES_RENDER_SIZE") {
+            render.size = parse_size(&size)?;
+        }
+        if let Ok(time) = env::var("TREES_RENDER_TIME") {
+            render.time = time.trim().parse().map_err(|_| format!("Invalid TREES_RENDER_TIME {:?}", time))?;
+        }
+        if let Ok(camera) = env::var("TREES_RENDER_CAMERA") {
+            render.pose = parse_pose(&camera)?;
+        }
+        Ok(Some(render))
+    }
+}
+
+pub fn parse_size(size: &str) -> Result<(u32, u32), String> {
+    let error = || format!("Invalid size {:?}, expected <width>x<height>", size);
+    let mut parts = size.trim().splitn(2, 'x');
+    let width = parts.next().and_then(|w| w.parse().ok()).ok_or_else(error)?;
+    let height = parts.next().and_then(|h| h.parse().ok()).ok_or_else(error)?;
+    Ok((width, height))
+}
+
+pub fn parse_pose(pose: &str) -> Result<CameraPose, String> {
+    let values = pose
+        .split(',')
+        .map(|v| v.trim().parse::<f32>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| format!("Invalid camera {:?}", pose))?;
+    match values[..] {
+        [angle_from_ground, angle_about_y, dist_origin, x_target, z_target] => Ok(CameraPose {
+            angle_from_ground,
+            angle_about_y,
+            dist_origin,
+            x_target,
+            z_target,
+        }),
+        _ => Err(format!("Invalid camera {:?}, expected five comma separated values", pose)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use crate::fs::TempDir;
+
+    #[test]
+    fn sizes_parse() {
+        assert_eq!(parse_size("640x480"), Ok((640, 480)));
+        assert_eq!(parse_size(" 1x2 "), Ok((1, 2)));
+        for bad in &["640", "640x", "x480", "640x480x2", "-1x2", "axb"] {
+            assert!(parse_size(bad).is_err(), "{:?} parsed", bad);
+        }
+    }
+
+    #[test]
+    fn poses_parse() {
+        let pose = CameraPose { angle_from_ground: 0.6, angle_about_y: 0.8, dist_origin: 25.0, x_target: -1.0, z_target: 2.5 };
+        assert_eq!(parse_pose("0.6, 0.8,25,-1,2.5"), Ok(pose));
+        assert!(parse_pose("0.6,0.8,25,-1").is_err());
+        assert!(parse_pose("0.6,0.8,25,-1,2.5,3").is_err());
+        assert!(parse_pose("0.6,0.8,far,-1,2.5").is_err());
+    }
+
+    #[test]
+    fn timestamps_are_utc_dates() {
+        let at = |seconds: u64| timestamp(UNIX_EPOCH + Duration::from_secs(seconds));
+        assert_eq!(at(0), "19700101-000000");
+        // The leap day in a year divisible by 400.
+        assert_eq!(at(951_825_599), "20000229-115959");
+        assert_eq!(at(1_709_251_199), "20240229-235959");
+        assert_eq!(at(1_709_251_200), "20240301-000000");
+    }
+
+    #[test]
+    fn png_paths_skip_taken_names() {
+        let dir = TempDir::new("offscreen-unused", &[("shot.png", ""), ("shot-2.png", "")]);
+        assert_eq!(unused_png_path(&dir.0, "shot"), dir.0.join("shot-3.png"));
+        assert_eq!(unused_png_path(&dir.0, "other"), dir.0.join("other.png"));
+    }
+}
@@ -0,0 +1,287 @@
+use std::env;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::f32::consts::PI;
+use image::{Frame, RgbaImage};
+use image::gif::Encoder as GifEncoder;
+use num_rational::Ratio;
+use crate::controls::CameraPose;
+use crate::offscreen::{parse_pose, parse_size, save_png};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimelapseFormat {
+    /// `frame-00000.png`, `frame-00001.png`, ... in the output directory.
+    PngSequence,
+    /// A single animated GIF at the output path.
+    Gif,
+}
+
+/// Camera poses keyed by simulated time, interpolated linearly between keys
+/// and held before the first and after the last.
+#[derive(Clone, Debug, Default)]
+pub struct CameraPath {
+    keys: Vec<(f32, CameraPose)>,
+}
+
+impl CameraPath {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn key(mut self, time: f32, pose: CameraPose) -> Self {
+        let index = self.keys.iter().position(|&(t, _)| t > time).unwrap_or(self.keys.len());
+        self.keys.insert(index, (time, pose));
+        self
+    }
+
+    pub fn pose_at(&self, time: f32) -> Option<CameraPose> {
+        let after = self.keys.iter().position(|&(t, _)| t > time);
+        match after {
+            None => self.keys.last().map(|&(_, pose)| pose),
+            Some(0) => Some(self.keys[0].1),
+            Some(after) => {
+                let (start, from) = self.keys[after - 1];
+                let (end, to) = self.keys[after];
+                Some(lerp_pose(from, to, (time - start) / (end - start)))
+            },
+        }
+    }
+}
+
+/// Turns the short way round between `angle_about_y`s.
+fn lerp_pose(from: CameraPose, to: CameraPose, t: f32) -> CameraPose {
+    let lerp = |a: f32, b: f32| a + (b - a) * t;
+    let mut turn = to.angle_about_y - from.angle_about_y;
+    if turn > PI {
+        turn -= 2.0 * PI;
+    } else if turn < -PI {
+        turn += 2.0 * PI;
+    }
+    CameraPose {
+        angle_from_ground: lerp(from.angle_from_ground, to.angle_from_ground),
+        angle_about_y: from.angle_about_y + turn * t,
+        dist_origin: lerp(from.dist_origin, to.dist_origin),
+        x_target: lerp(from.x_target, to.x_target),
+        z_target: lerp(from.z_target, to.z_target),
+    }
+}
+
+/// Renders the world's growth frame by frame at a fixed timestep, after which
+/// the app exits.
+///
+/// Configured through the environment:
+/// - `TREES_TIMELAPSE_OUTPUT`: a directory for a PNG sequence, or a `.gif`
+///   file; nothing happens without it.
+/// - `TREES_TIMELAPSE_DURATION`: simulated seconds to cover, `60` by default.
+/// - `TREES_TIMELAPSE_SPEED`: simulated seconds per second of output, `10`
+///   by default.
+/// - `TREES_TIMELAPSE_FPS`: output frames per second, `25` by default.
+/// - `TREES_TIMELAPSE_SIZE`: `<width>x<height>`, `640x480` by default.
+/// - `TREES_TIMELAPSE_CAMERA`: `;` separated `<time>:<camera>` keys, with
+///   cameras as for `TREES_RENDER_CAMERA`; the default camera otherwise.
+/// - `TREES_SEED`: as for `HeadlessRender`.
+#[derive(Clone, Debug)]
+pub struct Timelapse {
+    pub output: PathBuf,
+    pub format: TimelapseFormat,
+    pub duration: f32,
+    pub speed: f32,
+    pub fps: u32,
+    pub size: (u32, u32),
+    pub path: CameraPath,
+}
+
+impl Timelapse {
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let output = match env::var_os("TREES_TIMELAPSE_OUTPUT") {
+            Some(output) => PathBuf::from(output),
+            None => return Ok(None),
+        };
+        let format = match output.extension() {
+            Some(extension) if extension == "gif" => TimelapseFormat::Gif,
+            _ => TimelapseFormat::PngSequence,
+        };
+        let number = |name: &str, default: f32| -> Result<f32, String> {
+            match env::var(name) {
+                Ok(value) => value.trim().parse().map_err(|_| format!("Invalid {} {:?}", name, value)),
+                Err(_) => Ok(default),
+            }
+        };
+        let mut timelapse = Self {
+            output,
+            format,
+            duration: number("TREES_TIMELAPSE_DURATION", 60.0)?,
+            speed: number("TREES_TIMELAPSE_SPEED", 10.0)?,
+            fps: number("TREES_TIMELAPSE_FPS", 25.0)? as u32,
+            size: (640, 480),
+            path: CameraPath::new().key(0.0, CameraPose::default()),
+        };
+        if timelapse.fps == 0 || timelapse.speed <= 0.0 {
+            return Err("Timelapse speed and frame rate should be positive".to_string());
+        }
+        if let Ok(size) = env::var("TREES_TIMELAPSE_SIZE") {
+            timelapse.size = parse_size(&size)?;
+        }
+        if let Ok(camera) = env::var("TREES_TIMELAPSE_CAMERA") {
+            timelapse.path = parse_path(&camera)?;
+        }
+        Ok(Some(timelapse))
+    }
+
+    /// Simulated seconds between frames.
+    pub fn timestep(&self) -> f32 {
+        self.speed / self.fps as f32
+    }
+
+    pub fn frame_count(&self) -> u32 {
+        (self.duration / self.timestep()).ceil() as u32 + 1
+    }
+}
+
+fn parse_path(path: &str) -> Result<CameraPath, String> {
+    path.split(';')
+        .filter(|key| !key.trim().is_empty())
+        .try_fold(CameraPath::new(), |path, key| {
+            let mut parts = key.splitn(2, ':');
+            let time = parts.next()
+                .and_then(|t| t.trim().parse::<f32>().ok())
+                .ok_or_else(|| format!("Invalid camera key {:?}, expected <time>:<camera>", key))?;
+            let pose = parse_pose(parts.next().unwrap_or(""))?;
+            Ok(path.key(time, pose))
+        })
+}
+
+/// How long to show `frame` for in a GIF. Delays are whole hundredths of a
+/// second, and the encoder truncates anything finer, so where `fps` doesn't
+/// divide 100 the delays alternate to keep every frame within a hundredth of
+/// its time, e.g. 3, 4, 3, 3, 4, 3... at 30 fps.
+fn gif_delay(frame: u32, fps: u32) -> u16 {
+    let shown_at = |frame: u32| (frame as u64 * 100 + fps as u64 / 2) / fps as u64;
+    (shown_at(frame + 1) - shown_at(frame)).max(1) as u16
+}
+
+/// Where a `Timelapse` has got to.
+pub struct TimelapseExport {
+    pub settings: Timelapse,
+    frame: u32,
+    gif: Option<GifEncoder<BufWriter<File>>>,
+}
+
+impl TimelapseExport {
+    pub fn new(settings: Timelapse) -> Result<Self, String> {
+        let gif = match settings.format {
+            TimelapseFormat::Gif => {
+                if let Some(parent) = settings.output.parent() {
+                    std::fs::create_dir_all(parent).map_err(|e| format!("Could not create {:?}: {}", parent, e))?;
+                }
+                let file = File::create(&settings.output)
+                    .map_err(|e| format!("Could not create {:?}: {}", settings.output, e))?;
+                Some(GifEncoder::new(BufWriter::new(file)))
+            },
+            TimelapseFormat::PngSequence => None,
+        };
+        Ok(Self {
+            settings,
+            frame: 0,
+            gif,
+        })
+    }
+
+    /// The simulated time and camera for the next frame, or `None` once
+    /// they've all been written.
+    pub fn next_frame(&self) -> Option<(f32, CameraPose)> {
+        if self.frame >= self.settings.frame_count() {
+            return None;
+        }
+        let time = self.frame as f32 * self.settings.timestep();
+        let pose = self.settings.path.pose_at(time).unwrap_or_default();
+        Some((time, pose))
+    }
+
+    pub fn write_frame(&mut self, image: RgbaImage) -> Result<(), String> {
+        match self.gif.as_mut() {
+            Some(gif) => {
+                let delay = Ratio::from_integer(gif_delay(self.frame, self.settings.fps));
+                gif.encode_frames(Some(Frame::from_parts(image, 0, 0, delay)))
+                    .map_err(|e| format!("Could not encode frame {}: {}", self.frame, e))?;
+            },
+            None => {
+                let path = self.settings.output.join(format!("frame-{:05}.png", self.frame));
+                save_png(&image, path)?;
+            },
+        }
+        self.frame += 1;
+        Ok(())
+    }
+
+    pub fn progress(&self) -> (u32, u32) {
+        (self.frame, self.settings.frame_count())
+    }
+
+    /// Finishes the GIF, if there is one. Needed before exiting the process
+    /// since that skips destructors.
+    pub fn finish(self) {
+        drop(self.gif);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pose(angle_about_y: f32, dist_origin: f32) -> CameraPose {
+        CameraPose { angle_about_y, dist_origin, ..CameraPose::default() }
+    }
+
+    #[test]
+    fn paths_hold_before_and_after_their_keys() {
+        assert_eq!(CameraPath::new().pose_at(1.0), None);
+        let path = CameraPath::new().key(10.0, pose(1.0, 20.0)).key(2.0, pose(0.5, 10.0));
+        assert_eq!(path.pose_at(-5.0), Some(pose(0.5, 10.0)));
+        assert_eq!(path.pose_at(2.0), Some(pose(0.5, 10.0)));
+        assert_eq!(path.pose_at(10.0), Some(pose(1.0, 20.0)));
+        assert_eq!(path.pose_at(100.0), Some(pose(1.0, 20.0)));
+        let middle = path.pose_at(6.0).unwrap();
+        assert!((middle.angle_about_y - 0.75).abs() < 1e-6 && (middle.dist_origin - 15.0).abs() < 1e-5);
+    }
+
+    #[test]
+    fn poses_turn_the_short_way_halfway() {
+        let middle = lerp_pose(pose(3.0, 10.0), pose(-3.0, 30.0), 0.5);
+        // 3 and -3 are 0.28 apart the other way round, through π.
+        assert!((middle.angle_about_y - PI).abs() < 1e-5, "{}", middle.angle_about_y);
+        assert!((middle.dist_origin - 20.0).abs() < 1e-5);
+        let middle = lerp_pose(pose(0.5, 10.0), pose(1.5, 10.0), 0.5);
+        assert!((middle.angle_about_y - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn frames_cover_the_duration() {
+        let timelapse = |duration: f32, speed: f32, fps: u32| Timelapse {
+            output: PathBuf::new(),
+            format: TimelapseFormat::PngSequence,
+            duration,
+            speed,
+            fps,
+            size: (1, 1),
+            path: CameraPath::new(),
+        };
+        // Both ends are drawn.
+        assert_eq!(timelapse(60.0, 10.0, 25).frame_count(), 151);
+        assert_eq!(timelapse(0.0, 10.0, 25).frame_count(), 1);
+        // A part step at the end still gets a frame.
+        assert_eq!(timelapse(1.1, 1.0, 1).frame_count(), 3);
+    }
+
+    #[test]
+    fn gif_delays_keep_to_the_frame_rate() {
+        assert!((0..50).all(|frame| gif_delay(frame, 25) == 4));
+        for &fps in &[24, 25, 30, 60] {
+            let second: u32 = (0..fps).map(|frame| gif_delay(frame, fps) as u32).sum();
+            assert_eq!(second, 100, "at {} fps", fps);
+        }
+        assert_eq!((0..6).map(|frame| gif_delay(frame, 30)).collect::<Vec<_>>(), vec![3, 4, 3, 3, 4, 3]);
+        assert_eq!(gif_delay(0, 1000), 1);
+    }
+}
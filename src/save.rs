@@ -0,0 +1,144 @@
+use std::path::{Path, PathBuf};
+use crate::controls::CameraPose;
+use crate::offscreen::parse_pose;
+use crate::seed::WorldSeed;
+
+const HEADER: &str = "trees save 1";
+
+/// What's kept between runs, as `key = value` lines under a version header:
+///
+/// ```text
+/// trees save 1
+/// seed = 00c0ffee00c0ffee
+/// camera = 0.785,0,10,0,0
+/// ```
+///
+/// Unknown keys are ignored so newer saves still load.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SaveFile {
+    pub seed: WorldSeed,
+    pub camera: CameraPose,
+}
+
+impl SaveFile {
+    /// `world.save` in the app's storage.
+    pub fn default_path() -> PathBuf {
+        crate::fs::storage_dir().join("world.save")
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut lines = text.lines();
+        if lines.next().map(str::trim) != Some(HEADER) {
+            return Err(format!("Not a save file, expected {:?} first", HEADER));
+        }
+        let mut seed = None;
+        let mut camera = CameraPose::default();
+        for line in lines.map(str::trim).filter(|line| !line.is_empty() && !line.starts_with('#')) {
+            let mut parts = line.splitn(2, '=');
+            let key = parts.next().unwrap_or("").trim();
+            let value = parts.next().ok_or_else(|| format!("Invalid save line {:?}", line))?.trim();
+            match key {
+                // Always hex, with or without the `0x` `Display` adds.
+                "seed" => seed = Some(
+                    u64::from_str_radix(value.trim_start_matches("0x"), 16)
+                        .map(WorldSeed)
+                        .map_err(|_| format!("Invalid seed {:?} in save file", value))?
+                ),
+                "camera" => camera = parse_pose(value)?,
+                _ => {},
+            }
+        }
+        Ok(Self {
+            seed: seed.ok_or("Save file has no seed")?,
+            camera,
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        let pose = &self.camera;
+        format!(
+            "{}\nseed = {:016x}\ncamera = {},{},{},{},{}\n",
+            HEADER,
+            self.seed.0,
+            pose.angle_from_ground,
+            pose.angle_about_y,
+            pose.dist_origin,
+            pose.x_target,
+            pose.z_target
+        )
+    }
+
+    /// `Ok(None)` if nothing has been saved there yet.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Option<Self>, String> {
+        let path = path.as_ref();
+        match std::fs::read_to_string(path) {
+            Ok(text) => Self::parse(&text).map(Some).map_err(|e| format!("{:?}: {}", path, e)),
+            Err(ref e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(format!("Could not read {:?}: {}", path, e)),
+        }
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), String> {
+        let path = path.as_ref();
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| format!("Could not create {:?}: {}", parent, e))?;
+        }
+        std::fs::write(path, self.to_text()).map_err(|e| format!("Could not write {:?}: {}", path, e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::TempDir;
+
+    fn save() -> SaveFile {
+        SaveFile {
+            seed: WorldSeed(0x00c0_ffee_0123_4567),
+            camera: CameraPose {
+                angle_from_ground: 0.785_398_2,
+                angle_about_y: -2.1,
+                dist_origin: 12.345_679,
+                x_target: 1.0e-7,
+                z_target: -300.5,
+            },
+        }
+    }
+
+    #[test]
+    fn saves_round_trip() {
+        let save = save();
+        assert_eq!(SaveFile::parse(&save.to_text()), Ok(save.clone()));
+        let dir = TempDir::new("save", &[]);
+        let path = dir.0.join("nested").join("world.save");
+        assert_eq!(SaveFile::load(&path), Ok(None));
+        save.save(&path).unwrap();
+        assert_eq!(SaveFile::load(&path), Ok(Some(save)));
+    }
+
+    #[test]
+    fn unknown_keys_and_comments_are_skipped() {
+        let text = "trees save 1\n# A comment\nweather = rain\nseed = 0x2a\n";
+        let save = SaveFile::parse(text).unwrap();
+        assert_eq!(save.seed, WorldSeed(42));
+        assert_eq!(save.camera, CameraPose::default());
+    }
+
+    #[test]
+    fn bad_saves_are_rejected() {
+        let bad = [
+            "",
+            "seed = 2a\n",
+            "trees save 2\nseed = 2a\n",
+            "trees save 1\n",
+            "trees save 1\ncamera = 1,2,3,4,5\n",
+            "trees save 1\nseed\n",
+            "trees save 1\nseed = sea\n",
+            "trees save 1\nseed = 2a\ncamera = 1,2,3\n",
+            "trees save 1\nseed = 2a\ncamera = 1,2,x,4,5\n",
+        ];
+        for text in bad.iter() {
+            assert!(SaveFile::parse(text).is_err(), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,345 @@
+use crate::weather::{lerp, lerp_array};
+use crate::ecology::Habit;
+
+/// Simulated seconds in a year by default, short enough to watch go round.
+const YEAR_LENGTH: f64 = 20.0 * 60.0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Season {
+    Spring,
+    Summer,
+    Autumn,
+    Winter,
+}
+
+impl Season {
+    pub const ALL: [Season; 4] = [Season::Spring, Season::Summer, Season::Autumn, Season::Winter];
+
+    /// The season at `time_of_year`, see `Calendar::time_of_year`.
+    pub fn at(time_of_year: f32) -> Season {
+        Self::ALL[((time_of_year * 4.0) as usize).min(3)]
+    }
+}
+
+/// Daylight and sky at the middle of each season, multiplying the weather's.
+const SEASON_TINTS: &[(f32, ([f32; 3], [f32; 3]))] = &[
+    (0.125, ([1.0, 1.0, 0.96], [0.95, 1.0, 0.96])),
+    (0.375, ([1.05, 1.02, 0.94], [1.0, 1.0, 1.0])),
+    (0.625, ([1.0, 0.9, 0.78], [1.0, 0.93, 0.86])),
+    (0.875, ([0.82, 0.88, 1.0], [0.88, 0.92, 1.0])),
+];
+
+/// Turns simulated time into a time of year.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Calendar {
+    /// Simulated seconds in a year.
+    year_length: f64,
+    /// Time of year at time zero.
+    start: f64,
+}
+
+impl Default for Calendar {
+    /// Twenty minute years, starting in the middle of spring.
+    fn default() -> Self {
+        Self {
+            year_length: YEAR_LENGTH,
+            start: 0.125,
+        }
+    }
+}
+
+impl Calendar {
+    pub fn new(year_length: f64, start: f64) -> Result<Self, String> {
+        if !(year_length > 0.0) || !year_length.is_finite() {
+            return Err(format!("A year should last a positive number of seconds, not {}", year_length));
+        }
+        if !(0.0..1.0).contains(&start) {
+            return Err(format!("A calendar should start between 0 and 1 of the way through the year, not {}", start));
+        }
+        Ok(Self { year_length, start })
+    }
+
+    /// The default calendar, with the year's length in seconds and the time
+    /// of year it starts at taken from `TREES_YEAR_LENGTH` and
+    /// `TREES_YEAR_START` when they're set.
+    pub fn from_env() -> Result<Self, String> {
+        let number = |name: &str, default: f64| -> Result<f64, String> {
+            match std::env::var(name) {
+                Ok(value) => value.trim().parse().map_err(|_| format!("Invalid {} {:?}", name, value)),
+                Err(_) => Ok(default),
+            }
+        };
+        let default = Self::default();
+        Self::new(number("TREES_YEAR_LENGTH", default.year_length)?, number("TREES_YEAR_START", default.start)?)
+    }
+
+    pub fn year_length(&self) -> f64 {
+        self.year_length
+    }
+
+    /// Whole years gone by at `time`.
+    pub fn year(&self, time: f64) -> u64 {
+        (time / self.year_length + self.start).max(0.0).floor() as u64
+    }
+
+    /// How far through the year `time` is, from 0 at the start of spring up
+    /// to 1.
+    pub fn time_of_year(&self, time: f64) -> f32 {
+        let years = time / self.year_length + self.start;
+        (years - years.floor()) as f32
+    }
+
+    pub fn season(&self, time: f64) -> Season {
+        Season::at(self.time_of_year(time))
+    }
+
+    /// Daylight and sky colour multipliers at `time`, see
+    /// `Atmosphere::tinted`.
+    pub fn tint(&self, time: f64) -> ([f32; 3], [f32; 3]) {
+        sample(SEASON_TINTS, self.time_of_year(time), |(light_a, sky_a), (light_b, sky_b), t| {
+            (lerp_array(light_a, light_b, t), lerp_array(sky_a, sky_b, t))
+        })
+    }
+}
+
+/// Blends between the `(time of year, value)` keys either side of
+/// `time_of_year`, wrapping round from the last to the first. Keys should be
+/// in order.
+fn sample<T: Copy>(keys: &[(f32, T)], time_of_year: f32, blend: impl Fn(T, T, f32) -> T) -> T {
+    let after = keys.iter().position(|&(key, _)| key > time_of_year).unwrap_or(0);
+    let before = (after + keys.len() - 1) % keys.len();
+    let (start, a) = keys[before];
+    let (end, b) = keys[after];
+    let span = (end - start).rem_euclid(1.0);
+    if span == 0.0 {
+        return a;
+    }
+    blend(a, b, (time_of_year - start).rem_euclid(1.0) / span)
+}
+
+/// How a kind of tree looks and grows at one time of year.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Foliage {
+    pub leaf_colour: [f32; 4],
+    /// Fraction of a full crown of leaves.
+    pub leaf_density: f32,
+    /// Multiplies how fast the tree grows.
+    pub growth_rate: f32,
+    /// Leaves a second the tree drops.
+    pub leaf_drop: f32,
+}
+
+/// How a kind of tree changes through the year, and grows over the years.
+/// Each list is keyed by time of year, see `Calendar::time_of_year`, in
+/// order, and wraps round from the end of the year to the start.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Species {
+    pub name: &'static str,
+    pub leaf_colours: Vec<(f32, [f32; 4])>,
+    pub leaf_density: Vec<(f32, f32)>,
+    pub growth_rate: Vec<(f32, f32)>,
+    pub leaf_drop: Vec<(f32, f32)>,
+    /// What leaves fade to once they've fallen.
+    pub fallen_colour: [f32; 4],
+    pub habit: Habit,
+}
+
+impl Species {
+    /// Buds in spring, deep green through summer and orange in autumn, bare
+    /// all winter.
+    pub fn oak() -> Self {
+        Self {
+            name: "oak",
+            leaf_colours: vec![
+                (0.05, [0.62, 0.75, 0.3, 1.0]),
+                (0.25, [0.35, 0.55, 0.15, 1.0]),
+                (0.5, [0.3, 0.48, 0.12, 1.0]),
+                (0.62, [0.75, 0.45, 0.1, 1.0]),
+                (0.72, [0.6, 0.3, 0.1, 1.0]),
+            ],
+            leaf_density: vec![(0.0, 0.0), (0.05, 0.05), (0.2, 1.0), (0.55, 1.0), (0.75, 0.0)],
+            growth_rate: vec![(0.0, 0.3), (0.15, 1.5), (0.4, 1.0), (0.65, 0.3), (0.8, 0.0)],
+            leaf_drop: vec![(0.0, 0.0), (0.5, 0.3), (0.65, 6.0), (0.75, 0.5), (0.8, 0.0)],
+            fallen_colour: [0.5, 0.3, 0.12, 1.0],
+            habit: Habit {
+                max_height: 6.0,
+                crown_ratio: 0.45,
+                growth: 0.5,
+                maturity: 10.0,
+                lifespan: 80.0,
+                seed_radius: 3.0,
+                seeds_per_year: 6.0,
+                shade_tolerance: 0.35,
+                thirst: 0.15,
+            },
+        }
+    }
+
+    /// Pale and early to leaf, yellow in autumn.
+    pub fn birch() -> Self {
+        Self {
+            name: "birch",
+            leaf_colours: vec![
+                (0.02, [0.7, 0.82, 0.35, 1.0]),
+                (0.2, [0.45, 0.68, 0.2, 1.0]),
+                (0.5, [0.42, 0.6, 0.18, 1.0]),
+                (0.6, [0.9, 0.8, 0.2, 1.0]),
+                (0.7, [0.8, 0.6, 0.15, 1.0]),
+            ],
+            leaf_density: vec![(0.0, 0.0), (0.02, 0.1), (0.15, 1.0), (0.5, 1.0), (0.7, 0.0)],
+            growth_rate: vec![(0.0, 0.4), (0.1, 1.8), (0.4, 1.0), (0.6, 0.3), (0.75, 0.0)],
+            leaf_drop: vec![(0.0, 0.0), (0.45, 0.5), (0.6, 8.0), (0.7, 0.5), (0.75, 0.0)],
+            fallen_colour: [0.6, 0.45, 0.15, 1.0],
+            habit: Habit {
+                max_height: 5.0,
+                crown_ratio: 0.3,
+                growth: 0.9,
+                maturity: 5.0,
+                lifespan: 40.0,
+                seed_radius: 6.0,
+                seeds_per_year: 15.0,
+                shade_tolerance: 0.6,
+                thirst: 0.25,
+            },
+        }
+    }
+
+    /// Evergreen, a little darker in winter and shedding a few needles all
+    /// year.
+    pub fn pine() -> Self {
+        Self {
+            name: "pine",
+            leaf_colours: vec![
+                (0.2, [0.2, 0.42, 0.2, 1.0]),
+                (0.5, [0.16, 0.38, 0.18, 1.0]),
+                (0.85, [0.12, 0.28, 0.18, 1.0]),
+            ],
+            leaf_density: vec![(0.0, 1.0)],
+            growth_rate: vec![(0.0, 0.3), (0.2, 1.0), (0.5, 0.8), (0.75, 0.2)],
+            leaf_drop: vec![(0.0, 0.2), (0.6, 0.6)],
+            fallen_colour: [0.45, 0.32, 0.15, 1.0],
+            habit: Habit {
+                max_height: 7.0,
+                crown_ratio: 0.25,
+                growth: 0.6,
+                maturity: 8.0,
+                lifespan: 100.0,
+                seed_radius: 4.0,
+                seeds_per_year: 8.0,
+                shade_tolerance: 0.45,
+                thirst: 0.05,
+            },
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        let check = |what: &str, keys: &mut dyn Iterator<Item = f32>| -> Result<(), String> {
+            let mut previous = None;
+            let mut any = false;
+            for key in keys {
+                if !(0.0..1.0).contains(&key) || previous.map_or(false, |previous| key <= previous) {
+                    return Err(format!("The {}'s {} should be keyed by increasing times of year from 0 up to 1", self.name, what));
+                }
+                previous = Some(key);
+                any = true;
+            }
+            if !any {
+                return Err(format!("The {} has no {}", self.name, what));
+            }
+            Ok(())
+        };
+        check("leaf colours", &mut self.leaf_colours.iter().map(|&(key, _)| key))?;
+        check("leaf density", &mut self.leaf_density.iter().map(|&(key, _)| key))?;
+        check("growth rate", &mut self.growth_rate.iter().map(|&(key, _)| key))?;
+        check("leaf drop", &mut self.leaf_drop.iter().map(|&(key, _)| key))?;
+        self.habit.validate().map_err(|e| format!("The {}: {}", self.name, e))
+    }
+
+    /// This species at `time_of_year`. It should be valid.
+    pub fn foliage(&self, time_of_year: f32) -> Foliage {
+        Foliage {
+            leaf_colour: sample(&self.leaf_colours, time_of_year, lerp_array),
+            leaf_density: sample(&self.leaf_density, time_of_year, lerp),
+            growth_rate: sample(&self.growth_rate, time_of_year, lerp),
+            leaf_drop: sample(&self.leaf_drop, time_of_year, lerp),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-5
+    }
+
+    #[test]
+    fn calendars_need_a_positive_year_and_a_start_within_it() {
+        for &length in &[0.0, -1.0, f64::NAN, f64::INFINITY] {
+            assert!(Calendar::new(length, 0.0).is_err(), "{}", length);
+        }
+        for &start in &[-0.1, 1.0, 1.5] {
+            assert!(Calendar::new(100.0, start).is_err(), "{}", start);
+        }
+        assert!(Calendar::new(100.0, 0.0).is_ok());
+        assert!(Calendar::new(100.0, 0.99).is_ok());
+    }
+
+    #[test]
+    fn years_go_round() {
+        let calendar = Calendar::new(100.0, 0.5).unwrap();
+        assert_eq!(calendar.year(0.0), 0);
+        assert_eq!(calendar.year(49.0), 0);
+        assert_eq!(calendar.year(50.0), 1);
+        assert_eq!(calendar.year(250.0), 3);
+        assert_eq!(calendar.year(-100.0), 0);
+        assert!(close(calendar.time_of_year(0.0), 0.5));
+        assert!(close(calendar.time_of_year(50.0), 0.0));
+        assert!(close(calendar.time_of_year(75.0), 0.25));
+        assert!(close(calendar.time_of_year(175.0), 0.25));
+        assert!(close(calendar.time_of_year(-25.0), 0.25));
+        assert_eq!(calendar.season(60.0), Season::Spring);
+        assert_eq!(calendar.season(40.0), Season::Winter);
+    }
+
+    #[test]
+    fn seasons_split_the_year_in_quarters() {
+        let seasons = [
+            (0.0, Season::Spring), (0.2499, Season::Spring), (0.25, Season::Summer), (0.5, Season::Autumn),
+            (0.7499, Season::Autumn), (0.75, Season::Winter), (0.9999, Season::Winter), (1.0, Season::Winter),
+        ];
+        for &(time_of_year, season) in seasons.iter() {
+            assert_eq!(Season::at(time_of_year), season, "at {}", time_of_year);
+        }
+    }
+
+    #[test]
+    fn samples_wrap_from_the_last_key_to_the_first() {
+        let keys = [(0.25, 0.0), (0.75, 1.0)];
+        assert!(close(sample(&keys, 0.25, lerp), 0.0));
+        assert!(close(sample(&keys, 0.5, lerp), 0.5));
+        assert!(close(sample(&keys, 0.9, lerp), 0.7));
+        assert!(close(sample(&keys, 0.1, lerp), 0.3));
+        for &time_of_year in &[0.0, 0.3, 0.99] {
+            assert_eq!(sample(&[(0.3, 2.0)], time_of_year, lerp), 2.0);
+        }
+    }
+
+    #[test]
+    fn species_need_ordered_keys() {
+        for species in &[Species::oak(), Species::birch(), Species::pine()] {
+            assert_eq!(species.validate(), Ok(()));
+        }
+        let invalid = [
+            Species { leaf_colours: Vec::new(), ..Species::oak() },
+            Species { leaf_density: vec![(0.5, 1.0), (0.2, 0.0)], ..Species::oak() },
+            Species { growth_rate: vec![(0.5, 1.0), (0.5, 0.0)], ..Species::oak() },
+            Species { leaf_drop: vec![(1.0, 1.0)], ..Species::oak() },
+            Species { leaf_drop: vec![(-0.1, 1.0)], ..Species::oak() },
+        ];
+        for species in &invalid {
+            assert!(species.validate().is_err(), "{:?}", species);
+        }
+    }
+}
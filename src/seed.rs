@@ -0,0 +1,131 @@
+use std::fmt;
+use std::str::FromStr;
+use std::time::SystemTime;
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+
+/// What a sub-seed is for. Each purpose gets its own stream, so adding or
+/// removing randomness in one place doesn't disturb the others.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SeedPurpose {
+    WaterNoise,
+    Terrain,
+    Trees,
+    Scattering,
+    Weather,
+    Particles,
+}
+
+impl SeedPurpose {
+    /// Fixed forever, unlike the discriminants, so saved seeds keep
+    /// producing the same worlds.
+    fn tag(self) -> u64 {
+        match self {
+            SeedPurpose::WaterNoise => 0x7761_7465_7200_0001,
+            SeedPurpose::Terrain => 0x7465_7272_6100_0002,
+            SeedPurpose::Trees => 0x7472_6565_7300_0003,
+            SeedPurpose::Scattering => 0x7363_6174_7400_0004,
+            SeedPurpose::Weather => 0x7765_6174_6800_0005,
+            SeedPurpose::Particles => 0x7061_7274_6900_0006,
+        }
+    }
+}
+
+/// The one number everything procedural in a world comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct WorldSeed(pub u64);
+
+impl WorldSeed {
+    /// A seed from the clock, for a world nobody asked for in particular.
+    pub fn random() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        WorldSeed(splitmix64(nanos))
+    }
+
+    pub fn derive(self, purpose: SeedPurpose) -> u64 {
+        splitmix64(self.0 ^ splitmix64(purpose.tag()))
+    }
+
+    /// A 32 bit seed, for generators like the water's noise that don't take
+    /// a whole `u64`.
+    pub fn derive_u32(self, purpose: SeedPurpose) -> u32 {
+        let seed = self.derive(purpose);
+        (seed ^ (seed >> 32)) as u32
+    }
+
+    pub fn rng(self, purpose: SeedPurpose) -> Pcg32 {
+        Pcg32::seed_from_u64(self.derive(purpose))
+    }
+}
+
+/// Written as `0x` and 16 hex digits.
+impl fmt::Display for WorldSeed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "0x{:016x}", self.0)
+    }
+}
+
+/// Reads hex after a `0x`, as written by `Display`, then decimal, then any
+/// other text, which is hashed so that memorable seeds like `island` work
+/// too.
+impl FromStr for WorldSeed {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err("Empty world seed".to_string());
+        }
+        if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+            return u64::from_str_radix(hex, 16)
+                .map(WorldSeed)
+                .map_err(|_| format!("Invalid hex world seed {:?}", s));
+        }
+        match s.parse() {
+            Ok(seed) => Ok(WorldSeed(seed)),
+            Err(_) => Ok(WorldSeed(s.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+                // FNV-1a, which unlike `DefaultHasher` won't change between
+                // Rust versions.
+                (hash ^ byte as u64).wrapping_mul(0x0100_0000_01b3)
+            }))),
+        }
+    }
+}
+
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_needs_a_prefix() {
+        assert_eq!("0x10".parse(), Ok(WorldSeed(16)));
+        assert_eq!("0XfF".parse(), Ok(WorldSeed(255)));
+        assert_eq!("10".parse(), Ok(WorldSeed(10)));
+        assert!("0xisland".parse::<WorldSeed>().is_err());
+    }
+
+    #[test]
+    fn display_round_trips() {
+        let seed = WorldSeed(0x00c0_ffee_00c0_ffee);
+        assert_eq!(seed.to_string(), "0x00c0ffee00c0ffee");
+        assert_eq!(seed.to_string().parse(), Ok(seed));
+    }
+
+    #[test]
+    fn other_text_is_hashed() {
+        let island: WorldSeed = "island".parse().unwrap();
+        assert_eq!(" island ".parse(), Ok(island));
+        assert_ne!("islands".parse(), Ok(island));
+        // Hex digits without the prefix are just text.
+        assert_ne!("beef".parse(), Ok(WorldSeed(0xbeef)));
+    }
+}
@@ -1,10 +1,13 @@
-use opengl_graphics::shader_utils::{Shader, DynamicAttribute, compile_shader};
+use opengl_graphics::shader_utils::{Shader, DynamicAttribute};
 use opengl_graphics::gl::types::GLuint;
 use opengl_graphics::{gl, GlGraphics};
 use opengl_graphics::GLSL;
 use opengl_graphics::shader_uniforms::{ShaderUniform, SUMat4x4};
 use cgmath::{Matrix4, SquareMatrix, Vector3, Rad};
+use crate::shader::{ElementBuffer, link_program, Preprocessor, Includes, Target};
 
+pub const FRAGMENT_FILE: &'static str = "axis_bars/xyz_fragment.glsl";
+pub const VERTEX_FILE: &'static str = "axis_bars/xyz_vertex.glsl";
 const FRAGMENT_SOURCE: &'static str = include_str!("./xyz_fragment.glsl");
 const VERTEX_SOURCE: &'static str = include_str!("./xyz_vertex.glsl");
 
@@ -22,6 +25,7 @@ pub struct Xyz {
     color_buffer: Vec<[f32; 4]>,
     // Indices and the offset
     indices: Vec<u16>,
+    element_buffer: ElementBuffer,
     offset: usize,
     // Matrices and other items
     pub world: Matrix4<f32>,
@@ -51,50 +55,38 @@ impl Xyz {
     pub fn rotate_eye(&mut self, value: Vector3<f32>) {
         self.view = self.view * Matrix4::from_angle_x(Rad(value.x)) * Matrix4::from_angle_y(Rad(value.y)) * Matrix4::from_angle_z(Rad(value.z));
     }
-    pub fn set_light(&mut self, value: [f32; 3]) {
-        self.pos_buffer[12] = [value[0] - 0.2, value[1] - 0.2, value[2], 1.0];
-        self.pos_buffer[13] = [value[0] + 0.2, value[1] - 0.2, value[2], 1.0];
-        self.pos_buffer[14] = [value[0] - 0.2, value[1] + 0.2, value[2], 1.0];
-        self.pos_buffer[15] = [value[0] + 0.2, value[1] + 0.2, value[2], 1.0];
-    }
-}
-
-impl Shader for Xyz {
-    type Vertex = [f32; 4];
-    fn new(_: GLSL, gl: Option<&mut GlGraphics>) -> Self {
-        let gl = gl.unwrap();
-        let vertex_shader_compiled = compile_shader(gl::VERTEX_SHADER, VERTEX_SOURCE).expect("Vertex shader error");
-        let fragment_shader_compiled = compile_shader(gl::FRAGMENT_SHADER, FRAGMENT_SOURCE).expect("Fragment shader error");
-
-        let program = unsafe {
-            let program = gl::CreateProgram();
-            gl::AttachShader(program, vertex_shader_compiled);
-            gl::AttachShader(program, fragment_shader_compiled);
-            program
-        };
+    pub fn from_sources(gl: &mut GlGraphics, preprocessor: &Preprocessor, vertex: &str, fragment: &str) -> Result<Self, String> {
+        let vertex = preprocessor.process(gl::VERTEX_SHADER, VERTEX_FILE, vertex)?;
+        let fragment = preprocessor.process(gl::FRAGMENT_SHADER, FRAGMENT_FILE, fragment)?;
+        let linked = link_program(&[(gl::VERTEX_SHADER, &vertex), (gl::FRAGMENT_SHADER, &fragment)])?;
+        let program = linked.program;
+
+        let pos = DynamicAttribute::xyzw(program, "pos")?;
+        let color = DynamicAttribute::rgba(program, "color")?;
+        gl.use_program(program);
+        let uniforms = (|| -> Result<_, String> {
+            let projection_matrix_uni = gl.get_uniform("projection").ok_or("Could not find projection uniform")?;
+            projection_matrix_uni.set(gl, &[0.0; 16]);
+            let world_matrix_uni = gl.get_uniform("model").ok_or("Could not find model uniform")?;
+            world_matrix_uni.set(gl, &[0.0; 16]);
+            let view_matrix_uni = gl.get_uniform("view").ok_or("Could not find view uniform")?;
+            view_matrix_uni.set(gl, &[0.0; 16]);
+            Ok((projection_matrix_uni, world_matrix_uni, view_matrix_uni))
+        })();
+        gl.clear_program();
+        let (projection_matrix_uni, world_matrix_uni, view_matrix_uni) = uniforms?;
 
+        let (program, stages) = linked.release();
         let mut vao = 0;
         unsafe {
             gl::GenVertexArrays(1, &mut vao);
-            gl::LinkProgram(program);
         }
 
-        let pos = DynamicAttribute::xyzw(program, "pos").unwrap();
-        let color = DynamicAttribute::rgba(program, "color").unwrap();
-        gl.use_program(program);
-        let projection_matrix_uni = gl.get_uniform("projection").expect("Could not find projection uniform");
-        projection_matrix_uni.set(gl, &[0.0; 16]);
-        let world_matrix_uni = gl.get_uniform("model").expect("Could not find model uniform");
-        world_matrix_uni.set(gl, &[0.0; 16]);
-        let view_matrix_uni = gl.get_uniform("view").expect("Could not find view uniform");
-        view_matrix_uni.set(gl, &[0.0; 16]);
-        gl.clear_program();
-
-        Self {
+        Ok(Self {
             // Shader items
             vao,
-            vertex_shader: vertex_shader_compiled,
-            fragment_shader: fragment_shader_compiled,
+            vertex_shader: stages[0],
+            fragment_shader: stages[1],
             program,
             // Per vertex items
             pos,
@@ -146,6 +138,7 @@ impl Shader for Xyz {
                 8, 11, 9, 8, 10, 11,
                 12, 14, 15, 12, 15, 13,
             ],
+            element_buffer: ElementBuffer::new(),
             offset: 0,
             // Matrices and vectors
             world: Matrix4::identity(),
@@ -155,7 +148,35 @@ impl Shader for Xyz {
             world_matrix_uni,
             view_matrix_uni,
             projection_matrix_uni,
-        }
+        })
+    }
+
+    /// Swaps in a program built from new sources, keeping the current one if
+    /// they fail to compile or link.
+    pub fn reload(&mut self, gl: &mut GlGraphics, preprocessor: &Preprocessor, vertex: &str, fragment: &str) -> Result<(), String> {
+        let mut fresh = Self::from_sources(gl, preprocessor, vertex, fragment)?;
+        fresh.world = self.world;
+        fresh.view = self.view;
+        fresh.projection = self.projection;
+        fresh.pos_buffer[12..16].copy_from_slice(&self.pos_buffer[12..16]);
+        *self = fresh;
+        Ok(())
+    }
+    pub fn set_light(&mut self, value: [f32; 3]) {
+        self.pos_buffer[12] = [value[0] - 0.2, value[1] - 0.2, value[2], 1.0];
+        self.pos_buffer[13] = [value[0] + 0.2, value[1] - 0.2, value[2], 1.0];
+        self.pos_buffer[14] = [value[0] - 0.2, value[1] + 0.2, value[2], 1.0];
+        self.pos_buffer[15] = [value[0] + 0.2, value[1] + 0.2, value[2], 1.0];
+    }
+}
+
+impl Shader for Xyz {
+    type Vertex = [f32; 4];
+    fn new(glsl: GLSL, gl: Option<&mut GlGraphics>) -> Self {
+        let gl = gl.unwrap();
+        Target::for_context(glsl)
+            .and_then(|target| Self::from_sources(gl, &Preprocessor::shared(Includes::Baked, target), VERTEX_SOURCE, FRAGMENT_SOURCE))
+            .expect("Axis shader error")
     }
 
     fn flush(&mut self) {
@@ -168,7 +189,7 @@ impl Shader for Xyz {
             self.pos.bind_vao(self.vao);
             self.pos.set(&self.pos_buffer);
             gl::LineWidth(5.0);
-            gl::DrawElements(gl::TRIANGLES, 24, gl::UNSIGNED_SHORT, self.indices.as_ptr() as *const _);
+            self.element_buffer.draw_triangles(&self.indices[..24]);
             gl::BindVertexArray(0);
         }
     }
@@ -1,5 +1,3 @@
-#version 320 es
-precision mediump float;
 
 in vec4 v_Color;
 out vec4 outColor;
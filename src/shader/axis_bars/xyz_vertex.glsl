@@ -1,4 +1,3 @@
-#version 320 es
 uniform mat4x4 projection;
 uniform mat4x4 model;
 uniform mat4x4 view;
@@ -1,14 +1,21 @@
-use opengl_graphics::shader_utils::{Shader, DynamicAttribute, compile_shader};
+use opengl_graphics::shader_utils::{Shader, DynamicAttribute};
 use opengl_graphics::gl::types::GLuint;
 use opengl_graphics::{gl, GlGraphics};
 use opengl_graphics::GLSL;
 use graphics::BACK_END_MAX_VERTEX_COUNT;
-use opengl_graphics::shader_uniforms::{ShaderUniform, SUMat4x4, SUVec3, SUVec4};
+use opengl_graphics::shader_uniforms::{ShaderUniform, SUFloat, SUMat4x4, SUVec3, SUVec4};
 use cgmath::{Matrix4, SquareMatrix, Vector3, Rad};
+use crate::shader::{ElementBuffer, link_program, Preprocessor, Includes, Target};
 
+pub const FRAGMENT_FILE: &'static str = "basic_light/fragment.glsl";
+pub const VERTEX_FILE: &'static str = "basic_light/vertex.glsl";
 const FRAGMENT_SOURCE: &'static str = include_str!("./fragment.glsl");
 const VERTEX_SOURCE: &'static str = include_str!("./vertex.glsl");
 const CHUNKS: usize = 100;
+/// A `clip_plane` everything is in front of.
+pub const NO_CLIP: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+/// `light_colour` on a clear day.
+pub const LIGHT_COLOUR: [f32; 4] = [0.77, 0.61, 0.80, 1.0];
 
 pub struct LightShader {
     // Shader items
@@ -28,6 +35,7 @@ pub struct LightShader {
     normal_buffer: Vec<[f32; 3]>,
     // Indices and the offset
     indices: Vec<u16>,
+    element_buffer: ElementBuffer,
     offset: usize,
     texture: GLuint,
     // Matrices and other items
@@ -42,6 +50,9 @@ pub struct LightShader {
     pub light_uni: ShaderUniform<SUVec3>,
     pub eye_uni: ShaderUniform<SUVec3>,
     pub light_colour_uni: ShaderUniform<SUVec4>,
+    pub clip_plane_uni: ShaderUniform<SUVec4>,
+    pub fog_colour_uni: ShaderUniform<SUVec3>,
+    pub fog_density_uni: ShaderUniform<SUFloat>,
 }
 
 impl LightShader {
@@ -52,63 +63,73 @@ impl LightShader {
     pub fn rotate_eye(&mut self, value: Vector3<f32>) {
         self.view = self.view * Matrix4::from_angle_x(Rad(value.x)) * Matrix4::from_angle_y(Rad(value.y)) * Matrix4::from_angle_z(Rad(value.z));
     }
-}
-
-impl Drop for LightShader {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteVertexArrays(1, &self.vao);
-            gl::DeleteProgram(self.program);
-            gl::DeleteShader(self.vertex_shader);
-            gl::DeleteShader(self.fragment_shader);
-        }
-    }
-}
 
-impl Shader for LightShader {
-    type Vertex = [f32; 4];
-    fn new(_: GLSL, gl: Option<&mut GlGraphics>) -> Self {
-        let gl = gl.unwrap();
-        let vertex_shader_compiled = compile_shader(gl::VERTEX_SHADER, VERTEX_SOURCE).expect("Vertex shader error");
-        let fragment_shader_compiled = compile_shader(gl::FRAGMENT_SHADER, FRAGMENT_SOURCE).expect("Fragment shader error");
+    pub fn from_sources(gl: &mut GlGraphics, preprocessor: &Preprocessor, vertex: &str, fragment: &str) -> Result<Self, String> {
+        let vertex = preprocessor.process(gl::VERTEX_SHADER, VERTEX_FILE, vertex)?;
+        let fragment = preprocessor.process(gl::FRAGMENT_SHADER, FRAGMENT_FILE, fragment)?;
+        let linked = link_program(&[(gl::VERTEX_SHADER, &vertex), (gl::FRAGMENT_SHADER, &fragment)])?;
+        let program = linked.program;
 
-        let program = unsafe {
-            let program = gl::CreateProgram();
-            gl::AttachShader(program, vertex_shader_compiled);
-            gl::AttachShader(program, fragment_shader_compiled);
-            program
-        };
+        let pos = DynamicAttribute::xyzw(program, "pos")?;
+        let color = DynamicAttribute::rgba(program, "color")?;
+        let normal = DynamicAttribute::xyz(program, "normal")?;
+        let uv = DynamicAttribute::uv(program, "uv")?;
+        gl.use_program(program);
+        let uniforms = (|| -> Result<_, String> {
+            let light_uni = gl.get_uniform("light").ok_or("Could not find light uniform")?;
+            light_uni.set(gl, &[0.0; 3]);
+            let projection_matrix_uni = gl.get_uniform("projection").ok_or("Could not find projection uniform")?;
+            projection_matrix_uni.set(gl, &[0.0; 16]);
+            let world_matrix_uni = gl.get_uniform("model").ok_or("Could not find model uniform")?;
+            world_matrix_uni.set(gl, &[0.0; 16]);
+            let view_matrix_uni = gl.get_uniform("view").ok_or("Could not find view uniform")?;
+            view_matrix_uni.set(gl, &[0.0; 16]);
+            let eye_uni = gl.get_uniform("eye").ok_or("Could not find eye uniform")?;
+            eye_uni.set(gl, &[0.0; 3]);
+            let light_colour_uni = gl.get_uniform("light_colour").ok_or("Could not find light colour uniform")?;
+            light_colour_uni.set(gl, &LIGHT_COLOUR);
+            let clip_plane_uni = gl.get_uniform("clip_plane").ok_or("Could not find clip plane uniform")?;
+            clip_plane_uni.set(gl, &NO_CLIP);
+            let fog_colour_uni = gl.get_uniform("fog_colour").ok_or("Could not find fog colour uniform")?;
+            fog_colour_uni.set(gl, &[0.0; 3]);
+            let fog_density_uni = gl.get_uniform("fog_density").ok_or("Could not find fog density uniform")?;
+            fog_density_uni.set(gl, 0.0);
+            Ok((
+                light_uni,
+                projection_matrix_uni,
+                world_matrix_uni,
+                view_matrix_uni,
+                eye_uni,
+                light_colour_uni,
+                clip_plane_uni,
+                fog_colour_uni,
+                fog_density_uni,
+            ))
+        })();
+        gl.clear_program();
+        let (
+            light_uni,
+            projection_matrix_uni,
+            world_matrix_uni,
+            view_matrix_uni,
+            eye_uni,
+            light_colour_uni,
+            clip_plane_uni,
+            fog_colour_uni,
+            fog_density_uni,
+        ) = uniforms?;
 
+        let (program, stages) = linked.release();
         let mut vao = 0;
         unsafe {
             gl::GenVertexArrays(1, &mut vao);
-            gl::LinkProgram(program);
         }
 
-        let pos = DynamicAttribute::xyzw(program, "pos").unwrap();
-        let color = DynamicAttribute::rgba(program, "color").unwrap();
-        let normal = DynamicAttribute::xyz(program, "normal").unwrap();
-        let uv = DynamicAttribute::uv(program, "uv").unwrap();
-        gl.use_program(program);
-        let light_uni = gl.get_uniform("light").expect("Could not find light uniform");
-        light_uni.set(gl, &[0.0; 3]);
-        let projection_matrix_uni = gl.get_uniform("projection").expect("Could not find projection uniform");
-        projection_matrix_uni.set(gl, &[0.0; 16]);
-        let world_matrix_uni = gl.get_uniform("model").expect("Could not find model uniform");
-        world_matrix_uni.set(gl, &[0.0; 16]);
-        let view_matrix_uni = gl.get_uniform("view").expect("Could not find view uniform");
-        view_matrix_uni.set(gl, &[0.0; 16]);
-        let eye_uni = gl.get_uniform("eye").expect("Could not find eye uniform");
-        eye_uni.set(gl, &[0.0; 3]);
-        let light_colour_uni = gl.get_uniform("light_colour").expect("Could not find light colour uniform");
-        light_colour_uni.set(gl, &[0.77, 0.61, 0.80, 1.0]);
-        gl.clear_program();
-
-        Self {
+        Ok(Self {
             // Shader items
             vao,
-            vertex_shader: vertex_shader_compiled,
-            fragment_shader: fragment_shader_compiled,
+            vertex_shader: stages[0],
+            fragment_shader: stages[1],
             program,
             // Per vertex items
             uv,
@@ -121,6 +142,7 @@ impl Shader for LightShader {
             normal_buffer: vec![[0.0; 3]; CHUNKS * BACK_END_MAX_VERTEX_COUNT],
             // Indices and offset
             indices: vec![0u16; 100],
+            element_buffer: ElementBuffer::new(),
             offset: 0,
             texture: 0,
             // Matrices and vectors
@@ -135,8 +157,44 @@ impl Shader for LightShader {
             light_uni,
             eye_uni,
             light_colour_uni,
+            clip_plane_uni,
+            fog_colour_uni,
+            fog_density_uni,
+        })
+    }
+
+    /// Swaps in a program built from new sources, keeping the current one if
+    /// they fail to compile or link.
+    pub fn reload(&mut self, gl: &mut GlGraphics, preprocessor: &Preprocessor, vertex: &str, fragment: &str) -> Result<(), String> {
+        let mut fresh = Self::from_sources(gl, preprocessor, vertex, fragment)?;
+        fresh.world = self.world;
+        fresh.view = self.view;
+        fresh.projection = self.projection;
+        fresh.eye = self.eye;
+        *self = fresh;
+        Ok(())
+    }
+}
+
+impl Drop for LightShader {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.program);
+            gl::DeleteShader(self.vertex_shader);
+            gl::DeleteShader(self.fragment_shader);
         }
     }
+}
+
+impl Shader for LightShader {
+    type Vertex = [f32; 4];
+    fn new(glsl: GLSL, gl: Option<&mut GlGraphics>) -> Self {
+        let gl = gl.unwrap();
+        Target::for_context(glsl)
+            .and_then(|target| Self::from_sources(gl, &Preprocessor::shared(Includes::Baked, target), VERTEX_SOURCE, FRAGMENT_SOURCE))
+            .expect("Light shader error")
+    }
 
     fn flush(&mut self) {
         unsafe {
@@ -152,7 +210,7 @@ impl Shader for LightShader {
             self.normal.set(&self.normal_buffer[..self.offset]);
             self.uv.bind_vao(self.vao);
             self.uv.set(&self.uv_buffer[..self.offset]);
-            gl::DrawElements(gl::TRIANGLES, self.indices.len() as i32, gl::UNSIGNED_SHORT, self.indices.as_ptr() as *const _);
+            self.element_buffer.draw_triangles(&self.indices);
             gl::BindVertexArray(0);
             self.indices.clear();
         }
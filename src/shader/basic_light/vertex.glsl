@@ -1,4 +1,3 @@
-#version 320 es
 uniform mat4x4 projection;
 uniform mat4x4 model;
 uniform mat4x4 view;
@@ -12,6 +11,7 @@ out vec4 v_Color;
 out vec3 v_Pos;
 out vec3 v_Normal;
 out vec2 v_UV;
+out vec3 v_WorldPos;
 
 void main() {
     gl_Position = projection * view * model * pos;
@@ -19,4 +19,5 @@ void main() {
     v_Normal = normalize((view * model * vec4(normal, 0.0)).xyz);
     v_Pos = vec3(view * model * pos);
     v_UV = uv;
+    v_WorldPos = vec3(model * pos);
 }
@@ -1,37 +1,90 @@
-#version 320 es
-precision mediump float;
 uniform vec3 light;
 uniform vec3 eye;
 uniform highp mat4x4 view;
 uniform highp mat4x4 model;
+uniform highp mat4x4 projection;
 uniform vec4 light_colour;
+uniform mediump sampler3D perlin_source;
+// The scene mirrored in and seen through the water, rendered from this
+// camera; only sampled when `reflections` is 1.
+uniform sampler2D reflection_source;
+uniform sampler2D refraction_source;
+// Depth of the refracted scene, for how much water each fragment looks
+// through.
+uniform highp sampler2D scene_depth;
+uniform float reflections;
+uniform vec3 shallow_colour;
+uniform vec3 deep_colour;
+// Water shallower than this foams.
+uniform float foam_depth;
+
+// How far the noise pushes the reflected and refracted images, in screen
+// space.
+#define DISTORTION 0.02
+// Reflectance looking straight down on water.
+#define WATER_F0 0.02
+// Depth over which the shallow colour gives way to the deep one.
+#define DEPTH_FALLOFF 1.5
+// Foam bands between the shore and `foam_depth`, and how fast they wash in.
+#define FOAM_BANDS 3.0
+#define FOAM_SPEED 0.4
 
 in VertexData {
     vec4 f_Color;
     vec3 f_Pos;
     vec3 f_Normal;
+    vec3 f_Noise;
+    vec4 f_Clip;
     float f_Opacity;
 } v_Out;
 out vec4 outColor;
 
+#include "lighting.glsl"
+#include "fog.glsl"
+// For `wave_time`.
+#include "gerstner.glsl"
+
+// Distance in front of the camera of a depth buffer value.
+float view_distance(float depth) {
+    float ndc = depth * 2.0 - 1.0;
+    return projection[3][2] / (ndc + projection[2][2]);
+}
+
 void main() {
-    // https://www.desmos.com/calculator/rd2ohuwwzl
     vec4 color = v_Out.f_Color;
-    vec3 P = v_Out.f_Pos;
-    vec3 E = eye;
-    vec3 e_n = normalize(E - P);
-    vec3 n = v_Out.f_Normal;
+    Lighting lighting = phong(v_Out.f_Pos, v_Out.f_Normal, eye, light, view);
 
-    vec3 L = (view * vec4(light, 1.0)).xyz;
-
-    vec3 l_n = normalize(P - L); // -norm(L - P);
+    outColor.a = 1.0;
+    outColor.rgb = (lighting.specular * light_colour).rgb;
+    outColor.rgb += lighting.diffuse * color.a * color.rgb;
+    outColor.a = max(lighting.specular, lighting.diffuse * color.a);
 
-    vec3 l_r = reflect(l_n, n);
+    if (reflections > 0.5) {
+        vec2 screen = v_Out.f_Clip.xy / v_Out.f_Clip.w * 0.5 + 0.5;
+        vec2 distortion = vec2(
+            texture(perlin_source, v_Out.f_Noise).r,
+            texture(perlin_source, v_Out.f_Noise.zxy).r
+        ) * 2.0 - 1.0;
+        distortion *= DISTORTION;
+        // The mirrored scene lines up with its reflection on screen.
+        vec3 reflected = texture(reflection_source, screen + distortion).rgb;
+        vec3 refracted = texture(refraction_source, screen + distortion).rgb;
+        // Schlick's approximation, the normal may face either way.
+        float facing = abs(dot(normalize(-v_Out.f_Pos), v_Out.f_Normal));
+        float fresnel = WATER_F0 + (1.0 - WATER_F0) * pow(1.0 - facing, 5.0);
+        float depth = max(view_distance(texture(scene_depth, screen).r) + v_Out.f_Pos.z, 0.0);
+        float deepness = 1.0 - exp(-depth / DEPTH_FALLOFF);
+        vec3 tint = mix(shallow_colour, deep_colour, deepness);
+        vec3 water = mix(refracted, tint, mix(color.a, 1.0, deepness));
+        outColor.rgb = mix(water, reflected, fresnel) + (lighting.specular * light_colour).rgb;
+        outColor.a = 1.0;
 
-    float b_spec = clamp(dot(e_n, l_r), 0.0, 1.0);
-    float b_diff = clamp(dot(n, l_r) + 0.2, 0.2, 1.0);
-    outColor.a = 1.0;
-    outColor.rgb = (b_spec * light_colour).rgb;
-    outColor.rgb += b_diff * color.a * color.rgb;
-    outColor.a = max(b_spec, b_diff * color.a);
+        // Bands washing in towards the shore, broken up by the noise.
+        float shore = 1.0 - clamp(depth / foam_depth, 0.0, 1.0);
+        float bands = 0.5 + 0.5 * sin((depth / foam_depth * FOAM_BANDS + wave_time * FOAM_SPEED) * TAU);
+        float noise = texture(perlin_source, v_Out.f_Noise * 4.0).r;
+        float foam = smoothstep(0.55, 0.8, bands * 0.5 + noise * 0.5 + shore * 0.3) * shore;
+        outColor.rgb = mix(outColor.rgb, light_colour.rgb, foam);
+    }
+    outColor.rgb = apply_fog(outColor.rgb, v_Out.f_Pos);
 }
@@ -1,4 +1,3 @@
-#version 320 es
 layout(triangles) in;
 layout(triangle_strip, max_vertices = 3) out;
 //layout(line_strip, max_vertices = 4) out;
@@ -6,6 +5,8 @@ layout(triangle_strip, max_vertices = 3) out;
 in PerVertex {
     vec4 g_Color;
     vec3 g_Pos;
+    vec3 g_Noise;
+    vec4 g_Clip;
     float f_Opacity;
 } v_In[];
 
@@ -13,6 +14,8 @@ out VertexData {
     vec4 f_Color;
     vec3 f_Pos;
     vec3 f_Normal;
+    vec3 f_Noise;
+    vec4 f_Clip;
     float f_Opacity;
 } v_Out;
 
@@ -21,18 +24,24 @@ void main() {
 
     v_Out.f_Color = v_In[0].g_Color;
     v_Out.f_Pos = v_In[0].g_Pos;
+    v_Out.f_Noise = v_In[0].g_Noise;
+    v_Out.f_Clip = v_In[0].g_Clip;
     v_Out.f_Opacity = v_In[0].f_Opacity;
     gl_Position = gl_in[0].gl_Position;
     EmitVertex();
 
     v_Out.f_Color = v_In[1].g_Color;
     v_Out.f_Pos = v_In[1].g_Pos;
+    v_Out.f_Noise = v_In[1].g_Noise;
+    v_Out.f_Clip = v_In[1].g_Clip;
     v_Out.f_Opacity = v_In[1].f_Opacity;
     gl_Position = gl_in[1].gl_Position;
     EmitVertex();
 
     v_Out.f_Color = v_In[2].g_Color;
     v_Out.f_Pos = v_In[2].g_Pos;
+    v_Out.f_Noise = v_In[2].g_Noise;
+    v_Out.f_Clip = v_In[2].g_Clip;
     v_Out.f_Opacity = v_In[2].f_Opacity;
     gl_Position = gl_in[2].gl_Position;
     EmitVertex();
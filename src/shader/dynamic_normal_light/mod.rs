@@ -1,12 +1,16 @@
-use opengl_graphics::shader_utils::{Shader, DynamicAttribute, compile_shader};
+use opengl_graphics::shader_utils::{Shader, DynamicAttribute};
 use opengl_graphics::gl::types::GLuint;
 use opengl_graphics::{gl, GlGraphics};
 use opengl_graphics::GLSL;
 use graphics::BACK_END_MAX_VERTEX_COUNT;
-use opengl_graphics::shader_uniforms::{ShaderUniform, SUMat4x4, SUVec3, SUVec4};
+use opengl_graphics::shader_uniforms::{ShaderUniform, SUFloat, SUMat4x4, SUVec3, SUVec4};
 use cgmath::{Matrix4, SquareMatrix, Vector3, Rad};
-use crate::shader::Texture3D;
+use crate::shader::{Texture3D, ElementBuffer, link_program, Preprocessor, Includes, Target, LIGHT_COLOUR};
+use crate::drawable::MAX_WAVES;
 
+pub const FRAGMENT_FILE: &'static str = "dynamic_normal_light/fragment.glsl";
+pub const VERTEX_FILE: &'static str = "dynamic_normal_light/vertex.glsl";
+pub const GEOMETRY_FILE: &'static str = "dynamic_normal_light/geometry.glsl";
 const FRAGMENT_SOURCE: &'static str = include_str!("./fragment.glsl");
 const VERTEX_SOURCE: &'static str = include_str!("./vertex.glsl");
 const GEOMETRY_SOURCE: &'static str = include_str!("./geometry.glsl");
@@ -27,6 +31,7 @@ pub struct WaterLight {
     color_buffer: Vec<[f32; 4]>,
     // Indices and the offset
     indices: Vec<u16>,
+    element_buffer: ElementBuffer,
     offset: usize,
     // Matrices and other items
     pub world: Matrix4<f32>,
@@ -41,8 +46,29 @@ pub struct WaterLight {
     pub eye_uni: ShaderUniform<SUVec3>,
     pub light_colour_uni: ShaderUniform<SUVec4>,
     pub scaler_matrix_uni: ShaderUniform<SUMat4x4>,
+    // Gerstner waves, see `gerstner.glsl`
+    pub wave_shape_unis: Vec<ShaderUniform<SUVec4>>,
+    pub wave_motion_unis: Vec<ShaderUniform<SUVec4>>,
+    pub wave_count_uni: ShaderUniform<SUFloat>,
+    pub wave_time_uni: ShaderUniform<SUFloat>,
+    pub ripple_extent_uni: ShaderUniform<SUFloat>,
+    pub ripple_range_uni: ShaderUniform<SUFloat>,
+    pub reflections_uni: ShaderUniform<SUFloat>,
+    pub shallow_colour_uni: ShaderUniform<SUVec3>,
+    pub deep_colour_uni: ShaderUniform<SUVec3>,
+    pub foam_depth_uni: ShaderUniform<SUFloat>,
+    pub fog_colour_uni: ShaderUniform<SUVec3>,
+    pub fog_density_uni: ShaderUniform<SUFloat>,
     // Water perlin noise texture data:
     pub perlin: Texture3D,
+    // Ripple heights, bound to the second texture unit:
+    pub ripples: Texture3D,
+    // Colour textures of the reflection and refraction passes and the
+    // refraction's depth, bound to the third to fifth units; 0 when there
+    // are none.
+    pub reflection: GLuint,
+    pub refraction: GLuint,
+    pub refraction_depth: GLuint,
 }
 
 impl WaterLight {
@@ -53,65 +79,138 @@ impl WaterLight {
     pub fn rotate_eye(&mut self, value: Vector3<f32>) {
         self.view = self.view * Matrix4::from_angle_x(Rad(value.x)) * Matrix4::from_angle_y(Rad(value.y)) * Matrix4::from_angle_z(Rad(value.z));
     }
-}
 
-impl Drop for WaterLight {
-    fn drop(&mut self) {
-        unsafe {
-            gl::DeleteVertexArrays(1, &self.vao);
-            gl::DeleteProgram(self.program);
-            gl::DeleteShader(self.vertex_shader);
-            gl::DeleteShader(self.geometry_shader);
-            gl::DeleteShader(self.fragment_shader);
-        }
-    }
-}
+    pub fn from_sources(
+        gl: &mut GlGraphics,
+        preprocessor: &Preprocessor,
+        vertex: &str,
+        geometry: &str,
+        fragment: &str,
+    ) -> Result<Self, String> {
+        let vertex = preprocessor.process(gl::VERTEX_SHADER, VERTEX_FILE, vertex)?;
+        let geometry = preprocessor.process(gl::GEOMETRY_SHADER, GEOMETRY_FILE, geometry)?;
+        let fragment = preprocessor.process(gl::FRAGMENT_SHADER, FRAGMENT_FILE, fragment)?;
+        let linked = link_program(&[
+            (gl::VERTEX_SHADER, &vertex),
+            (gl::GEOMETRY_SHADER, &geometry),
+            (gl::FRAGMENT_SHADER, &fragment),
+        ])?;
+        let program = linked.program;
 
-impl Shader for WaterLight {
-    type Vertex = [f32; 4];
-    fn new(_: GLSL, gl: Option<&mut GlGraphics>) -> Self {
-        let gl = gl.unwrap();
-        let vertex_shader_compiled = compile_shader(gl::VERTEX_SHADER, VERTEX_SOURCE).expect("Vertex shader error");
-        let fragment_shader_compiled = compile_shader(gl::FRAGMENT_SHADER, FRAGMENT_SOURCE).expect("Fragment shader error");
-        let geometry_shader_compiled = compile_shader(gl::GEOMETRY_SHADER, GEOMETRY_SOURCE).expect("Geometry shader error");
-        let program = unsafe {
-            let program = gl::CreateProgram();
-            gl::AttachShader(program, vertex_shader_compiled);
-            gl::AttachShader(program, geometry_shader_compiled);
-            gl::AttachShader(program, fragment_shader_compiled);
-            program
-        };
+        let pos = DynamicAttribute::xyzw(program, "v_Pos")?;
+        let color = DynamicAttribute::rgba(program, "v_Color")?;
+        gl.use_program(program);
+        let uniforms = (|| -> Result<_, String> {
+            let light_uni = gl.get_uniform("light").ok_or("Could not find light uniform")?;
+            light_uni.set(gl, &[0.0; 3]);
+            let projection_matrix_uni = gl.get_uniform("projection").ok_or("Could not find projection uniform")?;
+            projection_matrix_uni.set(gl, &[0.0; 16]);
+            let world_matrix_uni = gl.get_uniform("model").ok_or("Could not find model uniform")?;
+            world_matrix_uni.set(gl, &[0.0; 16]);
+            let view_matrix_uni = gl.get_uniform("view").ok_or("Could not find view uniform")?;
+            view_matrix_uni.set(gl, &[0.0; 16]);
+            let eye_uni = gl.get_uniform("eye").ok_or("Could not find eye uniform")?;
+            eye_uni.set(gl, &[0.0; 3]);
+            let light_colour_uni = gl.get_uniform("light_colour").ok_or("Could not find light colour uniform")?;
+            light_colour_uni.set(gl, &LIGHT_COLOUR);
+            let scaler_matrix_uni = gl.get_uniform("texture_scaler").ok_or("Could not find texture scaler uniform")?;
+            scaler_matrix_uni.set(gl, &[0.0; 16]);
+            let mut wave_shape_unis = Vec::with_capacity(MAX_WAVES);
+            let mut wave_motion_unis = Vec::with_capacity(MAX_WAVES);
+            for i in 0..MAX_WAVES {
+                let shape = gl.get_uniform(&format!("wave_shape[{}]", i)).ok_or("Could not find wave shape uniform")?;
+                // A flat wave, since a zero wavelength would divide by zero.
+                shape.set(gl, &[1.0, 0.0, 0.0, 1.0]);
+                wave_shape_unis.push(shape);
+                let motion = gl.get_uniform(&format!("wave_motion[{}]", i)).ok_or("Could not find wave motion uniform")?;
+                motion.set(gl, &[0.0; 4]);
+                wave_motion_unis.push(motion);
+            }
+            let wave_count_uni = gl.get_uniform("wave_count").ok_or("Could not find wave count uniform")?;
+            wave_count_uni.set(gl, 0.0);
+            let wave_time_uni = gl.get_uniform("wave_time").ok_or("Could not find wave time uniform")?;
+            wave_time_uni.set(gl, 0.0);
+            let ripple_extent_uni = gl.get_uniform("ripple_extent").ok_or("Could not find ripple extent uniform")?;
+            ripple_extent_uni.set(gl, 1.0);
+            let ripple_range_uni = gl.get_uniform("ripple_range").ok_or("Could not find ripple range uniform")?;
+            ripple_range_uni.set(gl, 0.0);
+            let reflections_uni = gl.get_uniform("reflections").ok_or("Could not find reflections uniform")?;
+            reflections_uni.set(gl, 0.0);
+            let shallow_colour_uni = gl.get_uniform("shallow_colour").ok_or("Could not find shallow colour uniform")?;
+            shallow_colour_uni.set(gl, &[0.0; 3]);
+            let deep_colour_uni = gl.get_uniform("deep_colour").ok_or("Could not find deep colour uniform")?;
+            deep_colour_uni.set(gl, &[0.0; 3]);
+            let foam_depth_uni = gl.get_uniform("foam_depth").ok_or("Could not find foam depth uniform")?;
+            foam_depth_uni.set(gl, 1.0);
+            let fog_colour_uni = gl.get_uniform("fog_colour").ok_or("Could not find fog colour uniform")?;
+            fog_colour_uni.set(gl, &[0.0; 3]);
+            let fog_density_uni = gl.get_uniform("fog_density").ok_or("Could not find fog density uniform")?;
+            fog_density_uni.set(gl, 0.0);
+            // Texture units for the samplers.
+            unsafe {
+                gl::Uniform1i(gl::GetUniformLocation(program, b"perlin_source\0".as_ptr() as *const _), 0);
+                gl::Uniform1i(gl::GetUniformLocation(program, b"ripple_height\0".as_ptr() as *const _), 1);
+                gl::Uniform1i(gl::GetUniformLocation(program, b"reflection_source\0".as_ptr() as *const _), 2);
+                gl::Uniform1i(gl::GetUniformLocation(program, b"refraction_source\0".as_ptr() as *const _), 3);
+                gl::Uniform1i(gl::GetUniformLocation(program, b"scene_depth\0".as_ptr() as *const _), 4);
+            }
+            Ok((
+                light_uni,
+                projection_matrix_uni,
+                world_matrix_uni,
+                view_matrix_uni,
+                eye_uni,
+                light_colour_uni,
+                scaler_matrix_uni,
+                wave_shape_unis,
+                wave_motion_unis,
+                wave_count_uni,
+                wave_time_uni,
+                ripple_extent_uni,
+                ripple_range_uni,
+                reflections_uni,
+                shallow_colour_uni,
+                deep_colour_uni,
+                foam_depth_uni,
+                fog_colour_uni,
+                fog_density_uni,
+            ))
+        })();
+        gl.clear_program();
+        let (
+            light_uni,
+            projection_matrix_uni,
+            world_matrix_uni,
+            view_matrix_uni,
+            eye_uni,
+            light_colour_uni,
+            scaler_matrix_uni,
+            wave_shape_unis,
+            wave_motion_unis,
+            wave_count_uni,
+            wave_time_uni,
+            ripple_extent_uni,
+            ripple_range_uni,
+            reflections_uni,
+            shallow_colour_uni,
+            deep_colour_uni,
+            foam_depth_uni,
+            fog_colour_uni,
+            fog_density_uni,
+        ) = uniforms?;
+
+        let (program, stages) = linked.release();
         let mut vao = 0;
         unsafe {
             gl::GenVertexArrays(1, &mut vao);
-            gl::LinkProgram(program);
         }
 
-        let pos = DynamicAttribute::xyzw(program, "v_Pos").unwrap();
-        let color = DynamicAttribute::rgba(program, "v_Color").unwrap();
-        gl.use_program(program);
-        let light_uni = gl.get_uniform("light").expect("Could not find light uniform");
-        light_uni.set(gl, &[0.0; 3]);
-        let projection_matrix_uni = gl.get_uniform("projection").expect("Could not find projection uniform");
-        projection_matrix_uni.set(gl, &[0.0; 16]);
-        let world_matrix_uni = gl.get_uniform("model").expect("Could not find model uniform");
-        world_matrix_uni.set(gl, &[0.0; 16]);
-        let view_matrix_uni = gl.get_uniform("view").expect("Could not find view uniform");
-        view_matrix_uni.set(gl, &[0.0; 16]);
-        let eye_uni = gl.get_uniform("eye").expect("Could not find eye uniform");
-        eye_uni.set(gl, &[0.0; 3]);
-        let light_colour_uni = gl.get_uniform("light_colour").expect("Could not find light colour uniform");
-        light_colour_uni.set(gl, &[0.77, 0.61, 0.80, 1.0]);
-        let scaler_matrix_uni = gl.get_uniform("texture_scaler").expect("Could not find texture scaler uniform");
-        scaler_matrix_uni.set(gl, &[0.0; 16]);
-        gl.clear_program();
-
-        Self {
+        Ok(Self {
             // Shader items
             vao,
-            vertex_shader: vertex_shader_compiled,
-            geometry_shader: geometry_shader_compiled,
-            fragment_shader: fragment_shader_compiled,
+            vertex_shader: stages[0],
+            geometry_shader: stages[1],
+            fragment_shader: stages[2],
             program,
             // Per vertex items
             pos,
@@ -120,6 +219,7 @@ impl Shader for WaterLight {
             color_buffer: vec![[0.0; 4]; CHUNKS * BACK_END_MAX_VERTEX_COUNT],
             // Indices and offset
             indices: vec![0u16; 100],
+            element_buffer: ElementBuffer::new(),
             offset: 0,
             // Matrices and vectors
             world: Matrix4::from_translation(Vector3::new(0., 0., 100.)),
@@ -134,21 +234,94 @@ impl Shader for WaterLight {
             eye_uni,
             light_colour_uni,
             scaler_matrix_uni,
-            perlin: Texture3D::empty().unwrap(),
+            wave_shape_unis,
+            wave_motion_unis,
+            wave_count_uni,
+            wave_time_uni,
+            ripple_extent_uni,
+            ripple_range_uni,
+            reflections_uni,
+            shallow_colour_uni,
+            deep_colour_uni,
+            foam_depth_uni,
+            fog_colour_uni,
+            fog_density_uni,
+            // Flat water (mid-grey is no displacement) until the real noise
+            // has been generated.
+            perlin: Texture3D::from_data(&[128], [1; 3])?,
+            ripples: Texture3D::from_data(&[128], [1; 3])?,
+            reflection: 0,
+            refraction: 0,
+            refraction_depth: 0,
+        })
+    }
+
+    /// Swaps in a program built from new sources, keeping the current one if
+    /// they fail to compile or link. The noise and ripple textures carry over.
+    pub fn reload(
+        &mut self,
+        gl: &mut GlGraphics,
+        preprocessor: &Preprocessor,
+        vertex: &str,
+        geometry: &str,
+        fragment: &str,
+    ) -> Result<(), String> {
+        let mut fresh = Self::from_sources(gl, preprocessor, vertex, geometry, fragment)?;
+        fresh.world = self.world;
+        fresh.view = self.view;
+        fresh.projection = self.projection;
+        fresh.eye = self.eye;
+        std::mem::swap(&mut fresh.perlin, &mut self.perlin);
+        std::mem::swap(&mut fresh.ripples, &mut self.ripples);
+        fresh.reflection = self.reflection;
+        fresh.refraction = self.refraction;
+        fresh.refraction_depth = self.refraction_depth;
+        *self = fresh;
+        Ok(())
+    }
+}
+
+impl Drop for WaterLight {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.program);
+            gl::DeleteShader(self.vertex_shader);
+            gl::DeleteShader(self.geometry_shader);
+            gl::DeleteShader(self.fragment_shader);
         }
     }
+}
+
+impl Shader for WaterLight {
+    type Vertex = [f32; 4];
+    fn new(glsl: GLSL, gl: Option<&mut GlGraphics>) -> Self {
+        let gl = gl.unwrap();
+        Target::for_context(glsl)
+            .and_then(|target| Self::from_sources(gl, &Preprocessor::shared(Includes::Baked, target), VERTEX_SOURCE, GEOMETRY_SOURCE, FRAGMENT_SOURCE))
+            .expect("Water shader error")
+    }
 
     fn flush(&mut self) {
         unsafe {
             gl::BindVertexArray(self.vao);
             gl::Disable(gl::CULL_FACE);
+            gl::ActiveTexture(gl::TEXTURE4);
+            gl::BindTexture(gl::TEXTURE_2D, self.refraction_depth);
+            gl::ActiveTexture(gl::TEXTURE3);
+            gl::BindTexture(gl::TEXTURE_2D, self.refraction);
+            gl::ActiveTexture(gl::TEXTURE2);
+            gl::BindTexture(gl::TEXTURE_2D, self.reflection);
+            gl::ActiveTexture(gl::TEXTURE1);
+            gl::BindTexture(gl::TEXTURE_3D, self.ripples.get_id());
+            gl::ActiveTexture(gl::TEXTURE0);
             gl::BindTexture(gl::TEXTURE_3D, self.perlin.get_id());
 //            gl::Enable(gl::DEPTH_TEST);
             self.color.bind_vao(self.vao);
             self.color.set(&self.color_buffer[..self.offset]);
             self.pos.bind_vao(self.vao);
             self.pos.set(&self.pos_buffer[..self.offset]);
-            gl::DrawElements(gl::TRIANGLES, self.indices.len() as i32, gl::UNSIGNED_SHORT, self.indices.as_ptr() as *const _);
+            self.element_buffer.draw_triangles(&self.indices);
             gl::BindVertexArray(0);
             self.indices.clear();
         }
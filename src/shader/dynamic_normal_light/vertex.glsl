@@ -1,9 +1,15 @@
-#version 320 es
 uniform mat4x4 projection;
 uniform mat4x4 model;
 uniform mat4x4 view;
 uniform mediump sampler3D perlin_source;
 uniform mat4x4 texture_scaler;
+// A single layer of ripple heights over the water, see `drawable::Ripples`.
+uniform mediump sampler3D ripple_height;
+// Half the width in world units the ripples cover, and their largest height.
+uniform float ripple_extent;
+uniform float ripple_range;
+
+#include "gerstner.glsl"
 
 in vec4 v_Color;
 in vec4 v_Pos;
@@ -12,16 +18,28 @@ in float v_Opacity;
 out PerVertex {
     vec4 g_Color;
     vec3 g_Pos;
+    vec3 g_Noise;
+    vec4 g_Clip;
     float f_Opacity;
 } v_In;
 
+float ripple_offset(vec2 p) {
+    vec2 uv = p / (2.0 * ripple_extent) + 0.5;
+    return (texture(ripple_height, vec3(uv, 0.5)).r - 0.5) * 2.0 * ripple_range;
+}
+
 void main() {
     vec3 perlin_pos = (texture_scaler * v_Pos).xyz;
-    vec4 pos = v_Pos;
-    pos.y += texture(perlin_source, perlin_pos).r - 0.5;
-//    pos.xyz = perlin_pos;
-    gl_Position = projection * view * model * pos;
+    vec4 world_pos = model * v_Pos;
+    // NOISE_HEIGHT is defined by `Preprocessor::shared` from
+    // `drawable::NOISE_HEIGHT`.
+    world_pos.y += (texture(perlin_source, perlin_pos).r - 0.5) * 2.0 * NOISE_HEIGHT;
+    world_pos.y += ripple_offset(world_pos.xz);
+    world_pos.xyz += gerstner_offset(world_pos.xz);
+    gl_Position = projection * view * world_pos;
     v_In.g_Color = v_Color;
-    v_In.g_Pos = vec3(view * model * pos);
+    v_In.g_Pos = vec3(view * world_pos);
+    v_In.g_Noise = perlin_pos;
+    v_In.g_Clip = gl_Position;
     v_In.f_Opacity = v_Opacity;
 }
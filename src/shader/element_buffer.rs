@@ -0,0 +1,41 @@
+use opengl_graphics::gl::types::GLuint;
+use opengl_graphics::gl;
+use std::mem::size_of;
+
+/// Buffer the indices are streamed through for `DrawElements`.
+///
+/// GLES will read indices straight out of client memory, but desktop core
+/// profiles only take them from a bound element buffer.
+pub struct ElementBuffer {
+    id: GLuint,
+}
+
+impl ElementBuffer {
+    pub fn new() -> Self {
+        let mut id = 0;
+        unsafe {
+            gl::GenBuffers(1, &mut id);
+        }
+        Self { id }
+    }
+
+    /// Uploads `indices` and draws them as triangles. The VAO they belong
+    /// to must be bound, since it keeps hold of the element buffer binding.
+    pub unsafe fn draw_triangles(&self, indices: &[u16]) {
+        gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, self.id);
+        gl::BufferData(
+            gl::ELEMENT_ARRAY_BUFFER,
+            (indices.len() * size_of::<u16>()) as _,
+            indices.as_ptr() as *const _,
+            gl::STREAM_DRAW);
+        gl::DrawElements(gl::TRIANGLES, indices.len() as i32, gl::UNSIGNED_SHORT, std::ptr::null());
+    }
+}
+
+impl Drop for ElementBuffer {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteBuffers(1, &self.id);
+        }
+    }
+}
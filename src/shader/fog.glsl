@@ -0,0 +1,10 @@
+// Exponential fog towards the sky colour, shared between the lit shaders.
+uniform vec3 fog_colour;
+// Fraction of the light lost per world unit, roughly.
+uniform float fog_density;
+
+// view_pos is in view space.
+vec3 apply_fog(vec3 colour, vec3 view_pos) {
+    float fog = 1.0 - exp(-fog_density * length(view_pos));
+    return mix(colour, fog_colour, fog);
+}
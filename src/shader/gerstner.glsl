@@ -0,0 +1,36 @@
+// Sum of Gerstner waves, mirrored on the CPU by `drawable::Waves`; keep the
+// two in step. MAX_WAVES is defined by `Preprocessor::shared` from
+// `drawable::MAX_WAVES`.
+//
+// The uniforms are all highp, so that a fragment shader including this for
+// `wave_time` declares them just as the vertex shader does, and time keeps
+// its precision as it grows.
+#ifndef MAX_WAVES
+#error MAX_WAVES should be defined by the preprocessor
+#endif
+#define GRAVITY 9.81
+#define TAU 6.28318530718
+
+// xy: unit direction, z: amplitude, w: wavelength. Unused waves have no
+// amplitude or steepness.
+uniform highp vec4 wave_shape[MAX_WAVES];
+// x: steepness, y: phase.
+uniform highp vec4 wave_motion[MAX_WAVES];
+uniform highp float wave_count;
+uniform highp float wave_time;
+
+// How far the still water at world position p has moved.
+highp vec3 gerstner_offset(highp vec2 p) {
+    highp vec3 offset = vec3(0.0);
+    for (int i = 0; i < MAX_WAVES; i++) {
+        highp vec2 direction = wave_shape[i].xy;
+        highp float amplitude = wave_shape[i].z;
+        highp float k = TAU / wave_shape[i].w;
+        highp float speed = sqrt(GRAVITY / k);
+        highp float angle = k * (dot(direction, p) - speed * wave_time) + wave_motion[i].y;
+        highp float sideways = wave_motion[i].x / (k * max(wave_count, 1.0));
+        offset.xz += direction * sideways * cos(angle);
+        offset.y += amplitude * sin(angle);
+    }
+    return offset;
+}
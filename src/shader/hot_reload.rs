@@ -0,0 +1,150 @@
+use std::fs;
+use std::io;
+use std::marker::PhantomData;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use android_base::{Drawable, ViewProj, Transforms, ShaderContext};
+use opengl_graphics::{GlGraphics, GLSL};
+use opengl_graphics::shader_utils::Shader;
+use graphics::Context;
+use crate::shader::{LightShader, WaterLight, Xyz, ParticleShader, Preprocessor, Includes, Target, include_names};
+use crate::shader::{axis_bars, basic_light, dynamic_normal_light, particles};
+
+/// A shader whose sources can be swapped out while the app is running.
+pub trait HotReload: Shader {
+    /// Source files, relative to `src/shader`, in the order `reload` takes them.
+    const FILES: &'static [&'static str];
+
+    fn reload(&mut self, gl: &mut GlGraphics, preprocessor: &Preprocessor, sources: &[String]) -> Result<(), String>;
+}
+
+impl HotReload for LightShader {
+    const FILES: &'static [&'static str] = &[basic_light::VERTEX_FILE, basic_light::FRAGMENT_FILE];
+
+    fn reload(&mut self, gl: &mut GlGraphics, preprocessor: &Preprocessor, sources: &[String]) -> Result<(), String> {
+        LightShader::reload(self, gl, preprocessor, &sources[0], &sources[1])
+    }
+}
+
+impl HotReload for WaterLight {
+    const FILES: &'static [&'static str] = &[
+        dynamic_normal_light::VERTEX_FILE,
+        dynamic_normal_light::GEOMETRY_FILE,
+        dynamic_normal_light::FRAGMENT_FILE,
+    ];
+
+    fn reload(&mut self, gl: &mut GlGraphics, preprocessor: &Preprocessor, sources: &[String]) -> Result<(), String> {
+        WaterLight::reload(self, gl, preprocessor, &sources[0], &sources[1], &sources[2])
+    }
+}
+
+impl HotReload for Xyz {
+    const FILES: &'static [&'static str] = &[axis_bars::VERTEX_FILE, axis_bars::FRAGMENT_FILE];
+
+    fn reload(&mut self, gl: &mut GlGraphics, preprocessor: &Preprocessor, sources: &[String]) -> Result<(), String> {
+        Xyz::reload(self, gl, preprocessor, &sources[0], &sources[1])
+    }
+}
+
+impl HotReload for ParticleShader {
+    const FILES: &'static [&'static str] = &[particles::VERTEX_FILE, particles::FRAGMENT_FILE];
+
+    fn reload(&mut self, gl: &mut GlGraphics, preprocessor: &Preprocessor, sources: &[String]) -> Result<(), String> {
+        ParticleShader::reload(self, gl, preprocessor, &sources[0], &sources[1])
+    }
+}
+
+struct WatchedFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+}
+
+impl WatchedFile {
+    fn poll(&mut self) -> bool {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok();
+        let changed = modified.is_some() && modified != self.modified;
+        self.modified = modified;
+        changed
+    }
+}
+
+/// Watches the sources of `S`, and anything they could `#include`, on disk
+/// and recompiles it when one of them changes.
+///
+/// It's drawn like any other drawable so that it gets handed the live shader
+/// out of the `ShaderStorage`; it doesn't put anything on screen.
+pub struct Reloader<S> {
+    /// `S::FILES` followed by the includes.
+    files: Vec<WatchedFile>,
+    preprocessor: Preprocessor,
+    _shader: PhantomData<S>,
+}
+
+impl<S: HotReload> Reloader<S> {
+    pub fn new(glsl: GLSL) -> Result<Self, String> {
+        let target = Target::for_context(glsl)?;
+        let root = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src").join("shader");
+        let mut files = S::FILES
+            .iter()
+            .cloned()
+            .chain(include_names())
+            .map(|file| WatchedFile { path: root.join(file), modified: None })
+            .collect::<Vec<_>>();
+        // The baked in sources are current at startup, so only later edits count.
+        for file in &mut files {
+            file.poll();
+        }
+        Ok(Self {
+            files,
+            preprocessor: Preprocessor::shared(Includes::Directory(root), target),
+            _shader: PhantomData,
+        })
+    }
+
+    fn read(&self) -> io::Result<Vec<String>> {
+        self.files[..S::FILES.len()].iter().map(|file| fs::read_to_string(&file.path)).collect()
+    }
+}
+
+impl<S: HotReload + 'static> Drawable for Reloader<S> {
+    type Shader = S;
+    fn draw_with(&mut self, shader: &mut S, graphics: &mut GlGraphics, _context: &Context, _cache: &mut ViewProj, _transforms: &mut Transforms) {
+        // Poll everything so that each edit is only picked up once.
+        let changed = self.files.iter_mut().fold(false, |changed, file| file.poll() | changed);
+        if !changed {
+            return;
+        }
+        let result = self.read()
+            .map_err(|e| e.to_string())
+            .and_then(|sources| shader.reload(graphics, &self.preprocessor, &sources));
+        match result {
+            Ok(()) => println!("Reloaded {:?}", S::FILES),
+            Err(e) => println!("Keeping previous program for {:?}: {}", S::FILES, e),
+        }
+    }
+}
+
+/// Reloaders for every shader the app uses.
+pub struct ShaderReloaders {
+    light: Reloader<LightShader>,
+    water: Reloader<WaterLight>,
+    xyz: Reloader<Xyz>,
+    particles: Reloader<ParticleShader>,
+}
+
+impl ShaderReloaders {
+    pub fn new(glsl: GLSL) -> Result<Self, String> {
+        Ok(Self {
+            light: Reloader::new(glsl)?,
+            water: Reloader::new(glsl)?,
+            xyz: Reloader::new(glsl)?,
+            particles: Reloader::new(glsl)?,
+        })
+    }
+    pub fn poll(&mut self, context: &mut ShaderContext) {
+        context.draw(&mut self.light);
+        context.draw(&mut self.water);
+        context.draw(&mut self.xyz);
+        context.draw(&mut self.particles);
+    }
+}
@@ -0,0 +1,22 @@
+// Phong-style lighting shared between the lit shaders.
+// https://www.desmos.com/calculator/rd2ohuwwzl
+struct Lighting {
+    float diffuse;
+    float specular;
+};
+
+// P and n are in view space, light is in world space.
+Lighting phong(vec3 P, vec3 n, vec3 E, vec3 light_pos, highp mat4x4 view_matrix) {
+    vec3 e_n = normalize(E - P);
+
+    vec3 L = (view_matrix * vec4(light_pos, 1.0)).xyz;
+
+    vec3 l_n = normalize(P - L); // -norm(L - P);
+
+    vec3 l_r = reflect(l_n, n);
+
+    Lighting lighting;
+    lighting.specular = clamp(dot(e_n, l_r), 0.0, 1.0);
+    lighting.diffuse = clamp(dot(n, l_r) + 0.2, 0.2, 1.0);
+    return lighting;
+}
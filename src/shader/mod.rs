@@ -1,9 +1,21 @@
 mod axis_bars;
 mod basic_light;
 mod dynamic_normal_light;
+mod particles;
 mod texture_3d;
+mod program;
+mod preprocess;
+mod element_buffer;
+#[cfg(feature = "hot_reload")]
+mod hot_reload;
 
 pub use axis_bars::Xyz;
-pub use basic_light::LightShader;
+pub use basic_light::{LightShader, NO_CLIP, LIGHT_COLOUR};
 pub use dynamic_normal_light::WaterLight;
+pub use particles::ParticleShader;
 pub use texture_3d::*;
+pub use program::*;
+pub use preprocess::*;
+pub use element_buffer::*;
+#[cfg(feature = "hot_reload")]
+pub use hot_reload::*;
@@ -0,0 +1,25 @@
+// Layers of `SPRITES` in `particles.rs`, white with the sprite's shape in
+// alpha.
+uniform mediump sampler2DArray sprites;
+// Which layer of `sprites` to draw, or negative for plain round points.
+uniform float sprite_layer;
+
+in vec4 v_Color;
+out vec4 outColor;
+
+void main() {
+    if (sprite_layer >= 0.0) {
+        float alpha = texture(sprites, vec3(gl_PointCoord, sprite_layer)).a;
+        if (alpha < 0.01) {
+            discard;
+        }
+        outColor = vec4(v_Color.rgb, v_Color.a * alpha);
+        return;
+    }
+    // Round, soft edged points.
+    float distance = length(gl_PointCoord * 2.0 - 1.0);
+    if (distance > 1.0) {
+        discard;
+    }
+    outColor = vec4(v_Color.rgb, v_Color.a * (1.0 - smoothstep(0.6, 1.0, distance)));
+}
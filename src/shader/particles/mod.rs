@@ -0,0 +1,155 @@
+use opengl_graphics::shader_utils::{Shader, DynamicAttribute};
+use opengl_graphics::gl::types::GLuint;
+use opengl_graphics::{gl, GlGraphics};
+use opengl_graphics::GLSL;
+use graphics::BACK_END_MAX_VERTEX_COUNT;
+use opengl_graphics::shader_uniforms::{ShaderUniform, SUFloat, SUMat4x4};
+use crate::shader::{link_program, Preprocessor, Includes, Target};
+
+pub const FRAGMENT_FILE: &'static str = "particles/fragment.glsl";
+pub const VERTEX_FILE: &'static str = "particles/vertex.glsl";
+const FRAGMENT_SOURCE: &'static str = include_str!("./fragment.glsl");
+const VERTEX_SOURCE: &'static str = include_str!("./vertex.glsl");
+const CHUNKS: usize = 20;
+
+/// Draws each vertex as a point, sized in world units by its position's w and
+/// shaped by a layer of `sprites` or else round.
+pub struct ParticleShader {
+    // Shader items
+    vao: GLuint,
+    vertex_shader: GLuint,
+    fragment_shader: GLuint,
+    program: GLuint,
+    // Per-vertex attributes
+    pos: DynamicAttribute,
+    color: DynamicAttribute,
+    // Per-vertex attribute buffers
+    pos_buffer: Vec<[f32; 4]>,
+    color_buffer: Vec<[f32; 4]>,
+    offset: usize,
+    /// Desktop GL ignores `gl_PointSize` unless this is enabled; GLES always
+    /// honours it.
+    program_point_size: bool,
+    // Uniforms
+    pub projection_matrix_uni: ShaderUniform<SUMat4x4>,
+    pub view_matrix_uni: ShaderUniform<SUMat4x4>,
+    pub viewport_height_uni: ShaderUniform<SUFloat>,
+    pub sprite_layer_uni: ShaderUniform<SUFloat>,
+    // Sprite texture array, bound to the first texture unit; 0 when there
+    // is none.
+    pub sprites: GLuint,
+}
+
+impl ParticleShader {
+    pub fn from_sources(gl: &mut GlGraphics, preprocessor: &Preprocessor, vertex: &str, fragment: &str) -> Result<Self, String> {
+        let vertex = preprocessor.process(gl::VERTEX_SHADER, VERTEX_FILE, vertex)?;
+        let fragment = preprocessor.process(gl::FRAGMENT_SHADER, FRAGMENT_FILE, fragment)?;
+        let linked = link_program(&[(gl::VERTEX_SHADER, &vertex), (gl::FRAGMENT_SHADER, &fragment)])?;
+        let program = linked.program;
+
+        let pos = DynamicAttribute::xyzw(program, "pos")?;
+        let color = DynamicAttribute::rgba(program, "color")?;
+        gl.use_program(program);
+        let uniforms = (|| -> Result<_, String> {
+            let projection_matrix_uni = gl.get_uniform("projection").ok_or("Could not find projection uniform")?;
+            projection_matrix_uni.set(gl, &[0.0; 16]);
+            let view_matrix_uni = gl.get_uniform("view").ok_or("Could not find view uniform")?;
+            view_matrix_uni.set(gl, &[0.0; 16]);
+            let viewport_height_uni = gl.get_uniform("viewport_height").ok_or("Could not find viewport height uniform")?;
+            viewport_height_uni.set(gl, 1.0);
+            let sprite_layer_uni = gl.get_uniform("sprite_layer").ok_or("Could not find sprite layer uniform")?;
+            sprite_layer_uni.set(gl, -1.0);
+            Ok((projection_matrix_uni, view_matrix_uni, viewport_height_uni, sprite_layer_uni))
+        })();
+        gl.clear_program();
+        let (projection_matrix_uni, view_matrix_uni, viewport_height_uni, sprite_layer_uni) = uniforms?;
+
+        let (program, stages) = linked.release();
+        let mut vao = 0;
+        unsafe {
+            gl::GenVertexArrays(1, &mut vao);
+        }
+
+        Ok(Self {
+            // Shader items
+            vao,
+            vertex_shader: stages[0],
+            fragment_shader: stages[1],
+            program,
+            // Per vertex items
+            pos,
+            color,
+            pos_buffer: vec![[0.0; 4]; CHUNKS * BACK_END_MAX_VERTEX_COUNT],
+            color_buffer: vec![[0.0; 4]; CHUNKS * BACK_END_MAX_VERTEX_COUNT],
+            offset: 0,
+            program_point_size: !preprocessor.is_es(),
+            // Uniforms
+            projection_matrix_uni,
+            view_matrix_uni,
+            viewport_height_uni,
+            sprite_layer_uni,
+            sprites: 0,
+        })
+    }
+
+    /// Swaps in a program built from new sources, keeping the current one if
+    /// they fail to compile or link.
+    pub fn reload(&mut self, gl: &mut GlGraphics, preprocessor: &Preprocessor, vertex: &str, fragment: &str) -> Result<(), String> {
+        *self = Self::from_sources(gl, preprocessor, vertex, fragment)?;
+        Ok(())
+    }
+}
+
+impl Drop for ParticleShader {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteProgram(self.program);
+            gl::DeleteShader(self.vertex_shader);
+            gl::DeleteShader(self.fragment_shader);
+        }
+    }
+}
+
+impl Shader for ParticleShader {
+    type Vertex = [f32; 4];
+    fn new(glsl: GLSL, gl: Option<&mut GlGraphics>) -> Self {
+        let gl = gl.unwrap();
+        Target::for_context(glsl)
+            .and_then(|target| Self::from_sources(gl, &Preprocessor::shared(Includes::Baked, target), VERTEX_SOURCE, FRAGMENT_SOURCE))
+            .expect("Particle shader error")
+    }
+
+    fn flush(&mut self) {
+        unsafe {
+            gl::BindVertexArray(self.vao);
+            if self.program_point_size {
+                gl::Enable(gl::PROGRAM_POINT_SIZE);
+            }
+            gl::ActiveTexture(gl::TEXTURE0);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.sprites);
+            self.color.bind_vao(self.vao);
+            self.color.set(&self.color_buffer[..self.offset]);
+            self.pos.bind_vao(self.vao);
+            self.pos.set(&self.pos_buffer[..self.offset]);
+            gl::DrawArrays(gl::POINTS, 0, self.offset as i32);
+            gl::BindVertexArray(0);
+        }
+
+        self.offset = 0;
+    }
+
+    fn program(&self) -> GLuint {
+        self.program
+    }
+
+    fn offset(&mut self) -> &mut usize {
+        &mut self.offset
+    }
+
+    fn pos_buffer(&mut self) -> &mut Vec<[f32; 4]> { &mut self.pos_buffer }
+    fn colour_buffer(&mut self) -> Option<&mut Vec<[f32; 4]>> { Some(&mut self.color_buffer) }
+    fn uv_buffer(&mut self) -> Option<&mut Vec<[f32; 2]>> { None }
+    fn index_buffer(&mut self) -> Option<&mut Vec<u16>> { None }
+    fn normal_buffer(&mut self) -> Option<&mut Vec<[f32; 3]>> { None }
+}
@@ -0,0 +1,18 @@
+uniform mat4x4 projection;
+uniform mat4x4 view;
+// Pixels down the viewport, to turn sizes in world units into point sizes.
+uniform float viewport_height;
+
+// World position, with the particle's size in world units in w.
+in vec4 pos;
+in vec4 color;
+
+out vec4 v_Color;
+
+void main() {
+    vec4 view_pos = view * vec4(pos.xyz, 1.0);
+    gl_Position = projection * view_pos;
+    // projection[1][1] is the focal length in half viewports.
+    gl_PointSize = max(pos.w * projection[1][1] * viewport_height * 0.5 / max(-view_pos.z, 0.01), 1.0);
+    v_Color = color;
+}
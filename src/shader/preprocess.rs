@@ -0,0 +1,386 @@
+use std::fs;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::path::PathBuf;
+use opengl_graphics::GLSL;
+use opengl_graphics::gl;
+use opengl_graphics::gl::types::GLenum;
+use crate::drawable::{MAX_WAVES, NOISE_HEIGHT};
+
+/// Files which can be pulled in with `#include`, baked into the binary.
+const INCLUDES: &'static [(&'static str, &'static str)] = &[
+    ("lighting.glsl", include_str!("./lighting.glsl")),
+    ("gerstner.glsl", include_str!("./gerstner.glsl")),
+    ("fog.glsl", include_str!("./fog.glsl")),
+];
+
+/// Nesting deeper than this is assumed to be an include cycle.
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+pub fn include_names() -> impl Iterator<Item = &'static str> {
+    INCLUDES.iter().map(|&(name, _)| name)
+}
+
+/// The GLSL dialect sources are generated for.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    /// OpenGL ES 3.2, which is what Android gives us.
+    Es320,
+    /// A desktop OpenGL core profile, by version number (e.g. `330`).
+    Core(u32),
+}
+
+impl Target {
+    /// Picks the dialect for the version handed to `Shader::new`, on a
+    /// context that's GLES if `es`.
+    ///
+    /// The shaders need at least GLSL 3.30 for geometry shaders and interface
+    /// blocks, so older versions are refused. GLES contexts get GLSL ES 3.20,
+    /// the first ES version with geometry shaders.
+    pub fn from_glsl(glsl: GLSL, es: bool) -> Result<Self, String> {
+        let version = match glsl {
+            GLSL::V4_50 => 450,
+            GLSL::V4_40 => 440,
+            GLSL::V4_30 => 430,
+            GLSL::V4_20 => 420,
+            GLSL::V4_10 => 410,
+            GLSL::V4_00 => 400,
+            GLSL::V3_30 => 330,
+            _ => return Err(format!("The shaders need GLSL 3.30 or later, not {:?}", glsl)),
+        };
+        Ok(if es { Target::Es320 } else { Target::Core(version) })
+    }
+
+    /// `from_glsl` for whether the current context is GLES.
+    pub fn for_context(glsl: GLSL) -> Result<Self, String> {
+        let version = unsafe {
+            let version = gl::GetString(gl::VERSION);
+            if version.is_null() {
+                return Err("Could not read the OpenGL version".to_string());
+            }
+            CStr::from_ptr(version as *const c_char).to_string_lossy().into_owned()
+        };
+        Self::from_glsl(glsl, version.starts_with("OpenGL ES"))
+    }
+
+    /// The `#version` line and any default precisions `stage` needs.
+    pub fn header(&self, stage: GLenum) -> String {
+        match *self {
+            Target::Es320 if stage == gl::FRAGMENT_SHADER => "#version 320 es\nprecision mediump float;\n".to_string(),
+            Target::Es320 => "#version 320 es\n".to_string(),
+            // Precision qualifiers are accepted and ignored on desktop.
+            Target::Core(version) => format!("#version {} core\n", version),
+        }
+    }
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target::Es320
+    }
+}
+
+/// Where `#include`d files are looked up.
+#[derive(Clone, Debug)]
+pub enum Includes {
+    /// The copies baked in with `include_str!`.
+    Baked,
+    /// A directory on disk, for reading the latest copies while iterating.
+    Directory(PathBuf),
+}
+
+impl Includes {
+    fn load(&self, name: &str) -> Result<String, String> {
+        match self {
+            Includes::Baked => INCLUDES
+                .iter()
+                .find(|&&(file, _)| file == name)
+                .map(|&(_, source)| source.to_string())
+                .ok_or_else(|| format!("Unknown include \"{}\"", name)),
+            Includes::Directory(root) => fs::read_to_string(root.join(name))
+                .map_err(|e| format!("Could not read include \"{}\": {}", name, e)),
+        }
+    }
+}
+
+/// A source after preprocessing, along with what's needed to map line
+/// numbers in compiler logs back to the files they came from.
+#[derive(Clone, Debug)]
+pub struct ProcessedSource {
+    text: String,
+    /// Indexed by the source string number used in `#line` directives.
+    files: Vec<String>,
+}
+
+impl ProcessedSource {
+    pub fn as_str(&self) -> &str {
+        &self.text
+    }
+
+    /// Rewrites the `<source>:<line>` (or `<source>(<line>)`) locations in a
+    /// compiler log as `<file>:<line>`.
+    pub fn map_log(&self, log: &str) -> String {
+        log.lines()
+            .map(|line| self.map_log_line(line))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    fn map_log_line(&self, line: &str) -> String {
+        let bytes = line.as_bytes();
+        let mut start = 0;
+        while start < bytes.len() {
+            if bytes[start].is_ascii_digit() && (start == 0 || !bytes[start - 1].is_ascii_alphanumeric()) {
+                let index_end = start + bytes[start..].iter().take_while(|b| b.is_ascii_digit()).count();
+                let separator = bytes.get(index_end).cloned();
+                if separator == Some(b':') || separator == Some(b'(') {
+                    let line_start = index_end + 1;
+                    let line_end = line_start + bytes[line_start..].iter().take_while(|b| b.is_ascii_digit()).count();
+                    let closed = separator != Some(b'(') || bytes.get(line_end) == Some(&b')');
+                    if line_end > line_start && closed {
+                        let file = line[start..index_end].parse::<usize>().ok().and_then(|i| self.files.get(i));
+                        if let Some(file) = file {
+                            let after = if separator == Some(b'(') { line_end + 1 } else { line_end };
+                            return format!("{}{}:{}{}", &line[..start], file, &line[line_start..line_end], &line[after..]);
+                        }
+                    }
+                }
+                start = index_end;
+            } else {
+                start += 1;
+            }
+        }
+        line.to_string()
+    }
+}
+
+/// Prepends the header for the target, injects `#define`s after it and
+/// expands `#include "file"` directives, emitting `#line` directives so the
+/// compiler reports positions in the original files.
+///
+/// A source which starts with its own `#version` line keeps it instead of
+/// getting the target's header.
+#[derive(Clone, Debug)]
+pub struct Preprocessor {
+    target: Target,
+    includes: Includes,
+    defines: Vec<(String, String)>,
+}
+
+impl Default for Preprocessor {
+    fn default() -> Self {
+        Self::new(Includes::Baked)
+    }
+}
+
+impl Preprocessor {
+    pub fn new(includes: Includes) -> Self {
+        Self {
+            target: Target::default(),
+            includes,
+            defines: Vec::new(),
+        }
+    }
+
+    /// Adds `#define name value`, replacing an earlier value for `name`.
+    pub fn define<N: Into<String>, V: Into<String>>(mut self, name: N, value: V) -> Self {
+        let name = name.into();
+        let value = value.into();
+        match self.defines.iter_mut().find(|(n, _)| *n == name) {
+            Some(define) => define.1 = value,
+            None => self.defines.push((name, value)),
+        }
+        self
+    }
+
+    /// What every shader is built with: `target`, and the defines the
+    /// shaders share with the Rust side.
+    pub fn shared(includes: Includes, target: Target) -> Self {
+        Self::new(includes)
+            .target(target)
+            .define("MAX_WAVES", MAX_WAVES.to_string())
+            // Debug formatting always has a decimal point, as GLSL floats need.
+            .define("NOISE_HEIGHT", format!("{:?}", NOISE_HEIGHT))
+    }
+
+    pub fn target(mut self, target: Target) -> Self {
+        self.target = target;
+        self
+    }
+
+    pub fn is_es(&self) -> bool {
+        self.target == Target::Es320
+    }
+
+    pub fn includes(&self) -> &Includes {
+        &self.includes
+    }
+
+    pub fn process(&self, stage: GLenum, name: &str, source: &str) -> Result<ProcessedSource, String> {
+        let mut processed = ProcessedSource {
+            text: String::with_capacity(source.len()),
+            files: vec![name.to_string()],
+        };
+
+        let mut lines = source.lines().enumerate().peekable();
+        match lines.peek() {
+            Some(&(_, first)) if first.trim_start().starts_with("#version") => {
+                processed.text.push_str(first);
+                processed.text.push('\n');
+                lines.next();
+            }
+            _ => processed.text.push_str(&self.target.header(stage)),
+        }
+        for (define, value) in &self.defines {
+            processed.text.push_str(&format!("#define {} {}\n", define, value));
+        }
+        if let Some(&(number, _)) = lines.peek() {
+            processed.text.push_str(&format!("#line {} 0\n", number + 1));
+        }
+
+        let mut stack = vec![name.to_string()];
+        self.expand(lines, 0, &mut stack, &mut processed)?;
+        Ok(processed)
+    }
+
+    fn expand<'a, I: Iterator<Item = (usize, &'a str)>>(
+        &self,
+        lines: I,
+        file: usize,
+        stack: &mut Vec<String>,
+        processed: &mut ProcessedSource,
+    ) -> Result<(), String> {
+        for (number, line) in lines {
+            let included = match parse_include(line) {
+                Some(included) => included,
+                None => {
+                    processed.text.push_str(line);
+                    processed.text.push('\n');
+                    continue;
+                }
+            };
+            let included = included.map_err(|e| format!("{}:{}: {}", processed.files[file], number + 1, e))?;
+            if stack.iter().any(|f| f == included) || stack.len() >= MAX_INCLUDE_DEPTH {
+                return Err(format!("{}:{}: Recursive include of \"{}\"", processed.files[file], number + 1, included));
+            }
+
+            let source = self.includes
+                .load(included)
+                .map_err(|e| format!("{}:{}: {}", processed.files[file], number + 1, e))?;
+            let index = processed.files.len();
+            processed.files.push(included.to_string());
+            processed.text.push_str(&format!("#line 1 {}\n", index));
+            stack.push(included.to_string());
+            self.expand(source.lines().enumerate(), index, stack, processed)?;
+            stack.pop();
+            processed.text.push_str(&format!("#line {} {}\n", number + 2, file));
+        }
+        Ok(())
+    }
+}
+
+/// `None` if the line isn't an `#include`, otherwise the quoted file name.
+fn parse_include(line: &str) -> Option<Result<&str, String>> {
+    let rest = line.trim_start().strip_prefix("#")?.trim_start().strip_prefix("include")?;
+    let rest = rest.trim();
+    let name = rest
+        .strip_prefix('"')
+        .and_then(|r| r.strip_suffix('"'))
+        .filter(|name| !name.is_empty() && !name.contains('"'));
+    Some(name.ok_or_else(|| format!("Malformed include: {}", line.trim())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fs::TempDir;
+
+    fn preprocessor(includes: &TempDir) -> Preprocessor {
+        Preprocessor::new(Includes::Directory(includes.0.clone()))
+    }
+
+    /// Where a compiler following the `#line` directives would say the line
+    /// containing `needle` is, as `(source string, line)`.
+    fn compiler_position(processed: &ProcessedSource, needle: &str) -> (usize, usize) {
+        let (mut source, mut line) = (0, 0);
+        for text in processed.as_str().lines() {
+            line += 1;
+            if let Some(directive) = text.strip_prefix("#line ") {
+                let mut parts = directive.split_whitespace().map(|part| part.parse::<usize>().unwrap());
+                // The directive names the line after it.
+                line = parts.next().unwrap() - 1;
+                if let Some(index) = parts.next() {
+                    source = index;
+                }
+            } else if text.contains(needle) {
+                return (source, line);
+            }
+        }
+        panic!("\"{}\" isn't in the processed source", needle);
+    }
+
+    #[test]
+    fn errors_in_includes_map_back_to_the_included_file() {
+        let includes = TempDir::new("preprocess-map", &[
+            ("outer.glsl", "// outer\n#include \"inner.glsl\"\nfloat outer_broken;\n"),
+            ("inner.glsl", "// inner\n\nfloat inner_broken;\n"),
+        ]);
+        let source = "uniform float a;\n#include \"outer.glsl\"\nfloat main_broken;\n";
+        let processed = preprocessor(&includes).process(gl::FRAGMENT_SHADER, "main.glsl", source).unwrap();
+
+        for &(needle, expected) in &[
+            ("inner_broken", "inner.glsl:3"),
+            ("outer_broken", "outer.glsl:3"),
+            ("main_broken", "main.glsl:3"),
+        ] {
+            let (index, line) = compiler_position(&processed, needle);
+            let log = format!("ERROR: {}:{}: '{}' : syntax error", index, line, needle);
+            assert_eq!(processed.map_log(&log), format!("ERROR: {}: '{}' : syntax error", expected, needle));
+            // NVIDIA style positions map the same way.
+            let log = format!("{}({}) : error C0000: syntax error", index, line);
+            assert_eq!(processed.map_log(&log), format!("{} : error C0000: syntax error", expected));
+        }
+    }
+
+    #[test]
+    fn defines_come_after_the_version_and_before_the_source() {
+        let processed = Preprocessor::default()
+            .target(Target::Core(330))
+            .define("MAX_WAVES", "4")
+            .define("MAX_WAVES", "8")
+            .process(gl::VERTEX_SHADER, "main.glsl", "float a;\n")
+            .unwrap();
+        assert_eq!(processed.as_str(), "#version 330 core\n#define MAX_WAVES 8\n#line 1 0\nfloat a;\n");
+    }
+
+    #[test]
+    fn missing_includes_report_the_include_directive() {
+        let includes = TempDir::new("preprocess-missing", &[("outer.glsl", "\n\n#include \"absent.glsl\"\n")]);
+        let source = "float a;\n#include \"outer.glsl\"\n";
+        let error = preprocessor(&includes).process(gl::VERTEX_SHADER, "main.glsl", source).unwrap_err();
+        assert!(error.starts_with("outer.glsl:3: "), "{}", error);
+        assert!(error.contains("absent.glsl"), "{}", error);
+
+        let error = Preprocessor::default().process(gl::VERTEX_SHADER, "main.glsl", "\n#include \"absent.glsl\"\n").unwrap_err();
+        assert_eq!(error, "main.glsl:2: Unknown include \"absent.glsl\"");
+    }
+
+    #[test]
+    fn targets_follow_the_version_and_context() {
+        assert_eq!(Target::from_glsl(GLSL::V3_30, false), Ok(Target::Core(330)));
+        assert_eq!(Target::from_glsl(GLSL::V4_50, false), Ok(Target::Core(450)));
+        assert_eq!(Target::from_glsl(GLSL::V3_30, true), Ok(Target::Es320));
+        assert!(Target::from_glsl(GLSL::V1_20, false).is_err());
+        assert!(Target::from_glsl(GLSL::V1_50, true).is_err());
+    }
+
+    #[test]
+    fn recursive_includes_are_rejected() {
+        let includes = TempDir::new("preprocess-recursive", &[
+            ("a.glsl", "#include \"b.glsl\"\n"),
+            ("b.glsl", "\n#include \"a.glsl\"\n"),
+        ]);
+        let error = preprocessor(&includes).process(gl::VERTEX_SHADER, "main.glsl", "#include \"a.glsl\"\n").unwrap_err();
+        assert_eq!(error, "b.glsl:2: Recursive include of \"a.glsl\"");
+    }
+}
@@ -0,0 +1,84 @@
+use opengl_graphics::shader_utils::compile_shader;
+use opengl_graphics::gl::types::{GLenum, GLint, GLuint};
+use opengl_graphics::gl;
+use crate::shader::ProcessedSource;
+
+/// A linked program and the stages attached to it.
+///
+/// Everything is deleted on drop unless ownership is taken back with
+/// `release`, so a shader which fails halfway through being built doesn't
+/// leak GL objects.
+pub struct Linked {
+    pub program: GLuint,
+    stages: Vec<GLuint>,
+}
+
+impl Linked {
+    pub fn release(mut self) -> (GLuint, Vec<GLuint>) {
+        let program = self.program;
+        self.program = 0;
+        (program, std::mem::replace(&mut self.stages, Vec::new()))
+    }
+}
+
+impl Drop for Linked {
+    fn drop(&mut self) {
+        delete_program(self.program, &self.stages);
+    }
+}
+
+pub fn stage_name(stage: GLenum) -> &'static str {
+    match stage {
+        gl::VERTEX_SHADER => "Vertex",
+        gl::FRAGMENT_SHADER => "Fragment",
+        gl::GEOMETRY_SHADER => "Geometry",
+        _ => "Unknown",
+    }
+}
+
+pub fn delete_program(program: GLuint, stages: &[GLuint]) {
+    unsafe {
+        if program != 0 {
+            gl::DeleteProgram(program);
+        }
+        for &stage in stages {
+            gl::DeleteShader(stage);
+        }
+    }
+}
+
+/// Compiles each `(stage, source)` pair and links them into a program,
+/// returning the compiler or linker log instead of panicking. Compiler logs
+/// refer to the files the sources were preprocessed from.
+pub fn link_program(stages: &[(GLenum, &ProcessedSource)]) -> Result<Linked, String> {
+    let mut linked = Linked {
+        program: 0,
+        stages: Vec::with_capacity(stages.len()),
+    };
+    for &(stage, source) in stages {
+        let compiled = compile_shader(stage, source.as_str())
+            .map_err(|e| format!("{} shader error: {}", stage_name(stage), source.map_log(&e)))?;
+        linked.stages.push(compiled);
+    }
+
+    unsafe {
+        linked.program = gl::CreateProgram();
+        for &stage in &linked.stages {
+            gl::AttachShader(linked.program, stage);
+        }
+        gl::LinkProgram(linked.program);
+
+        let mut status = gl::FALSE as GLint;
+        gl::GetProgramiv(linked.program, gl::LINK_STATUS, &mut status);
+        if status != gl::TRUE as GLint {
+            let mut len = 0;
+            gl::GetProgramiv(linked.program, gl::INFO_LOG_LENGTH, &mut len);
+            let mut log = vec![0u8; len.max(1) as usize];
+            gl::GetProgramInfoLog(linked.program, len, std::ptr::null_mut(), log.as_mut_ptr() as *mut _);
+            let log = String::from_utf8_lossy(&log);
+            return Err(format!("Program link error: {}", log.trim_end_matches('\0')));
+        }
+    }
+
+    Ok(linked)
+}
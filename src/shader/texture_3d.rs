@@ -1,78 +1,286 @@
-use opengl_graphics::gl::types::GLuint;
+use opengl_graphics::gl::types::{GLenum, GLint, GLuint};
 use opengl_graphics::gl;
 
-pub struct Texture3D {
-    id: GLuint,
-    width: u32,
-    height: u32,
-    depth: u32,
+/// How each texel is stored. Data is always handed over as bytes, with
+/// half floats as native-endian `u16`s.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureFormat {
+    R8,
+    Rg8,
+    Rgba8,
+    R16F,
+    Rgba16F,
 }
 
-impl Texture3D {
-    pub unsafe fn new(id: GLuint, width: u32, height: u32, depth: u32) -> Self {
+impl TextureFormat {
+    /// Bytes per texel.
+    pub fn texel_size(self) -> usize {
+        match self {
+            TextureFormat::R8 => 1,
+            TextureFormat::Rg8 => 2,
+            TextureFormat::Rgba8 => 4,
+            TextureFormat::R16F => 2,
+            TextureFormat::Rgba16F => 8,
+        }
+    }
+
+    fn internal_format(self) -> GLenum {
+        match self {
+            TextureFormat::R8 => gl::R8,
+            TextureFormat::Rg8 => gl::RG8,
+            TextureFormat::Rgba8 => gl::RGBA8,
+            TextureFormat::R16F => gl::R16F,
+            TextureFormat::Rgba16F => gl::RGBA16F,
+        }
+    }
+
+    fn format(self) -> GLenum {
+        match self {
+            TextureFormat::R8 | TextureFormat::R16F => gl::RED,
+            TextureFormat::Rg8 => gl::RG,
+            TextureFormat::Rgba8 | TextureFormat::Rgba16F => gl::RGBA,
+        }
+    }
+
+    fn data_type(self) -> GLenum {
+        match self {
+            TextureFormat::R8 | TextureFormat::Rg8 | TextureFormat::Rgba8 => gl::UNSIGNED_BYTE,
+            TextureFormat::R16F | TextureFormat::Rgba16F => gl::HALF_FLOAT,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+impl Filter {
+    fn gl(self, mipmaps: bool) -> GLint {
+        (match (self, mipmaps) {
+            (Filter::Nearest, false) => gl::NEAREST,
+            (Filter::Linear, false) => gl::LINEAR,
+            (Filter::Nearest, true) => gl::NEAREST_MIPMAP_NEAREST,
+            (Filter::Linear, true) => gl::LINEAR_MIPMAP_LINEAR,
+        }) as GLint
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl Wrap {
+    fn gl(self) -> GLint {
+        (match self {
+            Wrap::Repeat => gl::REPEAT,
+            Wrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+            Wrap::ClampToEdge => gl::CLAMP_TO_EDGE,
+        }) as GLint
+    }
+}
+
+/// Runs `upload` with rows unpacked a byte at a time, since those of odd
+/// sized single or two channel volumes aren't 4 byte aligned, then puts back
+/// whatever alignment was set before for other uploads.
+unsafe fn with_byte_alignment(upload: impl FnOnce()) {
+    let mut previous = 4;
+    gl::GetIntegerv(gl::UNPACK_ALIGNMENT, &mut previous);
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    upload();
+    gl::PixelStorei(gl::UNPACK_ALIGNMENT, previous);
+}
+
+/// Settings for a `Texture3D`, by default a linearly filtered, repeating
+/// `R8` volume without mipmaps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Texture3DBuilder {
+    format: TextureFormat,
+    min_filter: Filter,
+    mag_filter: Filter,
+    /// Along s, t and r.
+    wrap: [Wrap; 3],
+    mipmaps: bool,
+}
+
+impl Default for Texture3DBuilder {
+    fn default() -> Self {
         Self {
-            id, width, height, depth
+            format: TextureFormat::R8,
+            min_filter: Filter::Linear,
+            mag_filter: Filter::Linear,
+            wrap: [Wrap::Repeat; 3],
+            mipmaps: false,
         }
     }
+}
 
-    pub fn get_id(&self) -> GLuint {
-        self.id
+impl Texture3DBuilder {
+    pub fn format(mut self, format: TextureFormat) -> Self {
+        self.format = format;
+        self
     }
 
-    pub fn empty() -> Result<Self, String> {
-        Self::create(&[0u8; 4], [1, 1, 1])
+    /// With mipmaps, `min` also picks between and blends mip levels.
+    pub fn filter(mut self, min: Filter, mag: Filter) -> Self {
+        self.min_filter = min;
+        self.mag_filter = mag;
+        self
     }
 
-    pub fn from_data(data: &[u8], dimensions: [usize; 3]) -> Result<Self, String> {
-        assert_eq!(data.len(), dimensions[0] * dimensions[1] * dimensions[2]);
+    /// The same wrapping along every axis.
+    pub fn wrap(mut self, wrap: Wrap) -> Self {
+        self.wrap = [wrap; 3];
+        self
+    }
+
+    pub fn wrap_axes(mut self, s: Wrap, t: Wrap, r: Wrap) -> Self {
+        self.wrap = [s, t, r];
+        self
+    }
 
-        Self::create(data, dimensions)
+    pub fn mipmaps(mut self, mipmaps: bool) -> Self {
+        self.mipmaps = mipmaps;
+        self
     }
 
-    fn create(memory: &[u8], size: [usize; 3]) -> Result<Self, String> {
+    /// `data` is the volume x fastest then y then z, in this builder's
+    /// format.
+    pub fn build(&self, data: &[u8], size: [usize; 3]) -> Result<Texture3D, String> {
+        if size.iter().any(|&axis| axis == 0) {
+            return Err(format!("Can't create a {:?} texture", size));
+        }
+        let expected = size[0] * size[1] * size[2] * self.format.texel_size();
+        if data.len() != expected {
+            return Err(format!(
+                "{:?} {:?} texture needs {} bytes but was given {}",
+                size, self.format, expected, data.len()
+            ));
+        }
         let mut id = 0;
-        let internal_format = gl::R8;
         unsafe {
             gl::GenTextures(1, &mut id);
             gl::BindTexture(gl::TEXTURE_3D, id);
-            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
-            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
-            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_S, gl::MIRRORED_REPEAT as i32);
-            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_T, gl::MIRRORED_REPEAT as i32);
-            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_R, gl::MIRRORED_REPEAT as i32);
-            gl::TexImage3D(
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, self.min_filter.gl(self.mipmaps));
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, self.mag_filter.gl(false));
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_S, self.wrap[0].gl());
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_T, self.wrap[1].gl());
+            gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_R, self.wrap[2].gl());
+            with_byte_alignment(|| gl::TexImage3D(
                 gl::TEXTURE_3D,
                 0,
-                internal_format as i32,
+                self.format.internal_format() as i32,
                 size[0] as i32,
                 size[1] as i32,
                 size[2] as i32,
                 0,
-                gl::RED,
-                gl::UNSIGNED_BYTE,
-                memory.as_ptr() as *const _);
-            Ok(Self::new(id, size[0] as _, size[1] as _, size[2] as _))
+                self.format.format(),
+                self.format.data_type(),
+                data.as_ptr() as *const _));
+            if self.mipmaps {
+                gl::GenerateMipmap(gl::TEXTURE_3D);
+            }
+        }
+        Ok(Texture3D {
+            id,
+            width: size[0] as _,
+            height: size[1] as _,
+            depth: size[2] as _,
+            format: self.format,
+            mipmaps: self.mipmaps,
+        })
+    }
+}
+
+pub struct Texture3D {
+    id: GLuint,
+    width: u32,
+    height: u32,
+    depth: u32,
+    format: TextureFormat,
+    mipmaps: bool,
+}
+
+impl Texture3D {
+    /// Takes ownership of an existing `R8` texture without mipmaps.
+    pub unsafe fn new(id: GLuint, width: u32, height: u32, depth: u32) -> Self {
+        Self {
+            id, width, height, depth,
+            format: TextureFormat::R8,
+            mipmaps: false,
         }
     }
 
-    pub fn update_with(&mut self, data: &[u8]) {
-        assert_eq!(data.len() as u32, self.width * self.height * self.depth);
+    pub fn builder() -> Texture3DBuilder {
+        Texture3DBuilder::default()
+    }
 
-        let offset = [0, 0, 0];
+    pub fn get_id(&self) -> GLuint {
+        self.id
+    }
+
+    pub fn size(&self) -> [usize; 3] {
+        [self.width as _, self.height as _, self.depth as _]
+    }
+
+    pub fn format(&self) -> TextureFormat {
+        self.format
+    }
+
+    /// A single black texel.
+    pub fn empty() -> Result<Self, String> {
+        Self::from_data(&[0], [1, 1, 1])
+    }
+
+    /// An `R8` volume with the default settings.
+    pub fn from_data(data: &[u8], dimensions: [usize; 3]) -> Result<Self, String> {
+        Self::builder().build(data, dimensions)
+    }
+
+    /// Replaces the whole volume.
+    pub fn update_with(&mut self, data: &[u8]) -> Result<(), String> {
+        self.update_region([0; 3], self.size(), data)
+    }
+
+    /// Replaces the `size` texels starting at `offset`, with `data` laid out
+    /// as for `Texture3DBuilder::build`. Mipmaps are regenerated.
+    pub fn update_region(&mut self, offset: [usize; 3], size: [usize; 3], data: &[u8]) -> Result<(), String> {
+        let bounds = self.size();
+        if (0..3).any(|axis| offset[axis] + size[axis] > bounds[axis]) {
+            return Err(format!(
+                "Region of {:?} at {:?} is outside the {:?} texture",
+                size, offset, bounds
+            ));
+        }
+        let expected = size[0] * size[1] * size[2] * self.format.texel_size();
+        if data.len() != expected {
+            return Err(format!(
+                "{:?} region of a {:?} texture needs {} bytes but was given {}",
+                size, self.format, expected, data.len()
+            ));
+        }
         unsafe {
             gl::BindTexture(gl::TEXTURE_3D, self.id);
-            gl::TexSubImage3D(gl::TEXTURE_3D,
-                              0,
-                              offset[0],
-                              offset[1],
-                              offset[2],
-                              self.width as _,
-                              self.height as _,
-                              self.depth as _,
-                              gl::RED,
-                              gl::UNSIGNED_BYTE,
-                              data.as_ptr() as *const _);
+            with_byte_alignment(|| gl::TexSubImage3D(gl::TEXTURE_3D,
+                                                     0,
+                                                     offset[0] as _,
+                                                     offset[1] as _,
+                                                     offset[2] as _,
+                                                     size[0] as _,
+                                                     size[1] as _,
+                                                     size[2] as _,
+                                                     self.format.format(),
+                                                     self.format.data_type(),
+                                                     data.as_ptr() as *const _));
+            if self.mipmaps {
+                gl::GenerateMipmap(gl::TEXTURE_3D);
+            }
         }
+        Ok(())
     }
 }
 
@@ -84,3 +292,37 @@ impl Drop for Texture3D {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Without a GL context these would panic on reaching a GL call, so
+    // they also check nothing is sent to the GL before the data is.
+
+    #[test]
+    fn build_rejects_empty_volumes() {
+        for &size in &[[0, 4, 4], [4, 0, 4], [4, 4, 0]] {
+            assert!(Texture3D::builder().build(&[], size).is_err(), "{:?}", size);
+        }
+    }
+
+    #[test]
+    fn build_rejects_the_wrong_amount_of_data() {
+        assert!(Texture3D::builder().build(&[0; 7], [2, 2, 2]).is_err());
+        assert!(Texture3D::builder().build(&[0; 9], [2, 2, 2]).is_err());
+        let half_floats = Texture3D::builder().format(TextureFormat::Rgba16F);
+        assert!(half_floats.build(&[0; 8], [2, 2, 2]).is_err());
+        assert!(half_floats.build(&[0; 63], [2, 2, 2]).is_err());
+    }
+
+    #[test]
+    fn updates_stay_inside_the_volume() {
+        let mut texture = unsafe { Texture3D::new(0, 4, 4, 4) };
+        assert!(texture.update_region([3, 0, 0], [2, 1, 1], &[0; 2]).is_err());
+        assert!(texture.update_region([0, 0, 0], [2, 2, 2], &[0; 7]).is_err());
+        assert!(texture.update_with(&[0; 63]).is_err());
+        // Deleting it would need a context too.
+        std::mem::forget(texture);
+    }
+}
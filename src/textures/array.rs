@@ -0,0 +1,86 @@
+use opengl_graphics::gl::types::GLuint;
+use opengl_graphics::gl;
+use image::RgbaImage;
+
+/// Same sized RGBA layers in one `sampler2DArray`, for sets of textures a
+/// shader picks between per vertex, like ground layers.
+pub struct TextureArray {
+    id: GLuint,
+    width: u32,
+    height: u32,
+    layers: u32,
+}
+
+impl TextureArray {
+    /// Mipmapped and repeating, with layers in the order given.
+    pub fn from_images(images: &[RgbaImage]) -> Result<Self, String> {
+        let (width, height) = images.first().ok_or("A texture array needs at least one layer")?.dimensions();
+        if let Some(image) = images.iter().find(|image| image.dimensions() != (width, height)) {
+            return Err(format!(
+                "Texture array layers should all be {}x{}, not {:?}",
+                width, height, image.dimensions()
+            ));
+        }
+        let mut id = 0;
+        unsafe {
+            gl::GenTextures(1, &mut id);
+            gl::BindTexture(gl::TEXTURE_2D_ARRAY, id);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+            gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+            gl::TexImage3D(
+                gl::TEXTURE_2D_ARRAY,
+                0,
+                gl::RGBA8 as i32,
+                width as i32,
+                height as i32,
+                images.len() as i32,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                std::ptr::null());
+            for (layer, image) in images.iter().enumerate() {
+                gl::TexSubImage3D(
+                    gl::TEXTURE_2D_ARRAY,
+                    0,
+                    0,
+                    0,
+                    layer as i32,
+                    width as i32,
+                    height as i32,
+                    1,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    image.as_ptr() as *const _);
+            }
+            gl::GenerateMipmap(gl::TEXTURE_2D_ARRAY);
+        }
+        Ok(Self {
+            id,
+            width,
+            height,
+            layers: images.len() as u32,
+        })
+    }
+
+    pub fn get_id(&self) -> GLuint {
+        self.id
+    }
+
+    pub fn size(&self) -> (u32, u32) {
+        (self.width, self.height)
+    }
+
+    pub fn layers(&self) -> u32 {
+        self.layers
+    }
+}
+
+impl Drop for TextureArray {
+    fn drop(&mut self) {
+        unsafe {
+            gl::DeleteTextures(1, &self.id);
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+use image::{GenericImage, RgbaImage};
+
+/// Texels around each image, filled with copies of its edge so filtering
+/// doesn't bleed between images or fade to transparent at their borders.
+const PADDING: u32 = 2;
+
+/// Where each of several images packed into one ended up, so things
+/// textured from any of them can be drawn together.
+#[derive(Clone, Debug, Default)]
+pub struct AtlasLayout {
+    /// `[u min, v min, u max, v max]` of each image by name.
+    regions: HashMap<String, [f32; 4]>,
+}
+
+impl AtlasLayout {
+    /// Packs onto shelves, tallest images first, into a square power of two
+    /// big enough for them all.
+    pub fn pack(images: Vec<(String, RgbaImage)>) -> Result<(RgbaImage, Self), String> {
+        let mut images = images;
+        images.sort_by(|a, b| b.1.height().cmp(&a.1.height()).then_with(|| a.0.cmp(&b.0)));
+        let area: u32 = images
+            .iter()
+            .map(|(_, image)| (image.width() + PADDING * 2) * (image.height() + PADDING * 2))
+            .sum();
+        let widest = images.iter().map(|(_, image)| image.width() + PADDING * 2).max().unwrap_or(1);
+        let mut side = widest.max((area as f32).sqrt() as u32).next_power_of_two();
+        loop {
+            if let Some(placements) = shelf_pack(&images, side) {
+                let mut atlas = RgbaImage::new(side, side);
+                let mut regions = HashMap::new();
+                for ((name, image), (x, y)) in images.iter().zip(placements) {
+                    if !atlas.copy_from(image, x, y) {
+                        return Err(format!("Could not place {:?} in the atlas", name));
+                    }
+                    extend_edges(&mut atlas, image, x, y);
+                    let size = side as f32;
+                    regions.insert(name.clone(), [
+                        x as f32 / size,
+                        y as f32 / size,
+                        (x + image.width()) as f32 / size,
+                        (y + image.height()) as f32 / size,
+                    ]);
+                }
+                return Ok((atlas, Self { regions }));
+            }
+            if side >= 8192 {
+                return Err("Atlas images don't fit in 8192x8192".to_string());
+            }
+            side *= 2;
+        }
+    }
+
+    pub fn region(&self, name: &str) -> Option<[f32; 4]> {
+        self.regions.get(name).cloned()
+    }
+
+    /// Maps `uv` within the named image onto the atlas.
+    pub fn map_uv(&self, name: &str, uv: [f32; 2]) -> Option<[f32; 2]> {
+        self.region(name).map(|[u0, v0, u1, v1]| [u0 + (u1 - u0) * uv[0], v0 + (v1 - v0) * uv[1]])
+    }
+}
+
+/// Fills the padding around `image`, placed at `x`, `y`, with its nearest
+/// edge texel.
+fn extend_edges(atlas: &mut RgbaImage, image: &RgbaImage, x: u32, y: u32) {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return;
+    }
+    for j in 0..height + PADDING * 2 {
+        for i in 0..width + PADDING * 2 {
+            let inside = (PADDING..width + PADDING).contains(&i) && (PADDING..height + PADDING).contains(&j);
+            if inside {
+                continue;
+            }
+            let source_x = i.max(PADDING).min(width + PADDING - 1) - PADDING;
+            let source_y = j.max(PADDING).min(height + PADDING - 1) - PADDING;
+            atlas.put_pixel(x + i - PADDING, y + j - PADDING, *image.get_pixel(source_x, source_y));
+        }
+    }
+}
+
+/// Top left corners of each image, or `None` if they don't fit.
+fn shelf_pack(images: &[(String, RgbaImage)], side: u32) -> Option<Vec<(u32, u32)>> {
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    let mut placements = Vec::with_capacity(images.len());
+    for (_, image) in images {
+        let (width, height) = (image.width() + PADDING * 2, image.height() + PADDING * 2);
+        if x + width > side {
+            x = 0;
+            y += shelf_height;
+            shelf_height = 0;
+        }
+        if x + width > side || y + height > side {
+            return None;
+        }
+        placements.push((x + PADDING, y + PADDING));
+        x += width;
+        shelf_height = shelf_height.max(height);
+    }
+    Some(placements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn image(width: u32, height: u32, value: u8) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| Rgba([value, x as u8, y as u8, 255]))
+    }
+
+    #[test]
+    fn regions_cover_their_images() {
+        let (atlas, layout) = AtlasLayout::pack(vec![
+            ("bark".to_string(), image(8, 16, 1)),
+            ("leaves".to_string(), image(16, 8, 2)),
+        ]).unwrap();
+        let size = atlas.width() as f32;
+        for &(name, value, (width, height)) in &[("bark", 1, (8, 16)), ("leaves", 2, (16, 8))] {
+            let [u0, v0, u1, v1] = layout.region(name).unwrap();
+            assert_eq!((((u1 - u0) * size) as u32, ((v1 - v0) * size) as u32), (width, height));
+            let (x, y) = ((u0 * size) as u32, (v0 * size) as u32);
+            assert_eq!(atlas.get_pixel(x + 3, y + 5), &Rgba([value, 3, 5, 255]));
+        }
+        let [u0, _, _, v1] = layout.region("bark").unwrap();
+        assert_eq!(layout.map_uv("bark", [0.0, 1.0]), Some([u0, v1]));
+        assert_eq!(layout.region("moss"), None);
+    }
+
+    #[test]
+    fn padding_repeats_the_edge() {
+        let (atlas, layout) = AtlasLayout::pack(vec![("bark".to_string(), image(4, 4, 9))]).unwrap();
+        let size = atlas.width() as f32;
+        let [u0, v0, _, _] = layout.region("bark").unwrap();
+        let (x, y) = ((u0 * size) as u32, (v0 * size) as u32);
+        assert_eq!(atlas.get_pixel(x - PADDING, y - PADDING), &Rgba([9, 0, 0, 255]));
+        assert_eq!(atlas.get_pixel(x + 4 + PADDING - 1, y + 1), &Rgba([9, 3, 1, 255]));
+        assert_eq!(atlas.get_pixel(x + 2, y + 4 + PADDING - 1), &Rgba([9, 2, 3, 255]));
+    }
+}
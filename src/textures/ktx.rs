@@ -0,0 +1,197 @@
+use std::convert::TryInto;
+use std::path::Path;
+use opengl_graphics::gl::types::{GLenum, GLuint};
+use opengl_graphics::{gl, Texture};
+use crate::fs::AssetError;
+
+const IDENTIFIER: [u8; 12] = [0xAB, b'K', b'T', b'X', b' ', b'1', b'1', 0xBB, b'\r', b'\n', 0x1A, b'\n'];
+const HEADER_SIZE: usize = 64;
+/// Most errors left over from earlier GL calls that are cleared before an
+/// upload. There's one flag per kind of error, but a lost context can keep
+/// reporting them forever.
+const MAX_STALE_ERRORS: usize = 16;
+
+pub fn is_ktx(data: &[u8]) -> bool {
+    data.starts_with(&IDENTIFIER)
+}
+
+/// The parts of a KTX 1.1 header needed to upload a 2D texture.
+struct Header {
+    gl_type: GLenum,
+    gl_format: GLenum,
+    internal_format: GLenum,
+    width: u32,
+    height: u32,
+    mip_levels: u32,
+}
+
+/// Checks a KTX 1.1 file holds a 2D texture, returning its header and each
+/// mip level's data.
+fn parse_ktx(data: &[u8]) -> Result<(Header, Vec<&[u8]>), &'static str> {
+    if !is_ktx(data) || data.len() < HEADER_SIZE {
+        return Err("Not a KTX 1.1 file");
+    }
+    let big_endian = match &data[12..16] {
+        [0x01, 0x02, 0x03, 0x04] => false,
+        [0x04, 0x03, 0x02, 0x01] => true,
+        _ => return Err("Invalid KTX endianness"),
+    };
+    let read = |offset: usize| -> Result<u32, &'static str> {
+        let bytes: [u8; 4] = data
+            .get(offset..offset + 4)
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or("KTX file is truncated")?;
+        Ok(if big_endian { u32::from_be_bytes(bytes) } else { u32::from_le_bytes(bytes) })
+    };
+    let header = Header {
+        gl_type: read(16)?,
+        gl_format: read(24)?,
+        internal_format: read(28)?,
+        width: read(36)?,
+        height: read(40)?,
+        mip_levels: read(56)?.max(1),
+    };
+    let (depth, array_elements, faces) = (read(44)?, read(48)?, read(52)?);
+    if header.height == 0 || depth != 0 || array_elements != 0 || faces != 1 {
+        return Err("Only 2D KTX textures are supported");
+    }
+
+    let mut offset = HEADER_SIZE + read(60)? as usize;
+    let mut levels = Vec::with_capacity(header.mip_levels as usize);
+    for _ in 0..header.mip_levels {
+        let size = read(offset)? as usize;
+        let start = offset + 4;
+        let level = data.get(start..start + size).ok_or("KTX file is truncated")?;
+        levels.push(level);
+        // Levels are padded to 4 bytes.
+        offset = start + (size + 3) / 4 * 4;
+    }
+    Ok((header, levels))
+}
+
+/// Uploads a 2D KTX texture, compressed (e.g. ETC2) or not, with every mip
+/// level it contains. Arrays, cube maps and 3D textures aren't supported.
+pub fn load_ktx(path: &Path, data: &[u8]) -> Result<Texture, AssetError> {
+    let error = |message: &str| AssetError::Decode(path.to_path_buf(), message.to_string());
+    let (header, levels) = parse_ktx(data).map_err(error)?;
+
+    let mut id: GLuint = 0;
+    unsafe {
+        // So that only errors from the upload are seen below.
+        for _ in 0..MAX_STALE_ERRORS {
+            if gl::GetError() == gl::NO_ERROR {
+                break;
+            }
+        }
+        gl::GenTextures(1, &mut id);
+        gl::BindTexture(gl::TEXTURE_2D, id);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        for (level, pixels) in levels.iter().enumerate() {
+            let width = (header.width >> level).max(1) as i32;
+            let height = (header.height >> level).max(1) as i32;
+            if header.gl_type == 0 {
+                gl::CompressedTexImage2D(
+                    gl::TEXTURE_2D,
+                    level as i32,
+                    header.internal_format,
+                    width,
+                    height,
+                    0,
+                    pixels.len() as i32,
+                    pixels.as_ptr() as *const _);
+            } else {
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    level as i32,
+                    header.internal_format as i32,
+                    width,
+                    height,
+                    0,
+                    header.gl_format,
+                    header.gl_type,
+                    pixels.as_ptr() as *const _);
+            }
+        }
+        let min_filter = if header.mip_levels > 1 { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR };
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, header.mip_levels as i32 - 1);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        if gl::GetError() != gl::NO_ERROR {
+            gl::DeleteTextures(1, &id);
+            return Err(error("The GL rejected the KTX texture's format"));
+        }
+    }
+    Ok(Texture::new(id, header.width, header.height))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An uncompressed 2x2 RGBA texture with one mip level, written with
+    /// `big_endian` byte order.
+    fn ktx(big_endian: bool) -> Vec<u8> {
+        let fields = [
+            0x0403_0201, gl::UNSIGNED_BYTE, 1, gl::RGBA, gl::RGBA8, gl::RGBA,
+            2, 2, 0, 0, 1, 1, 0,
+            16,
+        ];
+        let mut data = IDENTIFIER.to_vec();
+        for &field in fields.iter() {
+            data.extend_from_slice(&if big_endian { u32::to_be_bytes(field) } else { u32::to_le_bytes(field) });
+        }
+        data.extend_from_slice(&[255; 16]);
+        data
+    }
+
+    /// Overwrites the header field at byte `offset`.
+    fn set(data: &mut [u8], offset: usize, value: u32) {
+        data[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    #[test]
+    fn reads_either_endianness() {
+        for &big_endian in &[false, true] {
+            let data = ktx(big_endian);
+            let (header, levels) = parse_ktx(&data).unwrap();
+            assert_eq!((header.width, header.height, header.mip_levels), (2, 2, 1));
+            assert_eq!((header.gl_type, header.internal_format), (gl::UNSIGNED_BYTE, gl::RGBA8));
+            assert_eq!(levels, vec![&[255; 16][..]]);
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut data = ktx(false);
+        data[1] = b'P';
+        assert_eq!(parse_ktx(&data).err(), Some("Not a KTX 1.1 file"));
+        assert_eq!(parse_ktx(&ktx(false)[..HEADER_SIZE - 1]).err(), Some("Not a KTX 1.1 file"));
+    }
+
+    #[test]
+    fn rejects_unknown_endianness() {
+        let mut data = ktx(false);
+        set(&mut data, 12, 0x0102_0403);
+        assert_eq!(parse_ktx(&data).err(), Some("Invalid KTX endianness"));
+    }
+
+    #[test]
+    fn rejects_truncated_levels() {
+        let mut data = ktx(false);
+        assert_eq!(parse_ktx(&data[..data.len() - 1]).err(), Some("KTX file is truncated"));
+        assert_eq!(parse_ktx(&data[..HEADER_SIZE + 2]).err(), Some("KTX file is truncated"));
+        // A second level that isn't there.
+        set(&mut data, 56, 2);
+        assert_eq!(parse_ktx(&data).err(), Some("KTX file is truncated"));
+    }
+
+    #[test]
+    fn rejects_textures_that_are_not_2d() {
+        // Height, depth, array elements and faces.
+        for &(offset, value) in &[(40, 0), (44, 2), (48, 2), (52, 6)] {
+            let mut data = ktx(false);
+            set(&mut data, offset, value);
+            assert_eq!(parse_ktx(&data).err(), Some("Only 2D KTX textures are supported"), "field at {}", offset);
+        }
+    }
+}
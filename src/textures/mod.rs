@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use image::{ImageFormat, Rgba, RgbaImage};
+use opengl_graphics::Texture;
+use texture::TextureSettings;
+use crate::fs::{AssetSource, AssetError};
+
+mod ktx;
+mod array;
+mod atlas;
+
+pub use ktx::*;
+pub use array::*;
+pub use atlas::*;
+
+/// A texture drawables can share; it's freed once the last one is dropped.
+pub type TextureHandle = Arc<Texture>;
+pub type TextureArrayHandle = Arc<TextureArray>;
+
+/// An atlas's texture along with where each image is in it.
+#[derive(Clone)]
+pub struct Atlas {
+    pub texture: TextureHandle,
+    pub layout: Arc<AtlasLayout>,
+}
+
+/// Decodes a PNG or JPEG, going by the file's contents rather than its name.
+pub fn decode_image(path: &Path, data: &[u8]) -> Result<RgbaImage, AssetError> {
+    let format = image::guess_format(data)
+        .map_err(|e| AssetError::Decode(path.to_path_buf(), e.to_string()))?;
+    match format {
+        ImageFormat::PNG | ImageFormat::JPEG => image::load_from_memory_with_format(data, format)
+            .map(|image| image.to_rgba())
+            .map_err(|e| AssetError::Decode(path.to_path_buf(), e.to_string())),
+        other => Err(AssetError::Decode(path.to_path_buf(), format!("Unsupported image format {:?}", other))),
+    }
+}
+
+/// Loads textures by asset path, handing out the same texture to everyone
+/// who asks for the same path.
+pub struct TextureManager {
+    source: Box<dyn AssetSource>,
+    textures: HashMap<PathBuf, TextureHandle>,
+    arrays: HashMap<Vec<PathBuf>, TextureArrayHandle>,
+    atlases: HashMap<Vec<PathBuf>, Atlas>,
+    plain: Option<TextureHandle>,
+}
+
+impl TextureManager {
+    pub fn new(source: Box<dyn AssetSource>) -> Self {
+        Self {
+            source,
+            textures: HashMap::new(),
+            arrays: HashMap::new(),
+            atlases: HashMap::new(),
+            plain: None,
+        }
+    }
+
+    fn settings() -> TextureSettings {
+        TextureSettings::new().convert_gamma(true)
+    }
+
+    fn load_image(&self, path: &Path) -> Result<RgbaImage, AssetError> {
+        decode_image(path, &self.source.load(path)?)
+    }
+
+    /// A PNG, JPEG or KTX texture.
+    pub fn get<P: AsRef<Path>>(&mut self, path: P) -> Result<TextureHandle, AssetError> {
+        let path = path.as_ref();
+        if let Some(texture) = self.textures.get(path) {
+            return Ok(texture.clone());
+        }
+        let data = self.source.load(path)?;
+        let texture = if is_ktx(&data) {
+            load_ktx(path, &data)?
+        } else {
+            Texture::from_image(&decode_image(path, &data)?, &Self::settings())
+        };
+        let texture = Arc::new(texture);
+        self.textures.insert(path.to_path_buf(), texture.clone());
+        Ok(texture)
+    }
+
+    /// A single white texel, for drawing things untextured.
+    pub fn plain(&mut self) -> TextureHandle {
+        self.plain
+            .get_or_insert_with(|| Arc::new(Texture::from_image(
+                &RgbaImage::from_pixel(1, 1, Rgba([255; 4])),
+                &Self::settings(),
+            )))
+            .clone()
+    }
+
+    /// `paths` as the layers of one texture array, in order. They must all
+    /// be PNGs or JPEGs of the same size.
+    pub fn array<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<TextureArrayHandle, AssetError> {
+        let key = paths.iter().map(|path| path.as_ref().to_path_buf()).collect::<Vec<_>>();
+        if let Some(array) = self.arrays.get(&key) {
+            return Ok(array.clone());
+        }
+        let images = key.iter().map(|path| self.load_image(path)).collect::<Result<Vec<_>, _>>()?;
+        let first = key.first().cloned().unwrap_or_default();
+        let array = Arc::new(TextureArray::from_images(&images).map_err(|e| AssetError::Decode(first, e))?);
+        self.arrays.insert(key, array.clone());
+        Ok(array)
+    }
+
+    /// `paths` packed into one texture, each named by its path in the
+    /// layout. They must all be PNGs or JPEGs.
+    pub fn atlas<P: AsRef<Path>>(&mut self, paths: &[P]) -> Result<Atlas, AssetError> {
+        let key = paths.iter().map(|path| path.as_ref().to_path_buf()).collect::<Vec<_>>();
+        if let Some(atlas) = self.atlases.get(&key) {
+            return Ok(atlas.clone());
+        }
+        let images = key
+            .iter()
+            .map(|path| Ok((path.to_string_lossy().into_owned(), self.load_image(path)?)))
+            .collect::<Result<Vec<_>, AssetError>>()?;
+        let first = key.first().cloned().unwrap_or_default();
+        let (image, layout) = AtlasLayout::pack(images).map_err(|e| AssetError::Decode(first, e))?;
+        let atlas = Atlas {
+            texture: Arc::new(Texture::from_image(&image, &Self::settings())),
+            layout: Arc::new(layout),
+        };
+        self.atlases.insert(key, atlas.clone());
+        Ok(atlas)
+    }
+}
@@ -0,0 +1,362 @@
+use std::str::FromStr;
+use rand::Rng;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand_pcg::Pcg32;
+use crate::seed::{WorldSeed, SeedPurpose};
+use crate::seasons::Season;
+
+/// Seconds taken to blend from one kind of weather into the next.
+const TRANSITION: f64 = 20.0;
+/// Shortest and longest each kind of weather settles in for, in seconds.
+const SPELL: (f64, f64) = (60.0, 180.0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum WeatherKind {
+    Clear,
+    Overcast,
+    Rain,
+    Storm,
+    Snow,
+}
+
+impl WeatherKind {
+    const ALL: [WeatherKind; 5] = [
+        WeatherKind::Clear,
+        WeatherKind::Overcast,
+        WeatherKind::Rain,
+        WeatherKind::Storm,
+        WeatherKind::Snow,
+    ];
+
+    /// How likely each of `ALL` is to follow this in `season`; weather
+    /// mostly builds up and clears through overcast skies. It only snows in
+    /// winter, and rains instead the rest of the year.
+    fn next_weights(self, season: Season) -> [u32; 5] {
+        let mut weights = match self {
+            WeatherKind::Clear => [0, 4, 1, 0, 1],
+            WeatherKind::Overcast => [3, 0, 3, 1, 1],
+            WeatherKind::Rain => [1, 3, 0, 2, 0],
+            WeatherKind::Storm => [0, 2, 3, 0, 0],
+            WeatherKind::Snow => [1, 3, 0, 0, 0],
+        };
+        if season != Season::Winter {
+            weights[2] += weights[4];
+            weights[4] = 0;
+        }
+        weights
+    }
+
+    fn conditions(self) -> Conditions {
+        match self {
+            WeatherKind::Clear => Conditions::default(),
+            WeatherKind::Overcast => Conditions {
+                atmosphere: Atmosphere {
+                    light: [0.75, 0.75, 0.8],
+                    sky: [0.62, 0.68, 0.72, 1.0],
+                    fog_density: 0.01,
+                },
+                wind: [3.0, 0.0],
+                roughness: 1.3,
+                ..Conditions::default()
+            },
+            WeatherKind::Rain => Conditions {
+                atmosphere: Atmosphere {
+                    light: [0.55, 0.58, 0.65],
+                    sky: [0.45, 0.5, 0.55, 1.0],
+                    fog_density: 0.025,
+                },
+                wind: [4.0, 0.0],
+                rain: 800.0,
+                roughness: 1.6,
+                ..Conditions::default()
+            },
+            WeatherKind::Storm => Conditions {
+                atmosphere: Atmosphere {
+                    light: [0.38, 0.38, 0.45],
+                    sky: [0.28, 0.3, 0.35, 1.0],
+                    fog_density: 0.04,
+                },
+                wind: [9.0, 0.0],
+                rain: 2000.0,
+                roughness: 2.5,
+                ..Conditions::default()
+            },
+            WeatherKind::Snow => Conditions {
+                atmosphere: Atmosphere {
+                    light: [0.95, 0.97, 1.05],
+                    sky: [0.8, 0.83, 0.87, 1.0],
+                    fog_density: 0.03,
+                },
+                wind: [2.0, 0.0],
+                snow: 400.0,
+                roughness: 0.8,
+                ..Conditions::default()
+            },
+        }
+    }
+}
+
+impl FromStr for WeatherKind {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        Self::ALL
+            .iter()
+            .find(|kind| format!("{:?}", kind).eq_ignore_ascii_case(s))
+            .cloned()
+            .ok_or_else(|| format!("Unknown weather {:?}, expected one of {:?}", s, Self::ALL))
+    }
+}
+
+/// What the lit shaders need to know about the air.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Atmosphere {
+    /// Daylight relative to a clear day, multiplying each shader's own light
+    /// colour.
+    pub light: [f32; 3],
+    /// Cleared to behind everything, and what the fog fades to.
+    pub sky: [f32; 4],
+    pub fog_density: f32,
+}
+
+impl Default for Atmosphere {
+    fn default() -> Self {
+        Self {
+            light: [1.0; 3],
+            sky: [163.0 / 255.0, 250.0 / 255.0, 1., 1.],
+            fog_density: 0.002,
+        }
+    }
+}
+
+impl Atmosphere {
+    /// This atmosphere with its daylight and sky multiplied by `light` and
+    /// `sky`.
+    pub fn tinted(&self, light: [f32; 3], sky: [f32; 3]) -> Atmosphere {
+        Atmosphere {
+            light: [self.light[0] * light[0], self.light[1] * light[1], self.light[2] * light[2]],
+            sky: [self.sky[0] * sky[0], self.sky[1] * sky[1], self.sky[2] * sky[2], self.sky[3]],
+            fog_density: self.fog_density,
+        }
+    }
+
+    /// `colour`, lit by this atmosphere's daylight.
+    pub fn light_colour(&self, colour: [f32; 4]) -> [f32; 4] {
+        [colour[0] * self.light[0], colour[1] * self.light[1], colour[2] * self.light[2], colour[3]]
+    }
+
+    pub fn fog_colour(&self) -> [f32; 3] {
+        [self.sky[0], self.sky[1], self.sky[2]]
+    }
+}
+
+/// Everything the weather changes, at one moment.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Conditions {
+    pub atmosphere: Atmosphere,
+    /// World units a second the wind blows along x and z.
+    pub wind: [f32; 2],
+    /// Raindrops and snowflakes a second, see `ParticleSystem::rain`.
+    pub rain: f32,
+    pub snow: f32,
+    /// Multiplies the height of the waves.
+    pub roughness: f32,
+}
+
+impl Default for Conditions {
+    fn default() -> Self {
+        Self {
+            atmosphere: Atmosphere::default(),
+            wind: [1.0, 0.0],
+            rain: 0.0,
+            snow: 0.0,
+            roughness: 1.0,
+        }
+    }
+}
+
+pub(crate) fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+pub(crate) fn lerp_array<A: AsRef<[f32]> + AsMut<[f32]> + Copy>(a: A, b: A, t: f32) -> A {
+    let mut out = a;
+    for (out, (a, b)) in out.as_mut().iter_mut().zip(a.as_ref().iter().zip(b.as_ref())) {
+        *out = lerp(*a, *b, t);
+    }
+    out
+}
+
+impl Conditions {
+    fn lerp(&self, other: &Conditions, t: f32) -> Conditions {
+        Conditions {
+            atmosphere: Atmosphere {
+                light: lerp_array(self.atmosphere.light, other.atmosphere.light, t),
+                sky: lerp_array(self.atmosphere.sky, other.atmosphere.sky, t),
+                fog_density: lerp(self.atmosphere.fog_density, other.atmosphere.fog_density, t),
+            },
+            wind: lerp_array(self.wind, other.wind, t),
+            rain: lerp(self.rain, other.rain, t),
+            snow: lerp(self.snow, other.snow, t),
+            roughness: lerp(self.roughness, other.roughness, t),
+        }
+    }
+}
+
+/// Weather that wanders from one kind to another, blending smoothly between
+/// them. It only moves on with `step`, so the same seed and steps give the
+/// same weather.
+#[derive(Clone, Debug)]
+pub struct Weather {
+    rng: Pcg32,
+    kind: WeatherKind,
+    /// Where the wind blows from during the current spell, in radians.
+    heading: f32,
+    /// What's blowing in, and the heading its wind will come from.
+    next: Option<(WeatherKind, f32)>,
+    /// Seconds into the current spell, or the transition once `next` is set.
+    elapsed: f64,
+    spell: f64,
+}
+
+impl Weather {
+    /// Always starts out clear.
+    pub fn new(seed: WorldSeed) -> Self {
+        let mut rng = seed.rng(SeedPurpose::Weather);
+        let heading = rng.gen_range(0.0, std::f32::consts::PI * 2.0);
+        let spell = rng.gen_range(SPELL.0, SPELL.1);
+        Self {
+            rng,
+            kind: WeatherKind::Clear,
+            heading,
+            next: None,
+            elapsed: 0.0,
+            spell,
+        }
+    }
+
+    /// The weather now, or the weather blowing out during a transition.
+    pub fn kind(&self) -> WeatherKind {
+        self.kind
+    }
+
+    /// Blows in `kind` straight away, skipping the transition.
+    pub fn set_kind(&mut self, kind: WeatherKind) {
+        self.kind = kind;
+        self.next = None;
+        self.elapsed = 0.0;
+    }
+
+    /// Moves on by `dt` seconds, picking the next kind of weather for
+    /// `season` when the current one's spell is up. Snow outside winter
+    /// blows out straight away, and snow on its way turns to rain.
+    pub fn step(&mut self, dt: f64, season: Season) {
+        self.elapsed += dt;
+        let winter = season == Season::Winter;
+        if let Some((kind, _)) = &mut self.next {
+            if *kind == WeatherKind::Snow && !winter {
+                *kind = WeatherKind::Rain;
+            }
+        }
+        match self.next {
+            None if self.elapsed >= self.spell || (self.kind == WeatherKind::Snow && !winter) => {
+                let weights = self.kind.next_weights(season);
+                let index = WeightedIndex::new(&weights).expect("Every kind of weather has something to follow it");
+                let kind = WeatherKind::ALL[index.sample(&mut self.rng)];
+                // The wind swings round by up to a quarter turn.
+                let heading = self.heading + self.rng.gen_range(-1.0f32, 1.0) * std::f32::consts::FRAC_PI_2;
+                self.next = Some((kind, heading));
+                self.elapsed = 0.0;
+            },
+            Some((kind, heading)) if self.elapsed >= TRANSITION => {
+                self.kind = kind;
+                self.heading = heading;
+                self.next = None;
+                self.elapsed = 0.0;
+                self.spell = self.rng.gen_range(SPELL.0, SPELL.1);
+            },
+            _ => {},
+        }
+    }
+
+    /// `kind`'s conditions, with its wind turned to `heading`.
+    fn settled(kind: WeatherKind, heading: f32) -> Conditions {
+        let mut conditions = kind.conditions();
+        let (sin, cos) = heading.sin_cos();
+        let speed = conditions.wind[0];
+        conditions.wind = [cos * speed, sin * speed];
+        conditions
+    }
+
+    pub fn conditions(&self) -> Conditions {
+        let current = Self::settled(self.kind, self.heading);
+        match self.next {
+            Some((kind, heading)) => {
+                let t = (self.elapsed / TRANSITION).min(1.0) as f32;
+                // Eased, so nothing jumps at either end.
+                current.lerp(&Self::settled(kind, heading), t * t * (3.0 - 2.0 * t))
+            },
+            None => current,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every kind of weather `seed` gives over `steps` of a minute in
+    /// `season`.
+    fn kinds(seed: u64, season: Season, steps: usize) -> Vec<WeatherKind> {
+        let mut weather = Weather::new(WorldSeed(seed));
+        (0..steps)
+            .map(|_| {
+                weather.step(60.0, season);
+                weather.kind()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn same_seed_same_weather() {
+        assert_eq!(kinds(5, Season::Spring, 200), kinds(5, Season::Spring, 200));
+    }
+
+    #[test]
+    fn snow_only_falls_in_winter() {
+        for &season in &[Season::Spring, Season::Summer, Season::Autumn] {
+            assert!(!kinds(5, season, 500).contains(&WeatherKind::Snow));
+        }
+        assert!(kinds(5, Season::Winter, 500).contains(&WeatherKind::Snow));
+        // Snow that's falling or blowing in as winter ends stops.
+        for &(seed, settled) in &[(5, true), (5, false), (9, true), (9, false)] {
+            let mut weather = Weather::new(WorldSeed(seed));
+            // Into a spell of snow, or while it's blowing in.
+            let snowing = |weather: &Weather| match weather.next {
+                None => settled && weather.kind == WeatherKind::Snow,
+                Some((kind, _)) => !settled && kind == WeatherKind::Snow,
+            };
+            let mut steps = 0;
+            while !snowing(&weather) {
+                weather.step(1.0, Season::Winter);
+                steps += 1;
+                assert!(steps < 100_000, "seed {} never snowed", seed);
+            }
+            for _ in 0..TRANSITION as usize + 1 {
+                weather.step(1.0, Season::Spring);
+            }
+            for _ in 0..1000 {
+                assert_ne!(weather.kind(), WeatherKind::Snow);
+                assert_eq!(weather.conditions().snow, 0.0);
+                weather.step(1.0, Season::Spring);
+            }
+        }
+    }
+
+    #[test]
+    fn kinds_parse_from_their_names() {
+        assert_eq!(" storm".parse(), Ok(WeatherKind::Storm));
+        assert_eq!("Overcast".parse(), Ok(WeatherKind::Overcast));
+        assert!("hail".parse::<WeatherKind>().is_err());
+    }
+}